@@ -0,0 +1,6 @@
+fn main() -> std::io::Result<()> {
+    tonic_build::configure()
+        .build_server(true)
+        .build_client(true)
+        .compile(&["proto/local_ext.proto"], &["proto"])
+}
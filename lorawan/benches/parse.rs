@@ -0,0 +1,38 @@
+//! Frame parsing benchmarks.
+//!
+//! Compares parsing an uplink from a byte slice, which copies the frame once,
+//! with parsing from a shared `Bytes` buffer, which does not copy. To measure
+//! on a gateway, build the benchmark for the target with
+//! `cargo bench -p lorawan --bench parse --no-run` and run the resulting
+//! binary there with `--bench`.
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use lorawan::{Direction, PHYPayload};
+
+const UPLINK: &[u8] = &[
+    64, 200, 213, 3, 2, 128, 11, 124, 1, 38, 51, 2, 5, 95, 101, 161, 40, 44, 86, 116, 134, 134,
+    205, 127, 80, 215, 216, 107, 195, 105, 179, 202, 251, 251, 103, 113, 108, 15, 139, 26, 35, 190,
+    230, 163, 135, 83, 179,
+];
+
+const JOIN_REQUEST: &[u8] = &[
+    0, 141, 8, 0, 32, 176, 213, 179, 112, 127, 140, 3, 32, 176, 213, 179, 112, 135, 15, 125, 90,
+    77, 199,
+];
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, frame) in [("uplink", UPLINK), ("join_request", JOIN_REQUEST)] {
+        group.bench_function(format!("{name}/slice"), |b| {
+            b.iter(|| PHYPayload::read(Direction::Uplink, &mut black_box(frame)).unwrap())
+        });
+        let bytes = Bytes::from_static(frame);
+        group.bench_function(format!("{name}/bytes"), |b| {
+            b.iter(|| PHYPayload::read(Direction::Uplink, &mut black_box(bytes.clone())).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
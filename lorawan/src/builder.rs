@@ -0,0 +1,448 @@
+//! Builders for data, JoinRequest and JoinAccept frames.
+//!
+//! Builders take plain text payloads and check field limits on `build`.
+//! Given session keys, a data frame is encrypted and signed. Join frames are
+//! signed with the AppKey; a built JoinAccept is not encrypted yet, use
+//! [`PHYPayload::write_join_accept`] for that.
+use super::{
+    CFList, Direction, FCtrl, FCtrlDownlink, FCtrlUplink, FRMPayload, Fhdr, JoinAccept,
+    JoinRequest, LoraWanError, MACPayload, MType, MacCommand, PHYPayload, PHYPayloadFrame, Payload,
+    AES128, MHDR,
+};
+use alloc::vec;
+use bytes::Bytes;
+
+/// Maximum length of the FOpts field
+pub const MAX_FOPTS_LEN: usize = 15;
+/// Highest valid FPort, reserved for the LoRaWAN certification test protocol.
+/// Higher ports are RFU.
+pub const TEST_FPORT: u8 = 224;
+
+fn mhdr(mtype: MType) -> MHDR {
+    let mut mhdr = MHDR(0);
+    mhdr.set_mtype(mtype);
+    mhdr
+}
+
+#[derive(Debug, Clone)]
+pub struct DataBuilder {
+    direction: Direction,
+    dev_addr: u32,
+    fcnt: u32,
+    confirmed: bool,
+    adr: bool,
+    ack: bool,
+    adr_ack_req: bool,
+    fpending: bool,
+    fopts: Bytes,
+    fport: Option<u8>,
+    payload: Bytes,
+    session_keys: Option<(AES128, AES128)>,
+}
+
+impl DataBuilder {
+    fn new(direction: Direction, dev_addr: u32) -> Self {
+        Self {
+            direction,
+            dev_addr,
+            fcnt: 0,
+            confirmed: false,
+            adr: false,
+            ack: false,
+            adr_ack_req: false,
+            fpending: false,
+            fopts: Bytes::new(),
+            fport: None,
+            payload: Bytes::new(),
+            session_keys: None,
+        }
+    }
+
+    /// Sets the full 32 bit frame counter. Only the lower 16 bits are sent,
+    /// the upper bits are used for encryption and the MIC.
+    pub fn fcnt(mut self, fcnt: u32) -> Self {
+        self.fcnt = fcnt;
+        self
+    }
+
+    pub fn confirmed(mut self) -> Self {
+        self.confirmed = true;
+        self
+    }
+
+    pub fn adr(mut self, adr: bool) -> Self {
+        self.adr = adr;
+        self
+    }
+
+    pub fn ack(mut self) -> Self {
+        self.ack = true;
+        self
+    }
+
+    /// Sets ADRACKReq on an uplink. Ignored for downlinks.
+    pub fn adr_ack_req(mut self) -> Self {
+        self.adr_ack_req = true;
+        self
+    }
+
+    /// Sets FPending on a downlink. Ignored for uplinks.
+    pub fn fpending(mut self) -> Self {
+        self.fpending = true;
+        self
+    }
+
+    pub fn fopts(mut self, fopts: impl Into<Bytes>) -> Self {
+        self.fopts = fopts.into();
+        self
+    }
+
+    /// Sets FOpts to the given MAC commands
+    pub fn mac_commands(mut self, commands: &[MacCommand]) -> Result<Self, LoraWanError> {
+        let mut fopts = vec![];
+        for command in commands {
+            command.write(&mut fopts)?;
+        }
+        self.fopts = fopts.into();
+        Ok(self)
+    }
+
+    pub fn fport(mut self, fport: u8) -> Self {
+        self.fport = Some(fport);
+        self
+    }
+
+    /// Sets the plain text FRMPayload
+    pub fn payload(mut self, payload: impl Into<Bytes>) -> Self {
+        self.payload = payload.into();
+        self
+    }
+
+    /// Encrypts the FRMPayload and signs the frame on build. The FRMPayload
+    /// of FPort 0 is encrypted with the NwkSKey.
+    pub fn session_keys(mut self, nwk_skey: AES128, app_skey: AES128) -> Self {
+        self.session_keys = Some((nwk_skey, app_skey));
+        self
+    }
+
+    fn mtype(&self) -> MType {
+        match (self.direction, self.confirmed) {
+            (Direction::Uplink, false) => MType::UnconfirmedUp,
+            (Direction::Uplink, true) => MType::ConfirmedUp,
+            (Direction::Downlink, false) => MType::UnconfirmedDown,
+            (Direction::Downlink, true) => MType::ConfirmedDown,
+        }
+    }
+
+    pub fn build(self) -> Result<PHYPayload, LoraWanError> {
+        if self.fopts.len() > MAX_FOPTS_LEN {
+            return Err(LoraWanError::InvalidFOptsLength(self.fopts.len()));
+        }
+        match self.fport {
+            Some(0) if !self.fopts.is_empty() => return Err(LoraWanError::InvalidFPortForFopts),
+            Some(fport) if fport > TEST_FPORT => return Err(LoraWanError::InvalidFPort(fport)),
+            None if !self.payload.is_empty() => return Err(LoraWanError::MissingFPort),
+            _ => (),
+        }
+
+        let fopts_len = self.fopts.len() as u8;
+        let fctrl = match self.direction {
+            Direction::Uplink => {
+                let mut fctrl = FCtrlUplink(0);
+                fctrl.set_adr(self.adr);
+                fctrl.set_addr_ack_req(self.adr_ack_req);
+                fctrl.set_ack(self.ack);
+                fctrl.set_fopts_len(fopts_len);
+                FCtrl::Uplink(fctrl)
+            }
+            Direction::Downlink => {
+                let mut fctrl = FCtrlDownlink(0);
+                fctrl.set_adr(self.adr);
+                fctrl.set_ack(self.ack);
+                fctrl.set_class_b(self.fpending);
+                fctrl.set_fopts_len(fopts_len);
+                FCtrl::Downlink(fctrl)
+            }
+        };
+        let mtype = self.mtype();
+        let payload = Payload::from(self.payload);
+        let mut phy_payload = PHYPayload {
+            mhdr: mhdr(mtype),
+            payload: PHYPayloadFrame::MACPayload(MACPayload {
+                fhdr: Fhdr {
+                    dev_addr: self.dev_addr,
+                    fctrl,
+                    fcnt: self.fcnt as u16,
+                    fopts: self.fopts,
+                },
+                fport: self.fport,
+                payload: self.fport.map(|_| match mtype {
+                    MType::UnconfirmedUp => FRMPayload::UnconfirmedUp(payload),
+                    MType::ConfirmedUp => FRMPayload::ConfirmedUp(payload),
+                    MType::UnconfirmedDown => FRMPayload::UnconfirmedDown(payload),
+                    _ => FRMPayload::ConfirmedDown(payload),
+                }),
+            }),
+            mic: None,
+        };
+        if let Some((nwk_skey, app_skey)) = self.session_keys {
+            let fcnt_msb = (self.fcnt >> 16) as u16;
+            let key = if self.fport == Some(0) {
+                &nwk_skey
+            } else {
+                &app_skey
+            };
+            phy_payload.encrypt_frm_payload(key, fcnt_msb)?;
+            phy_payload.set_mic(&nwk_skey, fcnt_msb)?;
+        }
+        Ok(phy_payload)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JoinRequestBuilder {
+    join_request: JoinRequest,
+    app_key: Option<AES128>,
+}
+
+impl JoinRequestBuilder {
+    pub fn dev_nonce(mut self, dev_nonce: u16) -> Self {
+        self.join_request.dev_nonce = dev_nonce.to_le_bytes();
+        self
+    }
+
+    /// Signs the frame with the AppKey on build
+    pub fn app_key(mut self, app_key: AES128) -> Self {
+        self.app_key = Some(app_key);
+        self
+    }
+
+    pub fn build(self) -> Result<PHYPayload, LoraWanError> {
+        let mut phy_payload = PHYPayload {
+            mhdr: mhdr(MType::JoinRequest),
+            payload: PHYPayloadFrame::JoinRequest(self.join_request),
+            mic: None,
+        };
+        if let Some(app_key) = self.app_key {
+            phy_payload.set_mic(&app_key, 0)?;
+        }
+        Ok(phy_payload)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JoinAcceptBuilder {
+    join_accept: JoinAccept,
+    rx1_dr_offset: u8,
+    rx2_data_rate: u8,
+    app_key: Option<AES128>,
+}
+
+impl JoinAcceptBuilder {
+    pub fn app_nonce(mut self, app_nonce: [u8; 3]) -> Self {
+        self.join_accept.app_nonce = app_nonce;
+        self
+    }
+
+    pub fn net_id(mut self, net_id: [u8; 3]) -> Self {
+        self.join_accept.net_id = net_id;
+        self
+    }
+
+    pub fn rx1_dr_offset(mut self, rx1_dr_offset: u8) -> Self {
+        self.rx1_dr_offset = rx1_dr_offset;
+        self
+    }
+
+    pub fn rx2_data_rate(mut self, rx2_data_rate: u8) -> Self {
+        self.rx2_data_rate = rx2_data_rate;
+        self
+    }
+
+    /// Sets the delay of the first receive window in seconds
+    pub fn rx_delay(mut self, rx_delay: u8) -> Self {
+        self.join_accept.rx_delay = rx_delay;
+        self
+    }
+
+    pub fn cf_list(mut self, cf_list: CFList) -> Self {
+        self.join_accept.cf_list = Some(cf_list);
+        self
+    }
+
+    /// Signs the frame with the AppKey on build
+    pub fn app_key(mut self, app_key: AES128) -> Self {
+        self.app_key = Some(app_key);
+        self
+    }
+
+    pub fn build(mut self) -> Result<PHYPayload, LoraWanError> {
+        if self.rx1_dr_offset > 0x07 {
+            return Err(LoraWanError::InvalidJoinAcceptField(
+                "rx1_dr_offset",
+                self.rx1_dr_offset,
+            ));
+        }
+        if self.rx2_data_rate > 0x0f {
+            return Err(LoraWanError::InvalidJoinAcceptField(
+                "rx2_data_rate",
+                self.rx2_data_rate,
+            ));
+        }
+        if self.join_accept.rx_delay > 0x0f {
+            return Err(LoraWanError::InvalidJoinAcceptField(
+                "rx_delay",
+                self.join_accept.rx_delay,
+            ));
+        }
+        self.join_accept.dl_settings = self.rx1_dr_offset << 4 | self.rx2_data_rate;
+        let mut phy_payload = PHYPayload {
+            mhdr: mhdr(MType::JoinAccept),
+            payload: PHYPayloadFrame::JoinAccept(self.join_accept),
+            mic: None,
+        };
+        if let Some(app_key) = self.app_key {
+            phy_payload.set_mic(&app_key, 0)?;
+        }
+        Ok(phy_payload)
+    }
+}
+
+impl PHYPayload {
+    /// Starts an unconfirmed data uplink
+    pub fn data_uplink(dev_addr: u32) -> DataBuilder {
+        DataBuilder::new(Direction::Uplink, dev_addr)
+    }
+
+    /// Starts an unconfirmed data downlink
+    pub fn data_downlink(dev_addr: u32) -> DataBuilder {
+        DataBuilder::new(Direction::Downlink, dev_addr)
+    }
+
+    pub fn join_request(app_eui: u64, dev_eui: u64) -> JoinRequestBuilder {
+        JoinRequestBuilder {
+            join_request: JoinRequest {
+                app_eui,
+                dev_eui,
+                dev_nonce: [0; 2],
+            },
+            app_key: None,
+        }
+    }
+
+    pub fn join_accept(dev_addr: u32) -> JoinAcceptBuilder {
+        JoinAcceptBuilder {
+            join_accept: JoinAccept {
+                app_nonce: [0; 3],
+                net_id: [0; 3],
+                dev_addr,
+                dl_settings: 0,
+                rx_delay: 1,
+                cf_list: None,
+            },
+            rx1_dr_offset: 0,
+            rx2_data_rate: 0,
+            app_key: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_data_uplink() {
+        // Rebuilds the uplink of the lib MIC and FRMPayload tests
+        let nwk_skey = AES128([
+            0x44, 0x02, 0x42, 0x41, 0xed, 0x4c, 0xe9, 0xa6, 0x8c, 0x6a, 0x8b, 0xc0, 0x55, 0x23,
+            0x3f, 0xd3,
+        ]);
+        let app_skey = AES128([
+            0xec, 0x92, 0x58, 0x02, 0xae, 0x43, 0x0c, 0xa7, 0x7f, 0xd3, 0xdd, 0x73, 0xcb, 0x2c,
+            0xc5, 0x88,
+        ]);
+        let payload = PHYPayload::data_uplink(0x49be7df1)
+            .fcnt(2)
+            .fport(1)
+            .payload(&b"test"[..])
+            .session_keys(nwk_skey, app_skey)
+            .build()
+            .unwrap();
+        assert_eq!(
+            vec![
+                0x40, 0xf1, 0x7d, 0xbe, 0x49, 0x00, 0x02, 0x00, 0x01, 0x95, 0x43, 0x78, 0x76, 0x2b,
+                0x11, 0xff, 0x0d,
+            ],
+            Vec::<u8>::try_from(payload).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_data_limits() {
+        assert!(matches!(
+            PHYPayload::data_uplink(1).fopts(vec![0x02; 16]).build(),
+            Err(LoraWanError::InvalidFOptsLength(16))
+        ));
+        assert!(matches!(
+            PHYPayload::data_uplink(1).fport(225).build(),
+            Err(LoraWanError::InvalidFPort(225))
+        ));
+        assert!(matches!(
+            PHYPayload::data_uplink(1).payload(&b"data"[..]).build(),
+            Err(LoraWanError::MissingFPort)
+        ));
+        assert!(matches!(
+            PHYPayload::data_downlink(1)
+                .fport(0)
+                .fopts(vec![0x06])
+                .build(),
+            Err(LoraWanError::InvalidFPortForFopts)
+        ));
+
+        let payload = PHYPayload::data_downlink(1)
+            .confirmed()
+            .ack()
+            .mac_commands(&[MacCommand::DevStatusReq])
+            .unwrap()
+            .session_keys(AES128([1; 16]), AES128([2; 16]))
+            .build()
+            .unwrap();
+        assert_eq!(MType::ConfirmedDown, payload.mtype());
+        let data = Vec::<u8>::try_from(payload.clone()).unwrap();
+        assert_eq!(
+            payload,
+            PHYPayload::read(Direction::Downlink, &mut &data[..]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_join() {
+        let app_key = AES128([1; 16]);
+        let join_request = PHYPayload::join_request(0x70B3D5B02000088D, 0x70B3D5B020038C7F)
+            .dev_nonce(0x1234)
+            .app_key(app_key)
+            .build()
+            .unwrap();
+        join_request.verify_mic(&app_key, 0).expect("valid mic");
+
+        let join_accept = PHYPayload::join_accept(0x48000001)
+            .rx1_dr_offset(1)
+            .rx2_data_rate(3)
+            .cf_list(CFList::ChannelMask {
+                masks: [0xff00, 0, 0, 0, 0],
+                rfu: [0; 5],
+            })
+            .app_key(app_key)
+            .build()
+            .unwrap();
+        let data = join_accept.write_join_accept(&app_key).unwrap();
+        let decrypted = PHYPayload::read_join_accept(&app_key, &mut &data[..]).unwrap();
+        decrypted.verify_mic(&app_key, 0).expect("valid mic");
+        assert_eq!(join_accept, decrypted);
+        assert!(matches!(
+            PHYPayload::join_accept(1).rx_delay(16).build(),
+            Err(LoraWanError::InvalidJoinAcceptField("rx_delay", 16))
+        ));
+    }
+}
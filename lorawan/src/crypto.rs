@@ -0,0 +1,226 @@
+//! LoRaWAN cryptographic helpers.
+//!
+//! The message integrity code (MIC) of a frame is the first four bytes of an
+//! AES-CMAC over the frame. Data frames prefix the frame with a B0 block that
+//! binds the MIC to the direction, device address and frame counter.
+//!
+//! FRMPayload and FOpts are encrypted by XOR-ing them with a key stream of
+//! AES encrypted A blocks, so encryption and decryption are the same
+//! operation. A JoinAccept is encrypted with an AES decrypt operation so end
+//! devices only need AES encrypt to decrypt it.
+use super::{Direction, LoraWanError, MType};
+use aes::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use cmac::{Cmac, Mac};
+
+/// A 128 bit AES key like an AppKey, NwkSKey or AppSKey
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AES128(pub [u8; 16]);
+
+impl From<[u8; 16]> for AES128 {
+    fn from(v: [u8; 16]) -> Self {
+        Self(v)
+    }
+}
+
+// Keys are deliberately not printed
+impl core::fmt::Debug for AES128 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("AES128(..)")
+    }
+}
+
+pub type MIC = [u8; 4];
+
+fn cmac(key: &AES128, blocks: &[&[u8]]) -> [u8; 16] {
+    let mut mac = <Cmac<Aes128> as Mac>::new_from_slice(&key.0).expect("valid key length");
+    for block in blocks {
+        mac.update(block);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+fn truncate(cmac: [u8; 16]) -> MIC {
+    let mut mic = [0u8; 4];
+    mic.copy_from_slice(&cmac[..4]);
+    mic
+}
+
+/// Computes the MIC of a data frame using the NwkSKey. The message is the
+/// frame from the MHDR up to and excluding the MIC. The frame counter is the
+/// full 32 bit counter, of which only the lower 16 bits are sent in the frame.
+pub fn data_mic(key: &AES128, direction: Direction, dev_addr: u32, fcnt: u32, msg: &[u8]) -> MIC {
+    let mut b0 = [0u8; 16];
+    b0[0] = 0x49;
+    b0[5] = match direction {
+        Direction::Uplink => 0,
+        Direction::Downlink => 1,
+    };
+    b0[6..10].copy_from_slice(&dev_addr.to_le_bytes());
+    b0[10..14].copy_from_slice(&fcnt.to_le_bytes());
+    // The message length always fits since frames are at most 255 bytes
+    b0[15] = msg.len() as u8;
+    truncate(cmac(key, &[&b0, msg]))
+}
+
+/// Computes the MIC of a JoinRequest or a (decrypted) JoinAccept using the
+/// AppKey. The message is the frame from the MHDR up to and excluding the MIC.
+pub fn join_mic(key: &AES128, msg: &[u8]) -> MIC {
+    truncate(cmac(key, &[msg]))
+}
+
+/// Builds an A block for the FRMPayload or FOpts key stream
+fn a_block(direction: Direction, dev_addr: u32, fcnt: u32) -> [u8; 16] {
+    let mut a = [0u8; 16];
+    a[0] = 0x01;
+    a[5] = match direction {
+        Direction::Uplink => 0,
+        Direction::Downlink => 1,
+    };
+    a[6..10].copy_from_slice(&dev_addr.to_le_bytes());
+    a[10..14].copy_from_slice(&fcnt.to_le_bytes());
+    a
+}
+
+/// XORs data with the key stream of consecutive A blocks, numbering blocks
+/// from 1
+fn apply_key_stream(key: &AES128, mut a: [u8; 16], data: &mut [u8]) {
+    let cipher = Aes128::new(GenericArray::from_slice(&key.0));
+    for (i, chunk) in data.chunks_mut(16).enumerate() {
+        a[15] = (i + 1) as u8;
+        let mut s = GenericArray::from(a);
+        cipher.encrypt_block(&mut s);
+        chunk.iter_mut().zip(s.iter()).for_each(|(b, s)| *b ^= s);
+    }
+}
+
+/// Encrypts or decrypts an FRMPayload in place. The key is the AppSKey, or
+/// the NwkSKey for FPort 0. The frame counter is the full 32 bit counter.
+pub fn encrypt_frm_payload(
+    key: &AES128,
+    direction: Direction,
+    dev_addr: u32,
+    fcnt: u32,
+    data: &mut [u8],
+) {
+    apply_key_stream(key, a_block(direction, dev_addr, fcnt), data)
+}
+
+/// Decrypts an FRMPayload in place. See [`encrypt_frm_payload`].
+pub fn decrypt_frm_payload(
+    key: &AES128,
+    direction: Direction,
+    dev_addr: u32,
+    fcnt: u32,
+    data: &mut [u8],
+) {
+    encrypt_frm_payload(key, direction, dev_addr, fcnt, data)
+}
+
+/// Encrypts or decrypts LoRaWAN 1.1 FOpts in place using the NwkSEncKey.
+/// Downlinks with an FPort above 0 use the AFCntDown counter, which is marked
+/// in the A block as described in the LoRaWAN 1.1 errata.
+pub fn encrypt_fopts(
+    key: &AES128,
+    direction: Direction,
+    a_fcnt_down: bool,
+    dev_addr: u32,
+    fcnt: u32,
+    data: &mut [u8],
+) {
+    let mut a = a_block(direction, dev_addr, fcnt);
+    a[4] = if a_fcnt_down { 0x02 } else { 0x01 };
+    // FOpts are at most 15 bytes and only use the first block
+    apply_key_stream(key, a, data)
+}
+
+/// Decrypts LoRaWAN 1.1 FOpts in place. See [`encrypt_fopts`].
+pub fn decrypt_fopts(
+    key: &AES128,
+    direction: Direction,
+    a_fcnt_down: bool,
+    dev_addr: u32,
+    fcnt: u32,
+    data: &mut [u8],
+) {
+    encrypt_fopts(key, direction, a_fcnt_down, dev_addr, fcnt, data)
+}
+
+fn check_join_accept_len(data: &[u8]) -> Result<(), LoraWanError> {
+    if data.is_empty() || !data.chunks_exact(16).remainder().is_empty() {
+        return Err(LoraWanError::InvalidPacketSize(
+            MType::JoinAccept,
+            data.len(),
+        ));
+    }
+    Ok(())
+}
+
+/// Encrypts a JoinAccept in place with the AppKey. The data is the frame after
+/// the MHDR, including the MIC.
+pub fn encrypt_join_accept(key: &AES128, data: &mut [u8]) -> Result<(), LoraWanError> {
+    check_join_accept_len(data)?;
+    let cipher = Aes128::new(GenericArray::from_slice(&key.0));
+    data.chunks_mut(16)
+        .for_each(|block| cipher.decrypt_block(GenericArray::from_mut_slice(block)));
+    Ok(())
+}
+
+/// Decrypts a JoinAccept in place with the AppKey. The data is the frame after
+/// the MHDR, including the MIC.
+pub fn decrypt_join_accept(key: &AES128, data: &mut [u8]) -> Result<(), LoraWanError> {
+    check_join_accept_len(data)?;
+    let cipher = Aes128::new(GenericArray::from_slice(&key.0));
+    data.chunks_mut(16)
+        .for_each(|block| cipher.encrypt_block(GenericArray::from_mut_slice(block)));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // RFC 4493 section 4 test vectors
+    const RFC4493_KEY: AES128 = AES128([
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f,
+        0x3c,
+    ]);
+
+    #[test]
+    fn test_cmac() {
+        assert_eq!(
+            [
+                0xbb, 0x1d, 0x69, 0x29, 0xe9, 0x59, 0x37, 0x28, 0x7f, 0xa3, 0x7d, 0x12, 0x9b, 0x75,
+                0x67, 0x46
+            ],
+            cmac(&RFC4493_KEY, &[])
+        );
+        let msg = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+            0x17, 0x2a,
+        ];
+        assert_eq!(
+            [
+                0x07, 0x0a, 0x16, 0xb4, 0x6b, 0x4d, 0x41, 0x44, 0xf7, 0x9b, 0xdd, 0x9d, 0xd0, 0x4a,
+                0x28, 0x7c
+            ],
+            cmac(&RFC4493_KEY, &[&msg[..8], &msg[8..]])
+        );
+    }
+
+    #[test]
+    fn test_fopts() {
+        let key = AES128([7; 16]);
+        let fopts = [0x02, 0x03, 0x05];
+        let mut data = fopts;
+        encrypt_fopts(&key, Direction::Downlink, false, 0x01020304, 7, &mut data);
+        assert_ne!(fopts, data);
+        let mut afcnt = fopts;
+        encrypt_fopts(&key, Direction::Downlink, true, 0x01020304, 7, &mut afcnt);
+        assert_ne!(data, afcnt);
+        decrypt_fopts(&key, Direction::Downlink, false, 0x01020304, 7, &mut data);
+        assert_eq!(fopts, data);
+    }
+}
@@ -0,0 +1,242 @@
+//! DevAddr and NetID as defined in the LoRaWAN Backend Interfaces
+//! specification.
+//!
+//! A DevAddr starts with a type prefix of `N` one bits followed by a zero bit,
+//! the NwkID of the network that issued the address, and the NwkAddr of the
+//! device within that network. The NwkID is the least significant bits of the
+//! NetID of the network, and the type prefix matches the NetID type.
+#[cfg(feature = "serde")]
+use super::serde_fmt;
+use super::LoraWanError;
+use core::{fmt, ops::RangeInclusive, str::FromStr};
+
+/// Number of NwkID bits in a DevAddr (and NetID) of each NetID type.
+const NWK_ID_BITS: [u32; 8] = [6, 6, 9, 11, 12, 13, 15, 17];
+/// Largest valid NetID, a 3 bit type followed by a 21 bit ID.
+const NET_ID_MAX: u32 = 0xFF_FFFF;
+
+fn mask(bits: u32) -> u32 {
+    (1 << bits) - 1
+}
+
+fn nwk_addr_bits(net_type: u8) -> u32 {
+    32 - (net_type as u32 + 1) - NWK_ID_BITS[net_type as usize]
+}
+
+/// A device address
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct DevAddr(#[cfg_attr(feature = "serde", serde(with = "serde_fmt::dev_addr"))] pub u32);
+
+impl DevAddr {
+    /// Returns the address type, the NetID type of the network that issued
+    /// the address. Addresses starting with 8 one bits have no valid type.
+    pub fn addr_type(&self) -> Option<u8> {
+        let net_type = self.0.leading_ones();
+        (net_type < 8).then_some(net_type as u8)
+    }
+
+    /// Returns the NwkID of the network that issued the address.
+    pub fn nwk_id(&self) -> Option<u32> {
+        self.addr_type().map(|net_type| {
+            (self.0 >> nwk_addr_bits(net_type)) & mask(NWK_ID_BITS[net_type as usize])
+        })
+    }
+
+    /// Returns the address of the device within its network.
+    pub fn nwk_addr(&self) -> Option<u32> {
+        self.addr_type()
+            .map(|net_type| self.0 & mask(nwk_addr_bits(net_type)))
+    }
+
+    /// Returns the NetID of the network that issued the address. For NetID
+    /// types 3 to 7 only the NwkID bits of the NetID are carried in the
+    /// address, and the remaining ID bits are returned as zero. Use
+    /// [`NetID::contains`] to check an address against a known NetID.
+    pub fn net_id(&self) -> Option<NetID> {
+        let net_type = self.addr_type()?;
+        let nwk_id = self.nwk_id()?;
+        Some(NetID(((net_type as u32) << 21) | nwk_id))
+    }
+}
+
+impl From<u32> for DevAddr {
+    fn from(v: u32) -> Self {
+        Self(v)
+    }
+}
+
+impl From<DevAddr> for u32 {
+    fn from(v: DevAddr) -> Self {
+        v.0
+    }
+}
+
+impl fmt::Display for DevAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08X}", self.0)
+    }
+}
+
+impl fmt::Debug for DevAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DevAddr")
+            .field(&format_args!("{:08X}", self.0))
+            .finish()
+    }
+}
+
+impl FromStr for DevAddr {
+    type Err = LoraWanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u32::from_str_radix(s, 16)
+            .map(Self)
+            .map_err(LoraWanError::ParseInt)
+    }
+}
+
+/// A LoRaWAN network identifier
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct NetID(#[cfg_attr(feature = "serde", serde(with = "serde_fmt::net_id"))] u32);
+
+impl NetID {
+    /// Constructs a NetID from its 24 bit value.
+    pub fn new(v: u32) -> Result<Self, LoraWanError> {
+        if v > NET_ID_MAX {
+            return Err(LoraWanError::InvalidNetID(v));
+        }
+        Ok(Self(v))
+    }
+
+    /// Returns the NetID type, the 3 most significant bits.
+    pub fn net_type(&self) -> u8 {
+        (self.0 >> 21) as u8
+    }
+
+    /// Returns the 21 bit ID of the NetID.
+    pub fn id(&self) -> u32 {
+        self.0 & mask(21)
+    }
+
+    /// Returns the NwkID, the least significant bits of the ID that are
+    /// carried in every DevAddr issued by the network.
+    pub fn nwk_id(&self) -> u32 {
+        self.0 & mask(NWK_ID_BITS[self.net_type() as usize])
+    }
+
+    /// Returns the range of device addresses the network can issue.
+    pub fn dev_addr_range(&self) -> RangeInclusive<DevAddr> {
+        let net_type = self.net_type();
+        let addr_bits = nwk_addr_bits(net_type);
+        let prefix = !(u32::MAX >> net_type);
+        let first = prefix | (self.nwk_id() << addr_bits);
+        DevAddr(first)..=DevAddr(first | mask(addr_bits))
+    }
+
+    /// Returns whether the given device address was issued by this network.
+    pub fn contains(&self, dev_addr: DevAddr) -> bool {
+        dev_addr.addr_type() == Some(self.net_type()) && dev_addr.nwk_id() == Some(self.nwk_id())
+    }
+}
+
+impl TryFrom<u32> for NetID {
+    type Error = LoraWanError;
+
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        Self::new(v)
+    }
+}
+
+impl From<NetID> for u32 {
+    fn from(v: NetID) -> Self {
+        v.0
+    }
+}
+
+impl fmt::Display for NetID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:06X}", self.0)
+    }
+}
+
+impl fmt::Debug for NetID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("NetID")
+            .field(&format_args!("{:06X}", self.0))
+            .finish()
+    }
+}
+
+impl FromStr for NetID {
+    type Err = LoraWanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u32::from_str_radix(s, 16)
+            .map_err(LoraWanError::ParseInt)
+            .and_then(Self::new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dev_addr() {
+        // Helium NetID 00003C
+        let dev_addr = DevAddr(0x7800_0012);
+        assert_eq!(Some(0), dev_addr.addr_type());
+        assert_eq!(Some(0x3C), dev_addr.nwk_id());
+        assert_eq!(Some(0x12), dev_addr.nwk_addr());
+        assert_eq!(Some(NetID(0x00_003C)), dev_addr.net_id());
+
+        // Helium NetID 60002D
+        let dev_addr = DevAddr(0xE05A_0001);
+        assert_eq!(Some(3), dev_addr.addr_type());
+        assert_eq!(Some(0x2D), dev_addr.nwk_id());
+        assert_eq!(Some(1), dev_addr.nwk_addr());
+        assert_eq!(Some(NetID(0x60_002D)), dev_addr.net_id());
+
+        assert_eq!(Some(7), DevAddr(0xFE00_0000).addr_type());
+        assert_eq!(None, DevAddr(0xFF00_0000).addr_type());
+        assert_eq!(None, DevAddr(0xFF00_0000).net_id());
+    }
+
+    #[test]
+    fn test_net_id() {
+        let net_id: NetID = "C00053".parse().expect("net id");
+        assert_eq!(6, net_id.net_type());
+        assert_eq!(0x53, net_id.nwk_id());
+        assert_eq!(
+            DevAddr(0xFC01_4C00)..=DevAddr(0xFC01_4FFF),
+            net_id.dev_addr_range()
+        );
+        assert!(net_id.contains(DevAddr(0xFC01_4C12)));
+        assert!(!net_id.contains(DevAddr(0xFC01_5012)));
+
+        // NetIDs of types 3 and up only share their NwkID bits with addresses
+        let net_id = NetID::new(0x60_402D).expect("net id");
+        assert!(net_id.contains(DevAddr(0xE05A_0001)));
+
+        let net_id = NetID::new(0x00_003C).expect("net id");
+        assert_eq!(
+            DevAddr(0x7800_0000)..=DevAddr(0x79FF_FFFF),
+            net_id.dev_addr_range()
+        );
+        assert!(!net_id.contains(DevAddr(0xE05A_0001)));
+
+        assert!(NetID::new(0x100_0000).is_err());
+        assert!("xyz".parse::<NetID>().is_err());
+        assert_eq!("00003C", NetID(0x3C).to_string());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{error::Error, fmt, io};
+use core::fmt;
 
 #[derive(Debug)]
 pub enum LoraWanError {
@@ -6,7 +6,17 @@ pub enum LoraWanError {
     InvalidPacketVersion(u8),
     InvalidFPortForFopts,
     InvalidPacketSize(super::MType, usize),
-    Io(io::Error),
+    InvalidMic,
+    InvalidMacCommandSize(u8, usize),
+    InvalidRejoinType(u8),
+    InvalidFOptsLength(usize),
+    InvalidFPort(u8),
+    MissingFPort,
+    InvalidJoinAcceptField(&'static str, u8),
+    InvalidNetID(u32),
+    ParseInt(core::num::ParseIntError),
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
 }
 
 impl fmt::Display for LoraWanError {
@@ -18,15 +28,31 @@ impl fmt::Display for LoraWanError {
             LoraWanError::InvalidPacketSize(mtype, s) => {
                 write!(f, "Invalid packet size {s} for type {mtype:?}")
             }
+            LoraWanError::InvalidMic => write!(f, "Invalid MIC"),
+            LoraWanError::InvalidMacCommandSize(cid, s) => {
+                write!(f, "Invalid MAC command size {s} for CID {cid:#02x}")
+            }
+            LoraWanError::InvalidRejoinType(v) => write!(f, "Invalid rejoin type: {v}"),
+            LoraWanError::InvalidFOptsLength(s) => write!(f, "Invalid fopts length: {s}"),
+            LoraWanError::InvalidFPort(v) => write!(f, "Invalid fport: {v}"),
+            LoraWanError::MissingFPort => write!(f, "Invalid: frm payload without fport"),
+            LoraWanError::InvalidJoinAcceptField(field, v) => {
+                write!(f, "Invalid join accept {field}: {v}")
+            }
+            LoraWanError::InvalidNetID(v) => write!(f, "Invalid net id: {v:#x}"),
+            LoraWanError::ParseInt(err) => err.fmt(f),
+            #[cfg(feature = "std")]
             LoraWanError::Io(err) => err.fmt(f),
         }
     }
 }
 
-impl Error for LoraWanError {}
+#[cfg(feature = "std")]
+impl std::error::Error for LoraWanError {}
 
-impl From<io::Error> for LoraWanError {
-    fn from(err: io::Error) -> Self {
+#[cfg(feature = "std")]
+impl From<std::io::Error> for LoraWanError {
+    fn from(err: std::io::Error) -> Self {
         LoraWanError::Io(err)
     }
 }
@@ -1,10 +1,24 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
 use bitfield::bitfield;
 use bytes::{Buf, BufMut, Bytes};
-use std::{convert::From, fmt, mem::size_of, result};
+use core::{convert::From, fmt, mem::size_of, result};
 
+pub mod builder;
+pub mod crypto;
+pub mod dev_addr;
 pub mod error;
+pub mod mac_command;
+#[cfg(feature = "serde")]
+mod serde_fmt;
+pub use builder::{DataBuilder, JoinAcceptBuilder, JoinRequestBuilder};
 pub use bytes;
+pub use crypto::{AES128, MIC};
+pub use dev_addr::{DevAddr, NetID};
 pub use error::LoraWanError;
+pub use mac_command::MacCommand;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Direction {
@@ -13,6 +27,7 @@ pub enum Direction {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MType {
     JoinRequest,
     JoinAccept,
@@ -20,6 +35,7 @@ pub enum MType {
     UnconfirmedDown,
     ConfirmedUp,
     ConfirmedDown,
+    RejoinRequest,
     Proprietary,
     Invalid(u8),
 }
@@ -33,6 +49,7 @@ impl From<u8> for MType {
             0b011 => MType::UnconfirmedDown,
             0b100 => MType::ConfirmedUp,
             0b101 => MType::ConfirmedDown,
+            0b110 => MType::RejoinRequest,
             0b111 => MType::Proprietary,
             _ => MType::Invalid(v),
         }
@@ -48,6 +65,7 @@ impl From<MType> for u8 {
             MType::UnconfirmedDown => 0b011,
             MType::ConfirmedUp => 0b100,
             MType::ConfirmedDown => 0b101,
+            MType::RejoinRequest => 0b110,
             MType::Proprietary => 0b111,
             MType::Invalid(v) => v,
         }
@@ -56,17 +74,22 @@ impl From<MType> for u8 {
 
 bitfield! {
     #[derive(Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(
+        feature = "serde",
+        derive(serde::Serialize, serde::Deserialize),
+        serde(into = "serde_fmt::MHDRFields", from = "serde_fmt::MHDRFields")
+    )]
     pub struct MHDR(u8);
     impl Debug;
     pub from into MType, mtype, set_mtype: 7, 5;
-    rfu, _: 4, 2;
+    rfu, set_rfu: 4, 2;
     pub major, set_major: 1, 0;
 }
 
 const MHDR_SIZE: usize = size_of::<u8>();
 
 impl MHDR {
-    pub fn read(reader: &mut dyn Buf) -> Result<Self, LoraWanError> {
+    pub fn read<B: Buf>(reader: &mut B) -> Result<Self, LoraWanError> {
         if reader.remaining() < MHDR_SIZE {
             return Err(LoraWanError::InvalidPacketSize(
                 MType::Invalid(0),
@@ -83,15 +106,19 @@ impl MHDR {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PHYPayload {
     pub mhdr: MHDR,
     pub payload: PHYPayloadFrame,
+    #[cfg_attr(feature = "serde", serde(with = "serde_fmt::option_hex"))]
     pub mic: Option<[u8; 4]>,
 }
 
 const JOIN_REQUEST_LEN: usize = 23;
 const JOIN_ACCEPT_LEN: usize = 17;
 const JOIN_ACCEPT_WITH_CFLIST_LEN: usize = 33;
+const REJOIN_REQUEST_02_LEN: usize = 19;
+const REJOIN_REQUEST_1_LEN: usize = 24;
 const DATA_MIN_LEN: usize = 12;
 
 impl PHYPayload {
@@ -107,7 +134,9 @@ impl PHYPayload {
         }
     }
 
-    pub fn read(direction: Direction, reader: &mut dyn Buf) -> Result<Self, LoraWanError> {
+    /// Reads a frame from the given buffer. Reading from a `Bytes` buffer does
+    /// not copy the frame, the payload fields share the buffer instead.
+    pub fn read<B: Buf>(direction: Direction, reader: &mut B) -> Result<Self, LoraWanError> {
         let mhdr = MHDR::read(reader)?;
         let version = mhdr.major();
         if version != 0 {
@@ -122,6 +151,9 @@ impl PHYPayload {
             MType::JoinAccept => {
                 phy_len != JOIN_ACCEPT_LEN && phy_len != JOIN_ACCEPT_WITH_CFLIST_LEN
             }
+            MType::RejoinRequest => {
+                phy_len != REJOIN_REQUEST_02_LEN && phy_len != REJOIN_REQUEST_1_LEN
+            }
             MType::UnconfirmedUp
             | MType::UnconfirmedDown
             | MType::ConfirmedUp
@@ -169,6 +201,133 @@ impl PHYPayload {
     pub fn mtype(&self) -> MType {
         self.mhdr.mtype()
     }
+
+    /// Computes the MIC of this payload. Data frames use the NwkSKey as the
+    /// key and the given upper 16 bits of the frame counter. Join frames use
+    /// the AppKey, and a JoinAccept is expected to be decrypted. A
+    /// RejoinRequest uses the SNwkSIntKey for type 0 and 2 and the JSIntKey
+    /// for type 1.
+    pub fn compute_mic(&self, key: &AES128, fcnt_msb: u16) -> Result<MIC, LoraWanError> {
+        let mut msg = Vec::with_capacity(DATA_MIN_LEN);
+        self.mhdr.write(&mut msg)?;
+        self.payload.write(&mut msg)?;
+        match &self.payload {
+            PHYPayloadFrame::MACPayload(mac_payload) => Ok(crypto::data_mic(
+                key,
+                mac_payload.fhdr.fctrl.direction(),
+                mac_payload.fhdr.dev_addr,
+                mac_payload.fhdr.fcnt(fcnt_msb),
+                &msg,
+            )),
+            PHYPayloadFrame::JoinRequest(_)
+            | PHYPayloadFrame::JoinAccept(_)
+            | PHYPayloadFrame::RejoinRequest(_) => Ok(crypto::join_mic(key, &msg)),
+            PHYPayloadFrame::Proprietary(_) => {
+                Err(LoraWanError::InvalidPacketType(self.mtype().into()))
+            }
+        }
+    }
+
+    /// Checks the MIC of this payload against the one computed with the given
+    /// key. See [`PHYPayload::compute_mic`] for the key and counter to use.
+    pub fn verify_mic(&self, key: &AES128, fcnt_msb: u16) -> Result<(), LoraWanError> {
+        let mic = self.compute_mic(key, fcnt_msb)?;
+        match self.mic {
+            Some(expected) if expected == mic => Ok(()),
+            _ => Err(LoraWanError::InvalidMic),
+        }
+    }
+
+    /// Computes and sets the MIC of this payload
+    pub fn set_mic(&mut self, key: &AES128, fcnt_msb: u16) -> Result<(), LoraWanError> {
+        self.mic = Some(self.compute_mic(key, fcnt_msb)?);
+        Ok(())
+    }
+
+    fn mac_payload_mut(&mut self) -> Result<&mut MACPayload, LoraWanError> {
+        match &mut self.payload {
+            PHYPayloadFrame::MACPayload(mac_payload) => Ok(mac_payload),
+            _ => Err(LoraWanError::InvalidPacketType(self.mhdr.mtype().into())),
+        }
+    }
+
+    /// Encrypts the FRMPayload of a data frame in place. The key is the
+    /// AppSKey, or the NwkSKey for FPort 0.
+    pub fn encrypt_frm_payload(&mut self, key: &AES128, fcnt_msb: u16) -> Result<(), LoraWanError> {
+        let mac_payload = self.mac_payload_mut()?;
+        let direction = mac_payload.fhdr.fctrl.direction();
+        let dev_addr = mac_payload.fhdr.dev_addr;
+        let fcnt = mac_payload.fhdr.fcnt(fcnt_msb);
+        if let Some(payload) = &mut mac_payload.payload {
+            let payload = payload.payload_mut();
+            let mut data = payload.0.to_vec();
+            crypto::encrypt_frm_payload(key, direction, dev_addr, fcnt, &mut data);
+            payload.0 = data.into();
+        }
+        Ok(())
+    }
+
+    /// Decrypts the FRMPayload of a data frame in place. See
+    /// [`PHYPayload::encrypt_frm_payload`] for the key to use.
+    pub fn decrypt_frm_payload(&mut self, key: &AES128, fcnt_msb: u16) -> Result<(), LoraWanError> {
+        self.encrypt_frm_payload(key, fcnt_msb)
+    }
+
+    /// Encrypts the FOpts of a LoRaWAN 1.1 data frame in place with the
+    /// NwkSEncKey. Set `a_fcnt_down` for downlinks counted by AFCntDown.
+    pub fn encrypt_fopts(
+        &mut self,
+        key: &AES128,
+        fcnt_msb: u16,
+        a_fcnt_down: bool,
+    ) -> Result<(), LoraWanError> {
+        let fhdr = &mut self.mac_payload_mut()?.fhdr;
+        let mut data = fhdr.fopts.to_vec();
+        crypto::encrypt_fopts(
+            key,
+            fhdr.fctrl.direction(),
+            a_fcnt_down,
+            fhdr.dev_addr,
+            fhdr.fcnt(fcnt_msb),
+            &mut data,
+        );
+        fhdr.fopts = data.into();
+        Ok(())
+    }
+
+    /// Decrypts the FOpts of a LoRaWAN 1.1 data frame in place. See
+    /// [`PHYPayload::encrypt_fopts`].
+    pub fn decrypt_fopts(
+        &mut self,
+        key: &AES128,
+        fcnt_msb: u16,
+        a_fcnt_down: bool,
+    ) -> Result<(), LoraWanError> {
+        self.encrypt_fopts(key, fcnt_msb, a_fcnt_down)
+    }
+
+    /// Reads an encrypted JoinAccept frame and decrypts it with the AppKey
+    pub fn read_join_accept<B: Buf>(key: &AES128, reader: &mut B) -> Result<Self, LoraWanError> {
+        let mut data = reader.copy_to_bytes(reader.remaining()).to_vec();
+        let mhdr = MHDR::read(&mut &data[..])?;
+        if mhdr.mtype() != MType::JoinAccept {
+            return Err(LoraWanError::InvalidPacketType(mhdr.mtype().into()));
+        }
+        crypto::decrypt_join_accept(key, &mut data[MHDR_SIZE..])?;
+        Self::read(Direction::Downlink, &mut &data[..])
+    }
+
+    /// Writes this JoinAccept encrypted with the AppKey. The MIC is expected
+    /// to be set.
+    pub fn write_join_accept(&self, key: &AES128) -> Result<Vec<u8>, LoraWanError> {
+        if self.mtype() != MType::JoinAccept {
+            return Err(LoraWanError::InvalidPacketType(self.mtype().into()));
+        }
+        let mut data = vec![];
+        self.write(&mut data)?;
+        crypto::encrypt_join_accept(key, &mut data[MHDR_SIZE..])?;
+        Ok(data)
+    }
 }
 
 impl TryFrom<PHYPayload> for Vec<u8> {
@@ -181,22 +340,25 @@ impl TryFrom<PHYPayload> for Vec<u8> {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PHYPayloadFrame {
     MACPayload(MACPayload),
     JoinRequest(JoinRequest),
     JoinAccept(JoinAccept),
-    Proprietary(Bytes),
+    RejoinRequest(RejoinRequest),
+    Proprietary(#[cfg_attr(feature = "serde", serde(with = "serde_fmt::base64"))] Bytes),
 }
 
 impl PHYPayloadFrame {
-    pub fn read(
+    pub fn read<B: Buf>(
         direction: Direction,
         packet_type: MType,
-        reader: &mut dyn Buf,
+        reader: &mut B,
     ) -> Result<Self, LoraWanError> {
         let res = match packet_type {
             MType::JoinRequest => Self::JoinRequest(JoinRequest::read(reader)?),
             MType::JoinAccept => Self::JoinAccept(JoinAccept::read(reader)?),
+            MType::RejoinRequest => Self::RejoinRequest(RejoinRequest::read(reader)?),
             MType::Proprietary => {
                 let proprietary_payload = reader.copy_to_bytes(reader.remaining());
                 Self::Proprietary(proprietary_payload)
@@ -211,6 +373,7 @@ impl PHYPayloadFrame {
             Self::MACPayload(mp) => mp.write(output),
             Self::JoinRequest(jr) => jr.write(output),
             Self::JoinAccept(ja) => ja.write(output),
+            Self::RejoinRequest(rr) => rr.write(output),
             Self::Proprietary(v) => {
                 output.put_slice(v);
                 Ok(v.len())
@@ -227,10 +390,13 @@ impl PHYPayloadFrame {
 }
 
 #[derive(PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Fhdr {
+    #[cfg_attr(feature = "serde", serde(with = "serde_fmt::dev_addr"))]
     pub dev_addr: u32,
     pub fctrl: FCtrl,
     pub fcnt: u16,
+    #[cfg_attr(feature = "serde", serde(with = "serde_fmt::base64"))]
     pub fopts: Bytes,
 }
 
@@ -248,10 +414,15 @@ impl fmt::Debug for Fhdr {
 }
 
 impl Fhdr {
-    pub fn read(
+    /// Returns the full 32 bit frame counter given its upper 16 bits
+    pub fn fcnt(&self, fcnt_msb: u16) -> u32 {
+        (fcnt_msb as u32) << 16 | self.fcnt as u32
+    }
+
+    pub fn read<B: Buf>(
         direction: Direction,
         payload_type: MType,
-        reader: &mut dyn Buf,
+        reader: &mut B,
     ) -> Result<Self, LoraWanError> {
         // Check minimum length requirements
         if reader.remaining() < FHDR_MIN_SIZE {
@@ -295,6 +466,11 @@ impl Fhdr {
 
 bitfield! {
     #[derive(Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(
+        feature = "serde",
+        derive(serde::Serialize, serde::Deserialize),
+        serde(into = "serde_fmt::FCtrlUplinkFields", from = "serde_fmt::FCtrlUplinkFields")
+    )]
     pub struct FCtrlUplink(u8);
     impl Debug;
     pub adr, set_adr: 7;
@@ -307,7 +483,7 @@ bitfield! {
 const FCTRL_UPLINK_SIZE: usize = size_of::<u8>();
 
 impl FCtrlUplink {
-    pub fn read(payload_type: MType, reader: &mut dyn Buf) -> Result<Self, LoraWanError> {
+    pub fn read<B: Buf>(payload_type: MType, reader: &mut B) -> Result<Self, LoraWanError> {
         if reader.remaining() < FCTRL_UPLINK_SIZE {
             return Err(LoraWanError::InvalidPacketSize(
                 payload_type,
@@ -325,10 +501,15 @@ impl FCtrlUplink {
 
 bitfield! {
     #[derive(Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(
+        feature = "serde",
+        derive(serde::Serialize, serde::Deserialize),
+        serde(into = "serde_fmt::FCtrlDownlinkFields", from = "serde_fmt::FCtrlDownlinkFields")
+    )]
     pub struct FCtrlDownlink(u8);
     impl Debug;
     pub adr, set_adr: 7;
-    rfu, _: 6;
+    rfu, set_rfu: 6;
     pub ack, set_ack: 5;
     pub class_b, set_class_b: 4;
     pub fopts_len, set_fopts_len:3, 0;
@@ -337,7 +518,7 @@ bitfield! {
 const FCTRL_DOWNLINK_SIZE: usize = size_of::<u8>();
 
 impl FCtrlDownlink {
-    pub fn read(payload_type: MType, reader: &mut dyn Buf) -> Result<Self, LoraWanError> {
+    pub fn read<B: Buf>(payload_type: MType, reader: &mut B) -> Result<Self, LoraWanError> {
         if reader.remaining() < FCTRL_DOWNLINK_SIZE {
             return Err(LoraWanError::InvalidPacketSize(
                 payload_type,
@@ -354,6 +535,7 @@ impl FCtrlDownlink {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FCtrl {
     Uplink(FCtrlUplink),
     Downlink(FCtrlDownlink),
@@ -362,6 +544,13 @@ pub enum FCtrl {
 pub const FCTRL_SIZE: usize = size_of::<u8>();
 
 impl FCtrl {
+    pub fn direction(&self) -> Direction {
+        match self {
+            FCtrl::Uplink(_) => Direction::Uplink,
+            FCtrl::Downlink(_) => Direction::Downlink,
+        }
+    }
+
     pub fn fopts_len(&self) -> usize {
         match self {
             FCtrl::Uplink(fctrl) => fctrl.fopts_len().into(),
@@ -369,10 +558,10 @@ impl FCtrl {
         }
     }
 
-    pub fn read(
+    pub fn read<B: Buf>(
         direction: Direction,
         payload_type: MType,
-        reader: &mut dyn Buf,
+        reader: &mut B,
     ) -> Result<Self, LoraWanError> {
         let res = match direction {
             Direction::Uplink => Self::Uplink(FCtrlUplink::read(payload_type, reader)?),
@@ -390,6 +579,7 @@ impl FCtrl {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MACPayload {
     pub fhdr: Fhdr,
     pub fport: Option<u8>,
@@ -397,19 +587,17 @@ pub struct MACPayload {
 }
 
 impl MACPayload {
-    pub fn read(
+    pub fn read<B: Buf>(
         payload_type: MType,
         direction: Direction,
-        reader: &mut dyn Buf,
+        reader: &mut B,
     ) -> Result<Self, LoraWanError> {
         let fhdr = Fhdr::read(direction, payload_type, reader)?;
-        let data = reader.copy_to_bytes(reader.remaining());
-        let (fport, payload) = match data.split_first() {
-            Some((port, mut payload)) => (
-                Some(*port),
-                Some(FRMPayload::read(payload_type, &mut payload)?),
-            ),
-            _ => (None, None),
+        let (fport, payload) = if reader.has_remaining() {
+            let fport = reader.get_u8();
+            (Some(fport), Some(FRMPayload::read(payload_type, reader)?))
+        } else {
+            (None, None)
         };
         if fport == Some(0) && fhdr.fctrl.fopts_len() > 0 {
             return Err(LoraWanError::InvalidFPortForFopts);
@@ -439,12 +627,25 @@ impl MACPayload {
         Ok(written)
     }
 
-    pub fn dev_addr(&self) -> u32 {
-        self.fhdr.dev_addr
+    pub fn dev_addr(&self) -> DevAddr {
+        DevAddr(self.fhdr.dev_addr)
+    }
+
+    /// Returns the MAC commands in FOpts, or in the FRMPayload for FPort 0.
+    /// An FPort 0 payload is expected to be decrypted.
+    pub fn mac_commands(&self) -> Result<Vec<MacCommand>, LoraWanError> {
+        let direction = self.fhdr.fctrl.direction();
+        match (self.fport, &self.payload) {
+            (Some(0), Some(payload)) => {
+                MacCommand::read_all(direction, &mut payload.payload().as_ref())
+            }
+            _ => MacCommand::read_all(direction, &mut &self.fhdr.fopts[..]),
+        }
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FRMPayload {
     UnconfirmedUp(Payload),
     UnconfirmedDown(Payload),
@@ -453,7 +654,7 @@ pub enum FRMPayload {
 }
 
 impl FRMPayload {
-    pub fn read(payload_type: MType, reader: &mut dyn Buf) -> Result<Self, LoraWanError> {
+    pub fn read<B: Buf>(payload_type: MType, reader: &mut B) -> Result<Self, LoraWanError> {
         let res = match payload_type {
             MType::UnconfirmedUp => Self::UnconfirmedUp(Payload::read(reader)?),
             MType::UnconfirmedDown => Self::UnconfirmedDown(Payload::read(reader)?),
@@ -465,6 +666,24 @@ impl FRMPayload {
         Ok(res)
     }
 
+    pub fn payload(&self) -> &Payload {
+        match self {
+            Self::UnconfirmedUp(p)
+            | Self::UnconfirmedDown(p)
+            | Self::ConfirmedUp(p)
+            | Self::ConfirmedDown(p) => p,
+        }
+    }
+
+    pub fn payload_mut(&mut self) -> &mut Payload {
+        match self {
+            Self::UnconfirmedUp(p)
+            | Self::UnconfirmedDown(p)
+            | Self::ConfirmedUp(p)
+            | Self::ConfirmedDown(p) => p,
+        }
+    }
+
     pub fn write(&self, output: &mut dyn BufMut) -> Result<usize, LoraWanError> {
         match self {
             Self::UnconfirmedUp(p) => p.write(output),
@@ -476,10 +695,24 @@ impl FRMPayload {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct Payload(Bytes);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Payload(#[cfg_attr(feature = "serde", serde(with = "serde_fmt::base64"))] Bytes);
+
+impl AsRef<[u8]> for Payload {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Bytes> for Payload {
+    fn from(v: Bytes) -> Self {
+        Self(v)
+    }
+}
 
 impl Payload {
-    pub fn read(reader: &mut dyn Buf) -> Result<Self, LoraWanError> {
+    pub fn read<B: Buf>(reader: &mut B) -> Result<Self, LoraWanError> {
         let data = reader.copy_to_bytes(reader.remaining());
         Ok(Self(data))
     }
@@ -491,9 +724,13 @@ impl Payload {
 }
 
 #[derive(PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct JoinRequest {
+    #[cfg_attr(feature = "serde", serde(with = "serde_fmt::eui"))]
     pub app_eui: u64,
+    #[cfg_attr(feature = "serde", serde(with = "serde_fmt::eui"))]
     pub dev_eui: u64,
+    #[cfg_attr(feature = "serde", serde(with = "serde_fmt::hex"))]
     pub dev_nonce: [u8; 2],
 }
 
@@ -510,7 +747,7 @@ impl fmt::Debug for JoinRequest {
 }
 
 impl JoinRequest {
-    pub fn read(reader: &mut dyn Buf) -> Result<Self, LoraWanError> {
+    pub fn read<B: Buf>(reader: &mut B) -> Result<Self, LoraWanError> {
         if reader.remaining() < JOIN_REQUEST_SIZE {
             return Err(LoraWanError::InvalidPacketSize(
                 MType::JoinRequest,
@@ -536,19 +773,23 @@ impl JoinRequest {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct JoinAccept {
+    #[cfg_attr(feature = "serde", serde(with = "serde_fmt::hex"))]
     pub app_nonce: [u8; 3],
+    #[cfg_attr(feature = "serde", serde(with = "serde_fmt::hex"))]
     pub net_id: [u8; 3],
+    #[cfg_attr(feature = "serde", serde(with = "serde_fmt::dev_addr"))]
     pub dev_addr: u32,
     pub dl_settings: u8,
     pub rx_delay: u8,
-    // cf_list: Option<CFList>,
+    pub cf_list: Option<CFList>,
 }
 
 const JOIN_ACCEPT_SIZE: usize = 6 * size_of::<u8>() + size_of::<u32>() + 2 * size_of::<u8>();
 
 impl JoinAccept {
-    pub fn read(reader: &mut dyn Buf) -> Result<Self, LoraWanError> {
+    pub fn read<B: Buf>(reader: &mut B) -> Result<Self, LoraWanError> {
         if reader.remaining() < JOIN_ACCEPT_SIZE {
             return Err(LoraWanError::InvalidPacketSize(
                 MType::JoinAccept,
@@ -566,6 +807,11 @@ impl JoinAccept {
             dev_addr: reader.get_u32_le(),
             dl_settings: reader.get_u8(),
             rx_delay: reader.get_u8(),
+            cf_list: match reader.remaining() {
+                0 => None,
+                CF_LIST_SIZE => Some(CFList::read(reader)?),
+                other => return Err(LoraWanError::InvalidPacketSize(MType::JoinAccept, other)),
+            },
         };
         Ok(res)
     }
@@ -576,7 +822,231 @@ impl JoinAccept {
         output.put_u32_le(self.dev_addr);
         output.put_u8(self.dl_settings);
         output.put_u8(self.rx_delay);
-        Ok(JOIN_ACCEPT_SIZE)
+        let cf_list_size = match &self.cf_list {
+            Some(cf_list) => cf_list.write(output)?,
+            None => 0,
+        };
+        Ok(JOIN_ACCEPT_SIZE + cf_list_size)
+    }
+}
+
+/// The optional channel list of a JoinAccept
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CFList {
+    /// Type 0: frequencies in Hz of up to five additional channels, with 0
+    /// for an unused entry
+    Frequencies([u32; 5]),
+    /// Type 1: channel masks for regions with fixed channel plans like US915
+    /// and AU915. Each mask enables 16 channels, starting with channel 0. The
+    /// RFU bytes are kept so a list, or a still encrypted JoinAccept that
+    /// looks like one, round trips unchanged.
+    ChannelMask {
+        masks: [u16; 5],
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                default,
+                with = "serde_fmt::hex",
+                skip_serializing_if = "serde_fmt::is_zeroed"
+            )
+        )]
+        rfu: [u8; 5],
+    },
+    /// A list of an unknown type, like in a JoinAccept that is still encrypted
+    Unknown {
+        cf_list_type: u8,
+        #[cfg_attr(feature = "serde", serde(with = "serde_fmt::hex"))]
+        data: [u8; 15],
+    },
+}
+
+const CF_LIST_SIZE: usize = 16;
+const CF_LIST_FREQUENCIES: u8 = 0;
+const CF_LIST_CHANNEL_MASK: u8 = 1;
+
+impl CFList {
+    pub fn cf_list_type(&self) -> u8 {
+        match self {
+            Self::Frequencies(_) => CF_LIST_FREQUENCIES,
+            Self::ChannelMask { .. } => CF_LIST_CHANNEL_MASK,
+            Self::Unknown { cf_list_type, .. } => *cf_list_type,
+        }
+    }
+
+    pub fn read<B: Buf>(reader: &mut B) -> Result<Self, LoraWanError> {
+        if reader.remaining() < CF_LIST_SIZE {
+            return Err(LoraWanError::InvalidPacketSize(
+                MType::JoinAccept,
+                reader.remaining(),
+            ));
+        }
+        let mut data = [0u8; CF_LIST_SIZE - 1];
+        reader.copy_to_slice(&mut data);
+        let cf_list_type = reader.get_u8();
+        let mut data_reader = &data[..];
+        let res = match cf_list_type {
+            CF_LIST_FREQUENCIES => {
+                Self::Frequencies([(); 5].map(|_| mac_command::get_frequency(&mut data_reader)))
+            }
+            CF_LIST_CHANNEL_MASK => {
+                let masks = [(); 5].map(|_| data_reader.get_u16_le());
+                let mut rfu = [0u8; 5];
+                data_reader.copy_to_slice(&mut rfu);
+                Self::ChannelMask { masks, rfu }
+            }
+            _ => Self::Unknown { cf_list_type, data },
+        };
+        Ok(res)
+    }
+
+    pub fn write(&self, output: &mut dyn BufMut) -> Result<usize, LoraWanError> {
+        match self {
+            Self::Frequencies(frequencies) => frequencies
+                .iter()
+                .for_each(|frequency| mac_command::put_frequency(output, *frequency)),
+            Self::ChannelMask { masks, rfu } => {
+                masks.iter().for_each(|mask| output.put_u16_le(*mask));
+                output.put_slice(rfu);
+            }
+            Self::Unknown { data, .. } => output.put_slice(data),
+        }
+        output.put_u8(self.cf_list_type());
+        Ok(CF_LIST_SIZE)
+    }
+}
+
+/// A LoRaWAN 1.1 RejoinRequest
+#[derive(PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RejoinRequest {
+    /// Rejoin type 0 or 2
+    Type02 {
+        rejoin_type: u8,
+        #[cfg_attr(feature = "serde", serde(with = "serde_fmt::hex"))]
+        net_id: [u8; 3],
+        #[cfg_attr(feature = "serde", serde(with = "serde_fmt::eui"))]
+        dev_eui: u64,
+        rj_count0: u16,
+    },
+    Type1 {
+        #[cfg_attr(feature = "serde", serde(with = "serde_fmt::eui"))]
+        join_eui: u64,
+        #[cfg_attr(feature = "serde", serde(with = "serde_fmt::eui"))]
+        dev_eui: u64,
+        rj_count1: u16,
+    },
+}
+
+const REJOIN_REQUEST_02_SIZE: usize =
+    size_of::<u8>() + size_of::<[u8; 3]>() + size_of::<u64>() + size_of::<u16>();
+const REJOIN_REQUEST_1_SIZE: usize = size_of::<u8>() + 2 * size_of::<u64>() + size_of::<u16>();
+
+impl fmt::Debug for RejoinRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> result::Result<(), fmt::Error> {
+        match self {
+            Self::Type02 {
+                rejoin_type,
+                net_id,
+                dev_eui,
+                rj_count0,
+            } => f
+                .debug_struct("RejoinRequest")
+                .field("rejoin_type", rejoin_type)
+                .field("net_id", net_id)
+                .field("dev_eui", &format_args!("{:#08x}", dev_eui))
+                .field("rj_count0", rj_count0)
+                .finish(),
+            Self::Type1 {
+                join_eui,
+                dev_eui,
+                rj_count1,
+            } => f
+                .debug_struct("RejoinRequest")
+                .field("rejoin_type", &1)
+                .field("join_eui", &format_args!("{:#08x}", join_eui))
+                .field("dev_eui", &format_args!("{:#08x}", dev_eui))
+                .field("rj_count1", rj_count1)
+                .finish(),
+        }
+    }
+}
+
+impl RejoinRequest {
+    pub fn rejoin_type(&self) -> u8 {
+        match self {
+            Self::Type02 { rejoin_type, .. } => *rejoin_type,
+            Self::Type1 { .. } => 1,
+        }
+    }
+
+    pub fn dev_eui(&self) -> u64 {
+        match self {
+            Self::Type02 { dev_eui, .. } | Self::Type1 { dev_eui, .. } => *dev_eui,
+        }
+    }
+
+    pub fn read<B: Buf>(reader: &mut B) -> Result<Self, LoraWanError> {
+        if !reader.has_remaining() {
+            return Err(LoraWanError::InvalidPacketSize(MType::RejoinRequest, 0));
+        }
+        let rejoin_type = reader.chunk()[0];
+        let size = match rejoin_type {
+            0 | 2 => REJOIN_REQUEST_02_SIZE,
+            1 => REJOIN_REQUEST_1_SIZE,
+            other => return Err(LoraWanError::InvalidRejoinType(other)),
+        };
+        if reader.remaining() != size {
+            return Err(LoraWanError::InvalidPacketSize(
+                MType::RejoinRequest,
+                reader.remaining(),
+            ));
+        }
+        reader.advance(size_of::<u8>());
+        let res = if rejoin_type == 1 {
+            Self::Type1 {
+                join_eui: reader.get_u64_le(),
+                dev_eui: reader.get_u64_le(),
+                rj_count1: reader.get_u16_le(),
+            }
+        } else {
+            let mut net_id = [0u8; 3];
+            reader.copy_to_slice(&mut net_id);
+            Self::Type02 {
+                rejoin_type,
+                net_id,
+                dev_eui: reader.get_u64_le(),
+                rj_count0: reader.get_u16_le(),
+            }
+        };
+        Ok(res)
+    }
+
+    pub fn write(&self, output: &mut dyn BufMut) -> Result<usize, LoraWanError> {
+        output.put_u8(self.rejoin_type());
+        match self {
+            Self::Type02 {
+                net_id,
+                dev_eui,
+                rj_count0,
+                ..
+            } => {
+                output.put_slice(net_id);
+                output.put_u64_le(*dev_eui);
+                output.put_u16_le(*rj_count0);
+                Ok(REJOIN_REQUEST_02_SIZE)
+            }
+            Self::Type1 {
+                join_eui,
+                dev_eui,
+                rj_count1,
+            } => {
+                output.put_u64_le(*join_eui);
+                output.put_u64_le(*dev_eui);
+                output.put_u16_le(*rj_count1);
+                Ok(REJOIN_REQUEST_1_SIZE)
+            }
+        }
     }
 }
 
@@ -597,6 +1067,100 @@ mod test {
         assert_eq!(data_a, data_b);
     }
 
+    #[test]
+    fn test_read_zero_copy() {
+        let data = Bytes::from_static(&[
+            0x40, 0xf1, 0x7d, 0xbe, 0x49, 0x00, 0x02, 0x00, 0x01, 0x95, 0x43, 0x78, 0x76, 0x2b,
+            0x11, 0xff, 0x0d,
+        ]);
+        let payload = PHYPayload::read(Direction::Uplink, &mut data.clone()).unwrap();
+        let PHYPayloadFrame::MACPayload(mac_payload) = payload.payload else {
+            panic!("expected mac payload");
+        };
+        let frm_payload = mac_payload.payload.unwrap();
+        assert_eq!(
+            data[9..13].as_ptr(),
+            frm_payload.payload().as_ref().as_ptr()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let (_, join_request) = &mk_test_packets()[2];
+        for (direction, data) in [
+            (Direction::Uplink, join_request.to_vec()),
+            (
+                Direction::Uplink,
+                vec![
+                    0x40, 0xf1, 0x7d, 0xbe, 0x49, 0x00, 0x02, 0x00, 0x01, 0x95, 0x43, 0x78, 0x76,
+                    0x2b, 0x11, 0xff, 0x0d,
+                ],
+            ),
+            (
+                Direction::Downlink,
+                Vec::<u8>::try_from(
+                    PHYPayload::data_downlink(0x48000001)
+                        .ack()
+                        .mac_commands(&[MacCommand::DevStatusReq])
+                        .unwrap()
+                        .fport(2)
+                        .payload(&b"down"[..])
+                        .session_keys(AES128([1; 16]), AES128([2; 16]))
+                        .build()
+                        .unwrap(),
+                )
+                .unwrap(),
+            ),
+        ] {
+            let payload = PHYPayload::read(direction, &mut &data[..]).unwrap();
+            let json = serde_json::to_value(&payload).unwrap();
+            let decoded: PHYPayload = serde_json::from_value(json).unwrap();
+            assert_eq!(payload, decoded);
+            assert_eq!(data, Vec::<u8>::try_from(decoded).unwrap());
+        }
+
+        // RFU bits are kept
+        let mut rfu_data = join_request.to_vec();
+        rfu_data[0] |= 0b0001_1100;
+        let mut downlink = Vec::<u8>::try_from(
+            PHYPayload::data_downlink(0x48000001)
+                .ack()
+                .fport(2)
+                .payload(&b"down"[..])
+                .session_keys(AES128([1; 16]), AES128([2; 16]))
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+        downlink[5] |= 0b0100_0000;
+        for (direction, data) in [
+            (Direction::Uplink, rfu_data),
+            (Direction::Downlink, downlink),
+        ] {
+            let payload = PHYPayload::read(direction, &mut &data[..]).unwrap();
+            let json = serde_json::to_value(&payload).unwrap();
+            let decoded: PHYPayload = serde_json::from_value(json).unwrap();
+            assert_eq!(data, Vec::<u8>::try_from(decoded).unwrap());
+        }
+
+        let payload = PHYPayload::read(Direction::Uplink, &mut &join_request[..]).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "mhdr": { "mtype": "JoinRequest", "major": 0 },
+                "payload": {
+                    "JoinRequest": {
+                        "app_eui": "70B3D5B02000088D",
+                        "dev_eui": "70B3D5B020038C7F",
+                        "dev_nonce": "870F",
+                    }
+                },
+                "mic": "7D5A4DC7",
+            }),
+            serde_json::to_value(&payload).unwrap()
+        );
+    }
+
     #[test]
     fn test_packets() {
         for (routing, data) in mk_test_packets() {
@@ -605,6 +1169,106 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_rejoin_request() {
+        let type0: &[u8] = &[
+            0xc0, 0x00, 0x13, 0x00, 0x00, 0x7f, 0x8c, 0x03, 0x20, 0xb0, 0xd5, 0xb3, 0x70, 0x05,
+            0x00, 0x01, 0x02, 0x03, 0x04,
+        ];
+        let type1: &[u8] = &[
+            0xc0, 0x01, 0x8d, 0x08, 0x00, 0x20, 0xb0, 0xd5, 0xb3, 0x70, 0x7f, 0x8c, 0x03, 0x20,
+            0xb0, 0xd5, 0xb3, 0x70, 0x02, 0x00, 0x01, 0x02, 0x03, 0x04,
+        ];
+        for data in [type0, type1] {
+            let payload = PHYPayload::read(Direction::Uplink, &mut &data[..]).unwrap();
+            assert_eq!(MType::RejoinRequest, payload.mtype());
+            let PHYPayloadFrame::RejoinRequest(rejoin) = &payload.payload else {
+                panic!("expected rejoin request");
+            };
+            assert_eq!(0x70B3D5B020038C7F, rejoin.dev_eui());
+            assert_eq!(data, Vec::<u8>::try_from(payload).unwrap());
+        }
+        // Type 1 header with a type 0 length
+        let mut invalid = type0.to_vec();
+        invalid[1] = 1;
+        assert!(matches!(
+            PHYPayload::read(Direction::Uplink, &mut &invalid[..]),
+            Err(LoraWanError::InvalidPacketSize(MType::RejoinRequest, 14))
+        ));
+        invalid[1] = 3;
+        assert!(matches!(
+            PHYPayload::read(Direction::Uplink, &mut &invalid[..]),
+            Err(LoraWanError::InvalidRejoinType(3))
+        ));
+    }
+
+    #[test]
+    fn test_cf_list() {
+        let mut mhdr = MHDR(0);
+        mhdr.set_mtype(MType::JoinAccept);
+        for cf_list in [
+            CFList::Frequencies([867_100_000, 867_300_000, 867_500_000, 867_700_000, 0]),
+            CFList::ChannelMask {
+                masks: [0, 0xff00, 0, 0, 0x0002],
+                rfu: [0; 5],
+            },
+            // Non-zero RFU bytes, like in a still encrypted JoinAccept
+            CFList::ChannelMask {
+                masks: [0xffff, 0, 0, 0, 0],
+                rfu: [0x12, 0, 0x34, 0, 0x56],
+            },
+            CFList::Unknown {
+                cf_list_type: 0x7f,
+                data: [0x55; 15],
+            },
+        ] {
+            let payload = PHYPayload {
+                mhdr,
+                payload: PHYPayloadFrame::JoinAccept(JoinAccept {
+                    app_nonce: [1, 2, 3],
+                    net_id: [0x24, 0, 0],
+                    dev_addr: 0x48000001,
+                    dl_settings: 0,
+                    rx_delay: 1,
+                    cf_list: Some(cf_list),
+                }),
+                mic: Some([1, 2, 3, 4]),
+            };
+            let data = Vec::<u8>::try_from(payload.clone()).unwrap();
+            assert_eq!(JOIN_ACCEPT_WITH_CFLIST_LEN, data.len());
+            assert_eq!(
+                payload,
+                PHYPayload::read(Direction::Downlink, &mut &data[..]).unwrap()
+            );
+        }
+
+        let data: &[u8] = &[
+            0x18, 0x4f, 0x84, 0xe8, 0x56, 0x84, 0xb8, 0x5e, 0x84, 0x88, 0x66, 0x84, 0x58, 0x6e,
+            0x84, 0x00,
+        ];
+        assert_eq!(
+            CFList::Frequencies([
+                867_100_000,
+                867_300_000,
+                867_500_000,
+                867_700_000,
+                867_900_000
+            ]),
+            CFList::read(&mut &data[..]).unwrap()
+        );
+
+        // A channel mask list writes back its RFU bytes unchanged
+        let data: &[u8] = &[
+            0xff, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0x12, 0, 0x34, 0, 0x56, 0x01,
+        ];
+        let mut written = vec![];
+        CFList::read(&mut &data[..])
+            .unwrap()
+            .write(&mut written)
+            .unwrap();
+        assert_eq!(data, &written[..]);
+    }
+
     #[test]
     fn test_fopts_len_error() {
         // FCtrl indicates 8 bytes in FOpts but we will pass empty FOpts
@@ -627,6 +1291,160 @@ mod test {
         }
     }
 
+    fn hex_key(key: &str) -> AES128 {
+        let mut bytes = [0u8; 16];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&key[2 * i..2 * i + 2], 16).unwrap();
+        }
+        AES128(bytes)
+    }
+
+    #[test]
+    fn test_data_mic() {
+        // Unconfirmed uplink with FCnt 2 and payload "test"
+        let data: &[u8] = &[
+            0x40, 0xf1, 0x7d, 0xbe, 0x49, 0x00, 0x02, 0x00, 0x01, 0x95, 0x43, 0x78, 0x76, 0x2b,
+            0x11, 0xff, 0x0d,
+        ];
+        let nwk_skey = hex_key("44024241ed4ce9a68c6a8bc055233fd3");
+        let mut payload = PHYPayload::read(Direction::Uplink, &mut &data[..]).unwrap();
+        assert_eq!(Some([0x2b, 0x11, 0xff, 0x0d]), payload.mic);
+        payload.verify_mic(&nwk_skey, 0).expect("valid mic");
+        assert!(matches!(
+            payload.verify_mic(&nwk_skey, 1),
+            Err(LoraWanError::InvalidMic)
+        ));
+
+        payload.mic = None;
+        payload.set_mic(&nwk_skey, 0).unwrap();
+        assert_eq!(data, Vec::<u8>::try_from(payload).unwrap());
+    }
+
+    #[test]
+    fn test_frm_payload() {
+        let data: &[u8] = &[
+            0x40, 0xf1, 0x7d, 0xbe, 0x49, 0x00, 0x02, 0x00, 0x01, 0x95, 0x43, 0x78, 0x76, 0x2b,
+            0x11, 0xff, 0x0d,
+        ];
+        let app_skey = hex_key("ec925802ae430ca77fd3dd73cb2cc588");
+        let mut payload = PHYPayload::read(Direction::Uplink, &mut &data[..]).unwrap();
+        payload.decrypt_frm_payload(&app_skey, 0).unwrap();
+        let PHYPayloadFrame::MACPayload(mac_payload) = &payload.payload else {
+            panic!("expected mac payload");
+        };
+        assert_eq!(
+            b"test",
+            mac_payload.payload.as_ref().unwrap().payload().as_ref()
+        );
+
+        payload.encrypt_frm_payload(&app_skey, 0).unwrap();
+        assert_eq!(data, Vec::<u8>::try_from(payload).unwrap());
+    }
+
+    #[test]
+    fn test_join_accept_crypt() {
+        let app_key = hex_key("000102030405060708090a0b0c0d0e0f");
+        let mut mhdr = MHDR(0);
+        mhdr.set_mtype(MType::JoinAccept);
+        let mut payload = PHYPayload {
+            mhdr,
+            payload: PHYPayloadFrame::JoinAccept(JoinAccept {
+                app_nonce: [1, 2, 3],
+                net_id: [0x24, 0, 0],
+                dev_addr: 0x48000001,
+                dl_settings: 0,
+                rx_delay: 1,
+                cf_list: None,
+            }),
+            mic: None,
+        };
+        payload.set_mic(&app_key, 0).unwrap();
+        let data = payload.write_join_accept(&app_key).unwrap();
+        assert_eq!(JOIN_ACCEPT_LEN, data.len());
+        assert_ne!(Vec::<u8>::try_from(payload.clone()).unwrap(), data);
+
+        let decrypted = PHYPayload::read_join_accept(&app_key, &mut &data[..]).unwrap();
+        decrypted.verify_mic(&app_key, 0).expect("valid mic");
+        assert_eq!(payload, decrypted);
+    }
+
+    #[test]
+    fn test_join_mic() {
+        let app_key = hex_key("000102030405060708090a0b0c0d0e0f");
+        let (_, data) = &mk_test_packets()[2];
+        let mut payload = PHYPayload::read(Direction::Uplink, &mut &data[..]).unwrap();
+        payload.set_mic(&app_key, 0).unwrap();
+        payload.verify_mic(&app_key, 0).expect("valid mic");
+        assert!(matches!(
+            payload.verify_mic(&hex_key("00000000000000000000000000000000"), 0),
+            Err(LoraWanError::InvalidMic)
+        ));
+        assert!(PHYPayload::proprietary(&[1, 2, 3])
+            .verify_mic(&app_key, 0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_join_mic_vectors() {
+        let app_key = AES128([1; 16]);
+        // JoinRequest with AppEUI 0102030401020304, DevEUI 0203040502030405
+        // and DevNonce 0x102d
+        let data: &[u8] = &[
+            0x00, 0x04, 0x03, 0x02, 0x01, 0x04, 0x03, 0x02, 0x01, 0x05, 0x04, 0x03, 0x02, 0x05,
+            0x04, 0x03, 0x02, 0x2d, 0x10, 0x6a, 0x99, 0x0e, 0x12,
+        ];
+        let mut payload = PHYPayload::read(Direction::Uplink, &mut &data[..]).unwrap();
+        assert_eq!(Some([0x6a, 0x99, 0x0e, 0x12]), payload.mic);
+        payload.verify_mic(&app_key, 0).expect("valid mic");
+        assert!(matches!(
+            payload.verify_mic(&AES128([2; 16]), 0),
+            Err(LoraWanError::InvalidMic)
+        ));
+        payload.mic = None;
+        payload.set_mic(&app_key, 0).unwrap();
+        assert_eq!(data, Vec::<u8>::try_from(payload).unwrap());
+
+        // Encrypted JoinAccept with a CFList of 867.1 to 867.9 MHz
+        let data: &[u8] = &[
+            0x20, 0xe4, 0x56, 0x73, 0xb6, 0x3c, 0xb4, 0xb9, 0xce, 0xcb, 0x2a, 0xa8, 0x3f, 0x03,
+            0x33, 0xe6, 0x15, 0xd2, 0xac, 0x89, 0xee, 0xa1, 0x65, 0x98, 0x37, 0xc3, 0xaa, 0x6d,
+            0xf9, 0x68, 0x98, 0x89, 0xcf,
+        ];
+        let payload = PHYPayload::read_join_accept(&app_key, &mut &data[..]).unwrap();
+        payload.verify_mic(&app_key, 0).expect("valid mic");
+        let PHYPayloadFrame::JoinAccept(join_accept) = &payload.payload else {
+            panic!("expected join accept");
+        };
+        assert_eq!([3, 2, 1], join_accept.app_nonce);
+        assert_eq!(0x12, join_accept.dl_settings);
+        assert_eq!(3, join_accept.rx_delay);
+        assert_eq!(
+            Some(CFList::Frequencies([
+                867_100_000,
+                867_300_000,
+                867_500_000,
+                867_700_000,
+                867_900_000
+            ])),
+            join_accept.cf_list
+        );
+        assert_eq!(data, payload.write_join_accept(&app_key).unwrap());
+
+        // Encrypted JoinAccept without a CFList
+        let app_key = hex_key("00112233445566778899aabbccddeeff");
+        let data: &[u8] = &[
+            0x20, 0x49, 0x3e, 0xeb, 0x51, 0xfb, 0xa2, 0x11, 0x6f, 0x81, 0x0e, 0xdb, 0x37, 0x42,
+            0x97, 0x51, 0x42,
+        ];
+        let payload = PHYPayload::read_join_accept(&app_key, &mut &data[..]).unwrap();
+        payload.verify_mic(&app_key, 0).expect("valid mic");
+        let PHYPayloadFrame::JoinAccept(join_accept) = &payload.payload else {
+            panic!("expected join accept");
+        };
+        assert_eq!(None, join_accept.cf_list);
+        assert_eq!(data, payload.write_join_accept(&app_key).unwrap());
+    }
+
     impl TryFrom<&[u8]> for Routing {
         type Error = LoraWanError;
         fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
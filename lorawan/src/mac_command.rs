@@ -0,0 +1,518 @@
+//! LoRaWAN 1.0.4 MAC commands as carried in FOpts or an FPort 0 payload.
+//!
+//! A command identifier (CID) means a different command depending on the
+//! direction of the frame, so commands are read for a given direction.
+//! Unknown CIDs have an unknown length and take up the rest of the input.
+use super::{Direction, LoraWanError};
+use alloc::{vec, vec::Vec};
+use bytes::{Buf, BufMut, Bytes};
+
+const LINK_CHECK: u8 = 0x02;
+const LINK_ADR: u8 = 0x03;
+const DUTY_CYCLE: u8 = 0x04;
+const RX_PARAM_SETUP: u8 = 0x05;
+const DEV_STATUS: u8 = 0x06;
+const NEW_CHANNEL: u8 = 0x07;
+const RX_TIMING_SETUP: u8 = 0x08;
+const TX_PARAM_SETUP: u8 = 0x09;
+const DL_CHANNEL: u8 = 0x0a;
+const DEVICE_TIME: u8 = 0x0d;
+
+/// Frequencies are sent in units of 100 Hz
+const FREQUENCY_STEP: u32 = 100;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MacCommand {
+    // End device to network
+    LinkCheckReq,
+    LinkADRAns {
+        power_ack: bool,
+        data_rate_ack: bool,
+        channel_mask_ack: bool,
+    },
+    DutyCycleAns,
+    RXParamSetupAns {
+        rx1_dr_offset_ack: bool,
+        rx2_data_rate_ack: bool,
+        channel_ack: bool,
+    },
+    DevStatusAns {
+        battery: u8,
+        /// Demodulation SNR margin in dB, between -32 and 31
+        margin: i8,
+    },
+    NewChannelAns {
+        data_rate_range_ok: bool,
+        channel_frequency_ok: bool,
+    },
+    RXTimingSetupAns,
+    TxParamSetupAns,
+    DlChannelAns {
+        uplink_frequency_exists: bool,
+        channel_frequency_ok: bool,
+    },
+    DeviceTimeReq,
+
+    // Network to end device
+    LinkCheckAns {
+        margin: u8,
+        gw_cnt: u8,
+    },
+    LinkADRReq {
+        data_rate: u8,
+        tx_power: u8,
+        ch_mask: u16,
+        ch_mask_cntl: u8,
+        nb_trans: u8,
+    },
+    DutyCycleReq {
+        max_duty_cycle: u8,
+    },
+    RXParamSetupReq {
+        rx1_dr_offset: u8,
+        rx2_data_rate: u8,
+        /// Frequency in Hz
+        frequency: u32,
+    },
+    DevStatusReq,
+    NewChannelReq {
+        ch_index: u8,
+        /// Frequency in Hz
+        frequency: u32,
+        max_dr: u8,
+        min_dr: u8,
+    },
+    RXTimingSetupReq {
+        /// Delay of the first receive window in seconds
+        delay: u8,
+    },
+    TxParamSetupReq {
+        downlink_dwell_time: bool,
+        uplink_dwell_time: bool,
+        max_eirp: u8,
+    },
+    DlChannelReq {
+        ch_index: u8,
+        /// Frequency in Hz
+        frequency: u32,
+    },
+    DeviceTimeAns {
+        /// Seconds since the GPS epoch
+        seconds: u32,
+        /// Fractional second in 1/256 s steps
+        fractional: u8,
+    },
+
+    /// A command with an unknown CID and its remaining payload
+    Unknown {
+        cid: u8,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_fmt::base64"))]
+        payload: Bytes,
+    },
+}
+
+/// Returns the payload length of the command with the given CID in the given
+/// direction, if known
+fn payload_len(direction: Direction, cid: u8) -> Option<usize> {
+    let len = match (direction, cid) {
+        (Direction::Uplink, LINK_CHECK) => 0,
+        (Direction::Uplink, LINK_ADR) => 1,
+        (Direction::Uplink, DUTY_CYCLE) => 0,
+        (Direction::Uplink, RX_PARAM_SETUP) => 1,
+        (Direction::Uplink, DEV_STATUS) => 2,
+        (Direction::Uplink, NEW_CHANNEL) => 1,
+        (Direction::Uplink, RX_TIMING_SETUP) => 0,
+        (Direction::Uplink, TX_PARAM_SETUP) => 0,
+        (Direction::Uplink, DL_CHANNEL) => 1,
+        (Direction::Uplink, DEVICE_TIME) => 0,
+        (Direction::Downlink, LINK_CHECK) => 2,
+        (Direction::Downlink, LINK_ADR) => 4,
+        (Direction::Downlink, DUTY_CYCLE) => 1,
+        (Direction::Downlink, RX_PARAM_SETUP) => 4,
+        (Direction::Downlink, DEV_STATUS) => 0,
+        (Direction::Downlink, NEW_CHANNEL) => 5,
+        (Direction::Downlink, RX_TIMING_SETUP) => 1,
+        (Direction::Downlink, TX_PARAM_SETUP) => 1,
+        (Direction::Downlink, DL_CHANNEL) => 4,
+        (Direction::Downlink, DEVICE_TIME) => 5,
+        _ => return None,
+    };
+    Some(len)
+}
+
+fn bit(v: u8, bit: u8) -> bool {
+    v & (1 << bit) != 0
+}
+
+pub(crate) fn get_frequency<B: Buf>(reader: &mut B) -> u32 {
+    reader.get_uint_le(3) as u32 * FREQUENCY_STEP
+}
+
+pub(crate) fn put_frequency(output: &mut dyn BufMut, frequency: u32) {
+    output.put_uint_le((frequency / FREQUENCY_STEP) as u64, 3);
+}
+
+impl MacCommand {
+    pub fn cid(&self) -> u8 {
+        match self {
+            Self::LinkCheckReq | Self::LinkCheckAns { .. } => LINK_CHECK,
+            Self::LinkADRAns { .. } | Self::LinkADRReq { .. } => LINK_ADR,
+            Self::DutyCycleAns | Self::DutyCycleReq { .. } => DUTY_CYCLE,
+            Self::RXParamSetupAns { .. } | Self::RXParamSetupReq { .. } => RX_PARAM_SETUP,
+            Self::DevStatusAns { .. } | Self::DevStatusReq => DEV_STATUS,
+            Self::NewChannelAns { .. } | Self::NewChannelReq { .. } => NEW_CHANNEL,
+            Self::RXTimingSetupAns | Self::RXTimingSetupReq { .. } => RX_TIMING_SETUP,
+            Self::TxParamSetupAns | Self::TxParamSetupReq { .. } => TX_PARAM_SETUP,
+            Self::DlChannelAns { .. } | Self::DlChannelReq { .. } => DL_CHANNEL,
+            Self::DeviceTimeReq | Self::DeviceTimeAns { .. } => DEVICE_TIME,
+            Self::Unknown { cid, .. } => *cid,
+        }
+    }
+
+    pub fn read<B: Buf>(direction: Direction, reader: &mut B) -> Result<Self, LoraWanError> {
+        if !reader.has_remaining() {
+            return Err(LoraWanError::InvalidMacCommandSize(0, 0));
+        }
+        let cid = reader.get_u8();
+        let Some(len) = payload_len(direction, cid) else {
+            return Ok(Self::Unknown {
+                cid,
+                payload: reader.copy_to_bytes(reader.remaining()),
+            });
+        };
+        if reader.remaining() < len {
+            return Err(LoraWanError::InvalidMacCommandSize(cid, reader.remaining()));
+        }
+        let res = match (direction, cid) {
+            (Direction::Uplink, LINK_CHECK) => Self::LinkCheckReq,
+            (Direction::Uplink, LINK_ADR) => {
+                let status = reader.get_u8();
+                Self::LinkADRAns {
+                    power_ack: bit(status, 2),
+                    data_rate_ack: bit(status, 1),
+                    channel_mask_ack: bit(status, 0),
+                }
+            }
+            (Direction::Uplink, DUTY_CYCLE) => Self::DutyCycleAns,
+            (Direction::Uplink, RX_PARAM_SETUP) => {
+                let status = reader.get_u8();
+                Self::RXParamSetupAns {
+                    rx1_dr_offset_ack: bit(status, 2),
+                    rx2_data_rate_ack: bit(status, 1),
+                    channel_ack: bit(status, 0),
+                }
+            }
+            (Direction::Uplink, DEV_STATUS) => {
+                let battery = reader.get_u8();
+                // Sign extend the 6 bit margin
+                let margin = ((reader.get_u8() << 2) as i8) >> 2;
+                Self::DevStatusAns { battery, margin }
+            }
+            (Direction::Uplink, NEW_CHANNEL) => {
+                let status = reader.get_u8();
+                Self::NewChannelAns {
+                    data_rate_range_ok: bit(status, 1),
+                    channel_frequency_ok: bit(status, 0),
+                }
+            }
+            (Direction::Uplink, RX_TIMING_SETUP) => Self::RXTimingSetupAns,
+            (Direction::Uplink, TX_PARAM_SETUP) => Self::TxParamSetupAns,
+            (Direction::Uplink, DL_CHANNEL) => {
+                let status = reader.get_u8();
+                Self::DlChannelAns {
+                    uplink_frequency_exists: bit(status, 1),
+                    channel_frequency_ok: bit(status, 0),
+                }
+            }
+            (Direction::Uplink, DEVICE_TIME) => Self::DeviceTimeReq,
+            (Direction::Downlink, LINK_CHECK) => Self::LinkCheckAns {
+                margin: reader.get_u8(),
+                gw_cnt: reader.get_u8(),
+            },
+            (Direction::Downlink, LINK_ADR) => {
+                let data_rate_tx_power = reader.get_u8();
+                let ch_mask = reader.get_u16_le();
+                let redundancy = reader.get_u8();
+                Self::LinkADRReq {
+                    data_rate: data_rate_tx_power >> 4,
+                    tx_power: data_rate_tx_power & 0x0f,
+                    ch_mask,
+                    ch_mask_cntl: (redundancy >> 4) & 0x07,
+                    nb_trans: redundancy & 0x0f,
+                }
+            }
+            (Direction::Downlink, DUTY_CYCLE) => Self::DutyCycleReq {
+                max_duty_cycle: reader.get_u8() & 0x0f,
+            },
+            (Direction::Downlink, RX_PARAM_SETUP) => {
+                let dl_settings = reader.get_u8();
+                Self::RXParamSetupReq {
+                    rx1_dr_offset: (dl_settings >> 4) & 0x07,
+                    rx2_data_rate: dl_settings & 0x0f,
+                    frequency: get_frequency(reader),
+                }
+            }
+            (Direction::Downlink, DEV_STATUS) => Self::DevStatusReq,
+            (Direction::Downlink, NEW_CHANNEL) => {
+                let ch_index = reader.get_u8();
+                let frequency = get_frequency(reader);
+                let dr_range = reader.get_u8();
+                Self::NewChannelReq {
+                    ch_index,
+                    frequency,
+                    max_dr: dr_range >> 4,
+                    min_dr: dr_range & 0x0f,
+                }
+            }
+            (Direction::Downlink, RX_TIMING_SETUP) => Self::RXTimingSetupReq {
+                delay: reader.get_u8() & 0x0f,
+            },
+            (Direction::Downlink, TX_PARAM_SETUP) => {
+                let eirp_dwell_time = reader.get_u8();
+                Self::TxParamSetupReq {
+                    downlink_dwell_time: bit(eirp_dwell_time, 5),
+                    uplink_dwell_time: bit(eirp_dwell_time, 4),
+                    max_eirp: eirp_dwell_time & 0x0f,
+                }
+            }
+            (Direction::Downlink, DL_CHANNEL) => Self::DlChannelReq {
+                ch_index: reader.get_u8(),
+                frequency: get_frequency(reader),
+            },
+            (Direction::Downlink, DEVICE_TIME) => Self::DeviceTimeAns {
+                seconds: reader.get_u32_le(),
+                fractional: reader.get_u8(),
+            },
+            // payload_len only knows the commands above
+            _ => unreachable!(),
+        };
+        Ok(res)
+    }
+
+    /// Reads all commands in the given FOpts or FPort 0 payload
+    pub fn read_all<B: Buf>(
+        direction: Direction,
+        reader: &mut B,
+    ) -> Result<Vec<Self>, LoraWanError> {
+        let mut res = vec![];
+        while reader.has_remaining() {
+            res.push(Self::read(direction, reader)?);
+        }
+        Ok(res)
+    }
+
+    pub fn write(&self, output: &mut dyn BufMut) -> Result<usize, LoraWanError> {
+        let start = output.remaining_mut();
+        output.put_u8(self.cid());
+        match self {
+            Self::LinkCheckReq
+            | Self::DutyCycleAns
+            | Self::RXTimingSetupAns
+            | Self::TxParamSetupAns
+            | Self::DeviceTimeReq
+            | Self::DevStatusReq => (),
+            Self::LinkADRAns {
+                power_ack,
+                data_rate_ack,
+                channel_mask_ack,
+            } => output.put_u8(
+                (*power_ack as u8) << 2 | (*data_rate_ack as u8) << 1 | *channel_mask_ack as u8,
+            ),
+            Self::RXParamSetupAns {
+                rx1_dr_offset_ack,
+                rx2_data_rate_ack,
+                channel_ack,
+            } => output.put_u8(
+                (*rx1_dr_offset_ack as u8) << 2
+                    | (*rx2_data_rate_ack as u8) << 1
+                    | *channel_ack as u8,
+            ),
+            Self::DevStatusAns { battery, margin } => {
+                output.put_u8(*battery);
+                output.put_u8(*margin as u8 & 0x3f);
+            }
+            Self::NewChannelAns {
+                data_rate_range_ok,
+                channel_frequency_ok,
+            } => output.put_u8((*data_rate_range_ok as u8) << 1 | *channel_frequency_ok as u8),
+            Self::DlChannelAns {
+                uplink_frequency_exists,
+                channel_frequency_ok,
+            } => output.put_u8((*uplink_frequency_exists as u8) << 1 | *channel_frequency_ok as u8),
+            Self::LinkCheckAns { margin, gw_cnt } => {
+                output.put_u8(*margin);
+                output.put_u8(*gw_cnt);
+            }
+            Self::LinkADRReq {
+                data_rate,
+                tx_power,
+                ch_mask,
+                ch_mask_cntl,
+                nb_trans,
+            } => {
+                output.put_u8(data_rate << 4 | tx_power & 0x0f);
+                output.put_u16_le(*ch_mask);
+                output.put_u8((ch_mask_cntl & 0x07) << 4 | nb_trans & 0x0f);
+            }
+            Self::DutyCycleReq { max_duty_cycle } => output.put_u8(max_duty_cycle & 0x0f),
+            Self::RXParamSetupReq {
+                rx1_dr_offset,
+                rx2_data_rate,
+                frequency,
+            } => {
+                output.put_u8((rx1_dr_offset & 0x07) << 4 | rx2_data_rate & 0x0f);
+                put_frequency(output, *frequency);
+            }
+            Self::NewChannelReq {
+                ch_index,
+                frequency,
+                max_dr,
+                min_dr,
+            } => {
+                output.put_u8(*ch_index);
+                put_frequency(output, *frequency);
+                output.put_u8(max_dr << 4 | min_dr & 0x0f);
+            }
+            Self::RXTimingSetupReq { delay } => output.put_u8(delay & 0x0f),
+            Self::TxParamSetupReq {
+                downlink_dwell_time,
+                uplink_dwell_time,
+                max_eirp,
+            } => output.put_u8(
+                (*downlink_dwell_time as u8) << 5
+                    | (*uplink_dwell_time as u8) << 4
+                    | max_eirp & 0x0f,
+            ),
+            Self::DlChannelReq {
+                ch_index,
+                frequency,
+            } => {
+                output.put_u8(*ch_index);
+                put_frequency(output, *frequency);
+            }
+            Self::DeviceTimeAns {
+                seconds,
+                fractional,
+            } => {
+                output.put_u32_le(*seconds);
+                output.put_u8(*fractional);
+            }
+            Self::Unknown { payload, .. } => output.put_slice(payload),
+        }
+        Ok(start - output.remaining_mut())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn roundtrip(direction: Direction, data: &[u8]) -> Vec<MacCommand> {
+        let commands = MacCommand::read_all(direction, &mut &data[..]).expect("commands");
+        let mut written = vec![];
+        for command in &commands {
+            command.write(&mut written).expect("written");
+        }
+        assert_eq!(data, &written[..]);
+        commands
+    }
+
+    #[test]
+    fn test_uplink() {
+        let commands = roundtrip(
+            Direction::Uplink,
+            &[
+                0x02, 0x03, 0x07, 0x06, 0xff, 0x3e, 0x0d, 0x05, 0x06, 0x0a, 0x03,
+            ],
+        );
+        assert_eq!(
+            vec![
+                MacCommand::LinkCheckReq,
+                MacCommand::LinkADRAns {
+                    power_ack: true,
+                    data_rate_ack: true,
+                    channel_mask_ack: true,
+                },
+                MacCommand::DevStatusAns {
+                    battery: 255,
+                    margin: -2,
+                },
+                MacCommand::DeviceTimeReq,
+                MacCommand::RXParamSetupAns {
+                    rx1_dr_offset_ack: true,
+                    rx2_data_rate_ack: true,
+                    channel_ack: false,
+                },
+                MacCommand::DlChannelAns {
+                    uplink_frequency_exists: true,
+                    channel_frequency_ok: true,
+                },
+            ],
+            commands
+        );
+    }
+
+    #[test]
+    fn test_downlink() {
+        let commands = roundtrip(
+            Direction::Downlink,
+            &[
+                0x02, 0x14, 0x03, 0x03, 0x53, 0xff, 0x00, 0x01, 0x07, 0x03, 0x18, 0x4f, 0x84, 0x50,
+                0x05, 0x02, 0xd2, 0xad, 0x84, 0x0d, 0x10, 0x32, 0x54, 0x76, 0x80, 0x09, 0x35,
+            ],
+        );
+        assert_eq!(
+            vec![
+                MacCommand::LinkCheckAns {
+                    margin: 20,
+                    gw_cnt: 3,
+                },
+                MacCommand::LinkADRReq {
+                    data_rate: 5,
+                    tx_power: 3,
+                    ch_mask: 0x00ff,
+                    ch_mask_cntl: 0,
+                    nb_trans: 1,
+                },
+                MacCommand::NewChannelReq {
+                    ch_index: 3,
+                    frequency: 867_100_000,
+                    max_dr: 5,
+                    min_dr: 0,
+                },
+                MacCommand::RXParamSetupReq {
+                    rx1_dr_offset: 0,
+                    rx2_data_rate: 2,
+                    frequency: 869_525_000,
+                },
+                MacCommand::DeviceTimeAns {
+                    seconds: 0x76543210,
+                    fractional: 0x80,
+                },
+                MacCommand::TxParamSetupReq {
+                    downlink_dwell_time: true,
+                    uplink_dwell_time: true,
+                    max_eirp: 5,
+                },
+            ],
+            commands
+        );
+    }
+
+    #[test]
+    fn test_unknown_and_truncated() {
+        let commands = roundtrip(Direction::Uplink, &[0x06, 0x10, 0x20, 0x80, 0x01, 0x02]);
+        assert_eq!(
+            MacCommand::Unknown {
+                cid: 0x80,
+                payload: Bytes::from_static(&[0x01, 0x02]),
+            },
+            commands[1]
+        );
+        assert!(matches!(
+            MacCommand::read_all(Direction::Downlink, &mut &[0x03, 0x53, 0xff][..]),
+            Err(LoraWanError::InvalidMacCommandSize(0x03, 2))
+        ));
+    }
+}
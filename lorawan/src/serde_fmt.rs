@@ -0,0 +1,230 @@
+//! Readable serde encodings for frame fields: EUIs, DevAddrs and NetIDs as hex
+//! numbers, fixed size byte fields like the MIC as hex in frame byte order,
+//! and payloads as base64.
+use super::{FCtrlDownlink, FCtrlUplink, MType, MHDR};
+use ::base64::{engine::general_purpose::STANDARD, Engine};
+use alloc::{format, string::String};
+use bytes::Bytes;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let str = String::deserialize(deserializer)?;
+    u64::from_str_radix(&str, 16).map_err(de::Error::custom)
+}
+
+pub mod eui {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(eui: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{eui:016X}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        from_hex(deserializer)
+    }
+}
+
+pub mod dev_addr {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(dev_addr: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{dev_addr:08X}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        let v = from_hex(deserializer)?;
+        u32::try_from(v).map_err(de::Error::custom)
+    }
+}
+
+pub mod net_id {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(net_id: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{net_id:06X}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        let v = from_hex(deserializer)?;
+        match u32::try_from(v) {
+            Ok(v) if v <= 0xFF_FFFF => Ok(v),
+            _ => Err(de::Error::custom(format!("invalid net id: {v:#x}"))),
+        }
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+fn decode_hex<const N: usize, E: de::Error>(str: &str) -> Result<[u8; N], E> {
+    if str.len() != 2 * N || !str.is_ascii() {
+        return Err(E::invalid_length(str.len(), &"hex encoded bytes"));
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&str[2 * i..2 * i + 2], 16).map_err(E::custom)?;
+    }
+    Ok(bytes)
+}
+
+pub mod hex {
+    use super::*;
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode_hex(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        decode_hex(&String::deserialize(deserializer)?)
+    }
+}
+
+pub mod option_hex {
+    use super::*;
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &Option<[u8; N]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        bytes.map(|bytes| encode_hex(&bytes)).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<Option<[u8; N]>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|str| decode_hex(&str))
+            .transpose()
+    }
+}
+
+pub mod base64 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        let str = String::deserialize(deserializer)?;
+        STANDARD
+            .decode(str)
+            .map(Bytes::from)
+            .map_err(de::Error::custom)
+    }
+}
+
+fn is_zero(v: &u8) -> bool {
+    *v == 0
+}
+
+fn is_false(v: &bool) -> bool {
+    !v
+}
+
+pub(crate) fn is_zeroed<const N: usize>(v: &[u8; N]) -> bool {
+    v.iter().all(|b| *b == 0)
+}
+
+/// The RFU bits of headers are only included when set so frames round trip
+/// unchanged
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct MHDRFields {
+    mtype: MType,
+    major: u8,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    rfu: u8,
+}
+
+impl From<MHDR> for MHDRFields {
+    fn from(v: MHDR) -> Self {
+        Self {
+            mtype: v.mtype(),
+            major: v.major(),
+            rfu: v.rfu(),
+        }
+    }
+}
+
+impl From<MHDRFields> for MHDR {
+    fn from(v: MHDRFields) -> Self {
+        let mut mhdr = MHDR(0);
+        mhdr.set_mtype(v.mtype);
+        mhdr.set_rfu(v.rfu);
+        mhdr.set_major(v.major);
+        mhdr
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct FCtrlUplinkFields {
+    adr: bool,
+    adr_ack_req: bool,
+    ack: bool,
+    fpending: bool,
+    fopts_len: u8,
+}
+
+impl From<FCtrlUplink> for FCtrlUplinkFields {
+    fn from(v: FCtrlUplink) -> Self {
+        Self {
+            adr: v.adr(),
+            adr_ack_req: v.adr_ack_req(),
+            ack: v.ack(),
+            fpending: v.fpending(),
+            fopts_len: v.fopts_len(),
+        }
+    }
+}
+
+impl From<FCtrlUplinkFields> for FCtrlUplink {
+    fn from(v: FCtrlUplinkFields) -> Self {
+        let mut fctrl = FCtrlUplink(0);
+        fctrl.set_adr(v.adr);
+        fctrl.set_addr_ack_req(v.adr_ack_req);
+        fctrl.set_ack(v.ack);
+        fctrl.set_fpending(v.fpending);
+        fctrl.set_fopts_len(v.fopts_len);
+        fctrl
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct FCtrlDownlinkFields {
+    adr: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    rfu: bool,
+    ack: bool,
+    class_b: bool,
+    fopts_len: u8,
+}
+
+impl From<FCtrlDownlink> for FCtrlDownlinkFields {
+    fn from(v: FCtrlDownlink) -> Self {
+        Self {
+            adr: v.adr(),
+            rfu: v.rfu(),
+            ack: v.ack(),
+            class_b: v.class_b(),
+            fopts_len: v.fopts_len(),
+        }
+    }
+}
+
+impl From<FCtrlDownlinkFields> for FCtrlDownlink {
+    fn from(v: FCtrlDownlinkFields) -> Self {
+        let mut fctrl = FCtrlDownlink(0);
+        fctrl.set_adr(v.adr);
+        fctrl.set_rfu(v.rfu);
+        fctrl.set_ack(v.ack);
+        fctrl.set_class_b(v.class_b);
+        fctrl.set_fopts_len(v.fopts_len);
+        fctrl
+    }
+}
@@ -1,28 +1,53 @@
-use super::{AddGatewayReq, GatewayStakingMode, PubkeyReq, RegionReq, RouterReq};
+use super::{
+    AddGatewayReq, ClientsReq, EventsReq, GatewayStakingMode, JoinFilterReq, LocalExtClient,
+    PubkeyReq, RegionReq, RouterEndpointsReq, RouterReq,
+};
 use crate::{
     error::{DecodeError, Error},
+    events::Event,
+    gateway::ForwarderClient,
     packet_router::RouterStatus,
     settings::{ListenAddress, StakingMode},
-    PublicKey, Region, Result,
+    uplink_filter::JoinFilterStatus,
+    PublicKey, Region, Result, Stream,
 };
+use futures::StreamExt;
 use helium_proto::{
     services::local::Client, BlockchainTxn, BlockchainTxnAddGatewayV1, Message, Txn,
 };
 use std::convert::TryFrom;
+use tokio::net::UnixStream;
 use tonic::transport::{Channel, Endpoint};
 
 pub struct LocalClient {
     client: Client<Channel>,
+    ext_client: LocalExtClient<Channel>,
 }
 
 impl LocalClient {
     pub async fn new(address: &ListenAddress) -> Result<Self> {
-        let uri = http::Uri::try_from(address)?;
-        let endpoint = Endpoint::from_shared(uri.to_string()).unwrap();
-        let client = Client::connect(endpoint)
-            .await
-            .map_err(Error::local_client_connect)?;
-        Ok(Self { client })
+        let channel = match address.unix_path() {
+            Some(path) => {
+                // The uri is required but not used to connect to a unix socket
+                Endpoint::from_static("http://127.0.0.1")
+                    .connect_with_connector(tower::service_fn(move |_: http::Uri| {
+                        UnixStream::connect(path.clone())
+                    }))
+                    .await
+            }
+            None => {
+                let uri = http::Uri::try_from(address)?;
+                Endpoint::from_shared(uri.to_string())
+                    .unwrap()
+                    .connect()
+                    .await
+            }
+        }
+        .map_err(Error::local_client_connect)?;
+        Ok(Self {
+            client: Client::new(channel.clone()),
+            ext_client: LocalExtClient::new(channel),
+        })
     }
 
     pub async fn pubkey(&mut self) -> Result<(PublicKey, PublicKey)> {
@@ -40,9 +65,44 @@ impl LocalClient {
 
     pub async fn router(&mut self) -> Result<RouterStatus> {
         let response = self.client.router(RouterReq {}).await?;
+        let mut status: RouterStatus = response.into_inner().try_into()?;
+        let endpoints = self
+            .ext_client
+            .router_endpoints(RouterEndpointsReq {})
+            .await?
+            .into_inner();
+        status.endpoints = endpoints.uris;
+        status.reason = endpoints.reason.parse()?;
+        status.failures = endpoints.failures;
+        Ok(status)
+    }
+
+    pub async fn clients(&mut self) -> Result<Vec<ForwarderClient>> {
+        let response = self.ext_client.clients(ClientsReq {}).await?;
+        response
+            .into_inner()
+            .clients
+            .into_iter()
+            .map(ForwarderClient::try_from)
+            .collect()
+    }
+
+    pub async fn join_filter(&mut self, reload: bool) -> Result<JoinFilterStatus> {
+        let response = self
+            .ext_client
+            .join_filter(JoinFilterReq { reload })
+            .await?;
         response.into_inner().try_into()
     }
 
+    /// Returns a stream of live gateway events
+    pub async fn events(&mut self) -> Result<Stream<Event>> {
+        let events = self.ext_client.events(EventsReq {}).await?.into_inner();
+        Ok(events
+            .map(|event| event.map_err(Error::from).and_then(Event::try_from))
+            .boxed())
+    }
+
     pub async fn add_gateway(
         &mut self,
         owner: &PublicKey,
@@ -1,7 +1,21 @@
 mod client;
+mod rest;
 mod server;
 
+/// Local api extensions for this gateway which are served next to the helium
+/// local api
+#[allow(clippy::all)]
+mod ext {
+    tonic::include_proto!("gateway_rs");
+}
+
 pub use client::LocalClient;
+pub use ext::{
+    local_ext_client::LocalExtClient, local_ext_server::LocalExt, local_ext_server::LocalExtServer,
+    BeaconEventV1, ClientsReq, ClientsRes, DownlinkEventV1, EventV1, EventsReq, ForwarderClientV1,
+    IgnoredEventV1, JoinFilterReq, JoinFilterRes, JoinRuleV1, RouterEndpointsReq,
+    RouterEndpointsRes, UplinkEventV1, WitnessEventV1,
+};
 pub use helium_proto::{
     services::local::{
         AddGatewayReq, AddGatewayRes, PubkeyReq, PubkeyRes, RegionReq, RegionRes, RouterReq,
@@ -11,7 +25,14 @@ pub use helium_proto::{
 };
 pub use server::LocalServer;
 
-use crate::{Error, PublicKey, Result};
+use crate::{
+    events::{
+        BeaconEvent, DownlinkEvent, Event, EventKind, IgnoredEvent, UplinkEvent, WitnessEvent,
+    },
+    gateway::ForwarderClient,
+    uplink_filter::{JoinFilterStatus, JoinRule},
+    DecodeError, Error, PublicKey, Result,
+};
 
 impl TryFrom<RouterRes> for crate::packet_router::RouterStatus {
     type Error = Error;
@@ -21,6 +42,197 @@ impl TryFrom<RouterRes> for crate::packet_router::RouterStatus {
             uri: http::Uri::from_str(&value.uri)?,
             connected: value.connected,
             session_key: PublicKey::try_from(value.session_key).ok(),
+            endpoints: vec![value.uri],
+            reason: Default::default(),
+            failures: 0,
+        })
+    }
+}
+
+impl From<&crate::packet_router::RouterStatus> for RouterEndpointsRes {
+    fn from(value: &crate::packet_router::RouterStatus) -> Self {
+        Self {
+            uris: value.endpoints.clone(),
+            active: value.uri.to_string(),
+            reason: value.reason.to_string(),
+            failures: value.failures,
+        }
+    }
+}
+
+impl From<ForwarderClient> for ForwarderClientV1 {
+    fn from(value: ForwarderClient) -> Self {
+        Self {
+            mac: value.mac,
+            addr: value.addr.to_string(),
+            primary: value.primary,
+        }
+    }
+}
+
+impl TryFrom<ForwarderClientV1> for ForwarderClient {
+    type Error = Error;
+    fn try_from(value: ForwarderClientV1) -> Result<Self> {
+        Ok(Self {
+            mac: value.mac,
+            addr: value.addr.parse()?,
+            primary: value.primary,
+        })
+    }
+}
+
+impl From<JoinRule> for JoinRuleV1 {
+    fn from(value: JoinRule) -> Self {
+        Self {
+            name: value.name,
+            action: value.action.to_string(),
+            app_eui: value.app_eui.iter().map(ToString::to_string).collect(),
+            dev_eui: value.dev_eui.iter().map(ToString::to_string).collect(),
+            matches: value.matches,
+        }
+    }
+}
+
+impl TryFrom<JoinRuleV1> for JoinRule {
+    type Error = Error;
+    fn try_from(value: JoinRuleV1) -> Result<Self> {
+        Ok(Self {
+            name: value.name,
+            action: value.action.parse()?,
+            app_eui: value
+                .app_eui
+                .iter()
+                .map(|pattern| pattern.parse())
+                .collect::<Result<_>>()?,
+            dev_eui: value
+                .dev_eui
+                .iter()
+                .map(|pattern| pattern.parse())
+                .collect::<Result<_>>()?,
+            matches: value.matches,
+        })
+    }
+}
+
+impl From<JoinFilterStatus> for JoinFilterRes {
+    fn from(value: JoinFilterStatus) -> Self {
+        Self {
+            path: value
+                .path
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            rules: value.rules.into_iter().map(JoinRuleV1::from).collect(),
+        }
+    }
+}
+
+impl TryFrom<JoinFilterRes> for JoinFilterStatus {
+    type Error = Error;
+    fn try_from(value: JoinFilterRes) -> Result<Self> {
+        Ok(Self {
+            path: (!value.path.is_empty()).then(|| value.path.into()),
+            rules: value
+                .rules
+                .into_iter()
+                .map(JoinRule::try_from)
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl From<Event> for EventV1 {
+    fn from(value: Event) -> Self {
+        use ext::event_v1::Kind;
+        let kind = match value.kind {
+            EventKind::Uplink(event) => Kind::Uplink(UplinkEventV1 {
+                mac: event.mac,
+                tmst: event.tmst,
+                frequency: event.frequency,
+                datarate: event.datarate,
+                rssi: event.rssi,
+                snr: event.snr,
+                mtype: event.mtype,
+                dev_addr: event
+                    .dev_addr
+                    .map(|dev_addr| dev_addr.to_string())
+                    .unwrap_or_default(),
+                fcnt: event.fcnt.unwrap_or_default().into(),
+            }),
+            EventKind::Ignored(event) => Kind::Ignored(IgnoredEventV1 {
+                mac: event.mac,
+                reason: event.reason,
+            }),
+            EventKind::Downlink(event) => Kind::Downlink(DownlinkEventV1 {
+                mac: event.mac,
+                window: event.window,
+                outcome: event.outcome,
+                frequency: event.frequency,
+                tx_power: event.tx_power,
+            }),
+            EventKind::Beacon(event) => Kind::Beacon(BeaconEventV1 {
+                beacon_id: event.beacon_id,
+                status: event.status,
+                tx_power: event.tx_power.unwrap_or_default(),
+            }),
+            EventKind::Witness(event) => Kind::Witness(WitnessEventV1 {
+                beacon_id: event.beacon_id,
+                status: event.status,
+            }),
+        };
+        Self {
+            timestamp: value.timestamp,
+            kind: Some(kind),
+        }
+    }
+}
+
+impl TryFrom<EventV1> for Event {
+    type Error = Error;
+    fn try_from(value: EventV1) -> Result<Self> {
+        use ext::event_v1::Kind;
+        let kind = match value.kind {
+            Some(Kind::Uplink(event)) => {
+                // Only data frames have a device address and frame counter
+                let dev_addr = (!event.dev_addr.is_empty())
+                    .then(|| event.dev_addr.parse::<lorawan::DevAddr>())
+                    .transpose()?;
+                EventKind::Uplink(UplinkEvent {
+                    mac: event.mac,
+                    tmst: event.tmst,
+                    frequency: event.frequency,
+                    datarate: event.datarate,
+                    rssi: event.rssi,
+                    snr: event.snr,
+                    mtype: event.mtype,
+                    fcnt: dev_addr.map(|_| event.fcnt as u16),
+                    dev_addr,
+                })
+            }
+            Some(Kind::Ignored(event)) => EventKind::Ignored(IgnoredEvent {
+                mac: event.mac,
+                reason: event.reason,
+            }),
+            Some(Kind::Downlink(event)) => EventKind::Downlink(DownlinkEvent {
+                mac: event.mac,
+                window: event.window,
+                outcome: event.outcome,
+                frequency: event.frequency,
+                tx_power: event.tx_power,
+            }),
+            Some(Kind::Beacon(event)) => EventKind::Beacon(BeaconEvent {
+                tx_power: (event.status == "sent").then_some(event.tx_power),
+                beacon_id: event.beacon_id,
+                status: event.status,
+            }),
+            Some(Kind::Witness(event)) => EventKind::Witness(WitnessEvent {
+                beacon_id: event.beacon_id,
+                status: event.status,
+            }),
+            None => return Err(DecodeError::prost_decode("missing event kind")),
+        };
+        Ok(Self {
+            timestamp: value.timestamp,
+            kind,
         })
     }
 }
@@ -0,0 +1,268 @@
+//! The local api served as json over http for integrations on the gateway
+//! host that can not use grpc.
+//!
+//! Routes:
+//!
+//! * `GET /health` - Liveness of the gateway service
+//! * `GET /v1/pubkey` - Gateway and onboarding keys
+//! * `GET /v1/region` - Region and region parameters
+//! * `GET /v1/router` - Packet router status
+//! * `POST /v1/add_gateway` - Add gateway transaction for a json body with
+//!   `owner`, `payer` and optional `mode` fields
+//!
+//! Browsers can reach plain http listeners on the gateway host, so requests
+//! must name the host as `localhost`, a loopback address or the listen address
+//! to keep web pages from reaching the api through dns rebinding. Posted bodies
+//! must be json, which browsers do not send cross origin without a preflight
+//! request, and are limited in size.
+use super::{server::LocalServer, AddGatewayReq, PubkeyReq};
+use crate::{
+    cmd::add::{parse_pubkey, txn_json},
+    settings::{self, StakingMode},
+    Error, PublicKey, Result,
+};
+use futures::TryFutureExt;
+use helium_proto::{services::local::Api, BlockchainTxn, Message, Txn};
+use hyper::{
+    body::HttpBody,
+    header::{CONTENT_TYPE, HOST},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, StatusCode,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use tracing::info;
+
+/// Maximum size of a request body
+const MAX_BODY_SIZE: usize = 4096;
+
+type HttpResult = std::result::Result<Value, (StatusCode, String)>;
+
+#[derive(Debug, Deserialize)]
+struct AddGatewayBody {
+    owner: String,
+    payer: String,
+    /// Staking mode, "dataonly" or "full". Default dataonly
+    #[serde(default)]
+    mode: Option<String>,
+}
+
+/// Serves the local api as json on the given listen address until shutdown
+pub async fn run(
+    api: Arc<LocalServer>,
+    listen_addr: SocketAddr,
+    shutdown: &triggered::Listener,
+) -> Result {
+    info!(listen = %listen_addr, "starting http api");
+    let make_service = make_service_fn(move |_conn| {
+        let api = api.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(api.clone(), listen_addr, request)
+            }))
+        }
+    });
+    hyper::Server::try_bind(&listen_addr)?
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown.clone())
+        .map_err(Error::from)
+        .await
+}
+
+async fn handle_request(
+    api: Arc<LocalServer>,
+    listen_addr: SocketAddr,
+    request: Request<Body>,
+) -> std::result::Result<Response<Body>, Infallible> {
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or_default();
+    let result = match (request.method(), request.uri().path()) {
+        _ if !is_local_host(host, &listen_addr) => {
+            Err((StatusCode::FORBIDDEN, "invalid host".to_string()))
+        }
+        (&Method::GET, "/health") => Ok(json!({
+            "status": "ok",
+            "version": settings::version().to_string(),
+        })),
+        (&Method::GET, "/v1/pubkey") => pubkey(&api).await,
+        (&Method::GET, "/v1/region") => Ok(region(&api)),
+        (&Method::GET, "/v1/router") => router(&api).await,
+        (&Method::POST, "/v1/add_gateway") => add_gateway(&api, request).await,
+        _ => Err((StatusCode::NOT_FOUND, "not found".to_string())),
+    };
+    let (status, body) = match result {
+        Ok(body) => (StatusCode::OK, body),
+        Err((status, error)) => (status, json!({ "error": error })),
+    };
+    let response = Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("valid response");
+    Ok(response)
+}
+
+/// Returns whether the given Host header value names the gateway host as
+/// `localhost`, a loopback address or the listen address
+fn is_local_host(host: &str, listen_addr: &SocketAddr) -> bool {
+    let Ok(authority) = host.parse::<http::uri::Authority>() else {
+        return false;
+    };
+    let host = authority
+        .host()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    if host.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    host.parse::<IpAddr>()
+        .is_ok_and(|ip| ip.is_loopback() || ip == listen_addr.ip())
+}
+
+/// Reads a json request body of at most `MAX_BODY_SIZE` bytes
+async fn json_body(request: Request<Body>) -> std::result::Result<Vec<u8>, (StatusCode, String)> {
+    let is_json = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+    if !is_json {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "expected application/json".to_string(),
+        ));
+    }
+    let too_large = || {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("body larger than {MAX_BODY_SIZE} bytes"),
+        )
+    };
+    let mut body = request.into_body();
+    if body.size_hint().lower() > MAX_BODY_SIZE as u64 {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(bad_request)?;
+        if bytes.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+fn internal_error<E: ToString>(err: E) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
+
+fn bad_request<E: ToString>(err: E) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, err.to_string())
+}
+
+fn status_error(status: tonic::Status) -> (StatusCode, String) {
+    match status.code() {
+        tonic::Code::InvalidArgument => bad_request(status.message()),
+        _ => internal_error(status.message()),
+    }
+}
+
+async fn pubkey(api: &LocalServer) -> HttpResult {
+    let response = api
+        .pubkey(tonic::Request::new(PubkeyReq {}))
+        .await
+        .map_err(status_error)?
+        .into_inner();
+    let key = PublicKey::try_from(response.address).map_err(internal_error)?;
+    let onboarding_key =
+        PublicKey::try_from(response.onboarding_address).map_err(internal_error)?;
+    Ok(json!({
+        "key": key.to_string(),
+        "onboarding_key": onboarding_key.to_string(),
+    }))
+}
+
+fn region(api: &LocalServer) -> Value {
+    let region_params = api.region_params();
+    let channels: Vec<Value> = region_params
+        .params
+        .iter()
+        .map(|param| {
+            json!({
+                "frequency": param.channel_frequency,
+                "bandwidth": param.bandwidth,
+                "max_eirp": param.max_eirp,
+            })
+        })
+        .collect();
+    json!({
+        "region": region_params.region.to_string(),
+        "timestamp": region_params.timestamp,
+        "max_conducted_power": region_params.max_conducted_power().ok(),
+        "channels": channels,
+    })
+}
+
+async fn router(api: &LocalServer) -> HttpResult {
+    let status = api.router_status().await.map_err(internal_error)?;
+    serde_json::to_value(status).map_err(internal_error)
+}
+
+async fn add_gateway(api: &LocalServer, request: Request<Body>) -> HttpResult {
+    let body = json_body(request).await?;
+    let body: AddGatewayBody = serde_json::from_slice(&body).map_err(bad_request)?;
+    let owner = parse_pubkey(&body.owner).map_err(bad_request)?;
+    let payer = parse_pubkey(&body.payer).map_err(bad_request)?;
+    let mode = match body.mode {
+        Some(mode) => <StakingMode as clap::ValueEnum>::from_str(&mode, true)
+            .map_err(|_| bad_request(format!("invalid staking mode \"{mode}\"")))?,
+        None => StakingMode::DataOnly,
+    };
+    let response = api
+        .add_gateway(tonic::Request::new(AddGatewayReq {
+            owner: owner.to_vec(),
+            payer: payer.to_vec(),
+            staking_mode: super::GatewayStakingMode::from(&mode).into(),
+        }))
+        .await
+        .map_err(status_error)?
+        .into_inner();
+    let envelope =
+        BlockchainTxn::decode(response.add_gateway_txn.as_ref()).map_err(internal_error)?;
+    match envelope.txn {
+        Some(Txn::AddGateway(txn)) => txn_json(&mode, txn).map_err(internal_error),
+        _ => Err(internal_error("invalid add gateway transaction")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_local_host() {
+        let loopback: SocketAddr = "127.0.0.1:4468".parse().expect("listen addr");
+        assert!(is_local_host("localhost:4468", &loopback));
+        assert!(is_local_host("127.0.0.1:4468", &loopback));
+        assert!(is_local_host("[::1]:4468", &loopback));
+        assert!(is_local_host("LOCALHOST", &loopback));
+        // Rebound dns names and other hosts are rejected
+        assert!(!is_local_host("attacker.example.com:4468", &loopback));
+        assert!(!is_local_host("192.168.1.10:4468", &loopback));
+        assert!(!is_local_host("", &loopback));
+
+        let lan: SocketAddr = "192.168.1.10:4468".parse().expect("listen addr");
+        assert!(is_local_host("192.168.1.10:4468", &lan));
+        assert!(!is_local_host("gateway.local:4468", &lan));
+    }
+}
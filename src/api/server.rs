@@ -1,52 +1,187 @@
 use super::{
-    AddGatewayReq, AddGatewayRes, PubkeyReq, PubkeyRes, RegionReq, RegionRes, RouterReq, RouterRes,
+    rest, AddGatewayReq, AddGatewayRes, ClientsReq, ClientsRes, EventV1, EventsReq,
+    ForwarderClientV1, JoinFilterReq, JoinFilterRes, LocalExt, LocalExtServer, PubkeyReq,
+    PubkeyRes, RegionReq, RegionRes, RouterEndpointsReq, RouterEndpointsRes, RouterReq, RouterRes,
 };
-use crate::{packet_router, region_watcher, Error, Keypair, PublicKey, Result, Settings};
-use futures::TryFutureExt;
+use crate::{
+    events, gateway,
+    packet_router::{self, RouterStatus},
+    region_watcher,
+    settings::ApiSocketSettings,
+    Error, Keypair, PublicKey, RegionParams, Result, Settings,
+};
+use futures::{Stream, TryFutureExt};
 use helium_crypto::Sign;
 use helium_proto::services::local::{Api, Server};
 use helium_proto::{BlockchainTxn, BlockchainTxnAddGatewayV1, Message, Txn};
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    fmt, fs, io,
+    net::SocketAddr,
+    os::unix::fs::{DirBuilderExt, PermissionsExt},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{net::UnixListener, sync::broadcast::error::RecvError};
 use tonic::{self, transport::Server as TransportServer, Request, Response, Status};
-use tracing::info;
+use tracing::{info, warn};
+
+/// Time to wait before accepting again after a failed accept, so persistent
+/// errors like running out of file descriptors do not spin
+const ACCEPT_RETRY_WAIT: Duration = Duration::from_millis(100);
 
 pub type ApiResult<T> = std::result::Result<Response<T>, Status>;
+pub type EventStream = Pin<Box<dyn Stream<Item = std::result::Result<EventV1, Status>> + Send>>;
+
+/// Address the local api is served on
+#[derive(Debug, Clone)]
+enum ListenOn {
+    Tcp(SocketAddr),
+    Unix(PathBuf, ApiSocketSettings),
+}
+
+impl fmt::Display for ListenOn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => addr.fmt(f),
+            Self::Unix(path, _) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
 
 pub struct LocalServer {
     region_watch: region_watcher::MessageReceiver,
     packet_router: packet_router::MessageSender,
+    gateway: gateway::MessageSender,
     keypair: Arc<Keypair>,
     onboarding_key: PublicKey,
-    listen_addr: SocketAddr,
+    listen: ListenOn,
+    http_listen_addr: Option<SocketAddr>,
+    shutdown: triggered::Listener,
 }
 
 impl LocalServer {
     pub fn new(
         region_watch: region_watcher::MessageReceiver,
         packet_router: packet_router::MessageSender,
+        gateway: gateway::MessageSender,
         settings: &Settings,
+        shutdown: &triggered::Listener,
     ) -> Result<Self> {
         Ok(Self {
             keypair: settings.keypair.clone(),
             onboarding_key: settings.onboarding_key(),
-            listen_addr: (&settings.api).try_into()?,
+            listen: match settings.api.unix_path() {
+                Some(path) => ListenOn::Unix(path, settings.api_socket.clone()),
+                None => ListenOn::Tcp((&settings.api).try_into()?),
+            },
+            http_listen_addr: settings
+                .http_api
+                .as_ref()
+                .map(SocketAddr::try_from)
+                .transpose()?,
             region_watch,
             packet_router,
+            gateway,
+            shutdown: shutdown.clone(),
         })
     }
 
     pub async fn run(self, shutdown: &triggered::Listener) -> Result {
-        let listen_addr = self.listen_addr;
-        tracing::Span::current().record("listen", &listen_addr.to_string());
-        info!(listen = %listen_addr, "starting");
-        TransportServer::builder()
-            .add_service(Server::new(self))
-            .serve_with_shutdown(listen_addr, shutdown.clone())
-            .map_err(Error::from)
-            .await
+        let listen = self.listen.clone();
+        tracing::Span::current().record("listen", &listen.to_string());
+        info!(%listen, "starting");
+        let http_listen_addr = self.http_listen_addr;
+        let api = Arc::new(self);
+        let router = TransportServer::builder()
+            .add_service(Server::from_arc(api.clone()))
+            .add_service(LocalExtServer::from_arc(api.clone()));
+        let grpc = async move {
+            let served = match listen {
+                ListenOn::Tcp(listen_addr) => {
+                    router
+                        .serve_with_shutdown(listen_addr, shutdown.clone())
+                        .await
+                }
+                ListenOn::Unix(path, socket) => {
+                    let (listener, _socket_file) = bind_socket(&path, &socket)?;
+                    let incoming = futures::stream::unfold(listener, |listener| async move {
+                        loop {
+                            match listener.accept().await {
+                                Ok((stream, _)) => {
+                                    return Some((Ok::<_, io::Error>(stream), listener))
+                                }
+                                Err(err) => {
+                                    warn!(%err, "failed to accept api connection");
+                                    tokio::time::sleep(ACCEPT_RETRY_WAIT).await;
+                                }
+                            }
+                        }
+                    });
+                    router
+                        .serve_with_incoming_shutdown(incoming, shutdown.clone())
+                        .await
+                }
+            };
+            served.map_err(Error::from)
+        };
+        match http_listen_addr {
+            Some(http_listen_addr) => {
+                tokio::try_join!(grpc, rest::run(api, http_listen_addr, shutdown)).map(|_| ())
+            }
+            None => grpc.await,
+        }
+    }
+
+    pub(super) fn region_params(&self) -> RegionParams {
+        region_watcher::current_value(&self.region_watch)
+    }
+
+    pub(super) async fn router_status(&self) -> Result<RouterStatus> {
+        self.packet_router.status().await
+    }
+}
+
+/// Removes the api socket file when the server stops
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        _ = fs::remove_file(&self.0);
     }
 }
 
+/// Binds a unix domain socket at the given path, replacing a stale socket file
+/// from a previous run. The socket is bound in a private directory and only
+/// moved into place once the configured permissions and ownership are applied
+/// so it can not be connected to with the default permissions.
+fn bind_socket(path: &Path, settings: &ApiSocketSettings) -> Result<(UnixListener, SocketFile)> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::custom(format!("invalid api socket {}", path.display())))?;
+    let private_dir = path.with_file_name(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    _ = fs::remove_dir_all(&private_dir);
+    fs::DirBuilder::new().mode(0o700).create(&private_dir)?;
+    let bind = || -> Result<UnixListener> {
+        let private_path = private_dir.join(file_name);
+        let listener = UnixListener::bind(&private_path)?;
+        fs::set_permissions(&private_path, fs::Permissions::from_mode(settings.mode))?;
+        if settings.uid.is_some() || settings.gid.is_some() {
+            std::os::unix::fs::chown(&private_path, settings.uid, settings.gid)?;
+        }
+        fs::rename(&private_path, path)?;
+        Ok(listener)
+    };
+    let listener = bind();
+    _ = fs::remove_dir_all(&private_dir);
+    Ok((listener?, SocketFile(path.to_path_buf())))
+}
+
 #[tonic::async_trait]
 impl Api for LocalServer {
     async fn pubkey(&self, _request: Request<PubkeyReq>) -> ApiResult<PubkeyRes> {
@@ -107,3 +242,107 @@ impl Api for LocalServer {
         Ok(Response::new(AddGatewayRes { add_gateway_txn }))
     }
 }
+
+#[tonic::async_trait]
+impl LocalExt for LocalServer {
+    async fn clients(&self, _request: Request<ClientsReq>) -> ApiResult<ClientsRes> {
+        let clients = self
+            .gateway
+            .clients()
+            .map_err(|_err| Status::internal("Failed to get packet forwarder clients"))
+            .await?;
+        Ok(Response::new(ClientsRes {
+            clients: clients.into_iter().map(ForwarderClientV1::from).collect(),
+        }))
+    }
+
+    async fn join_filter(&self, request: Request<JoinFilterReq>) -> ApiResult<JoinFilterRes> {
+        let reload = request.into_inner().reload;
+        let status = self
+            .gateway
+            .join_filter(reload)
+            .map_err(|err| match err {
+                Error::Service(_) => Status::internal("Failed to get join filter"),
+                err => Status::failed_precondition(format!("Failed to reload join rules: {err}")),
+            })
+            .await?;
+        Ok(Response::new(status.into()))
+    }
+
+    async fn router_endpoints(
+        &self,
+        _request: Request<RouterEndpointsReq>,
+    ) -> ApiResult<RouterEndpointsRes> {
+        let router_status = self
+            .packet_router
+            .status()
+            .map_err(|_err| Status::internal("Failed to get router status"))
+            .await?;
+        Ok(Response::new(RouterEndpointsRes::from(&router_status)))
+    }
+
+    type eventsStream = EventStream;
+
+    async fn events(&self, _request: Request<EventsReq>) -> ApiResult<Self::eventsStream> {
+        // The events sender is never dropped, so streams are ended on shutdown
+        // to not hold up the graceful shutdown of the server
+        let state = (events::subscribe(), self.shutdown.clone());
+        let events = futures::stream::unfold(state, |(mut events, shutdown)| async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown.clone() => return None,
+                    event = events.recv() => match event {
+                        Ok(event) => return Some((Ok(EventV1::from(event)), (events, shutdown))),
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!(skipped, "events subscriber lagging");
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::UnixStream;
+
+    #[tokio::test]
+    async fn test_bind_socket() {
+        let dir = std::env::temp_dir().join(format!("gateway-rs-api-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("socket dir");
+        let path = dir.join("api.sock");
+        // A stale socket file from a previous run is replaced
+        fs::write(&path, b"").expect("stale socket");
+
+        let settings = ApiSocketSettings {
+            mode: 0o600,
+            uid: None,
+            gid: None,
+        };
+        let (listener, socket_file) = bind_socket(&path, &settings).expect("bind socket");
+        let mode = fs::metadata(&path)
+            .expect("socket metadata")
+            .permissions()
+            .mode();
+        assert_eq!(0o600, mode & 0o777);
+        assert_eq!(
+            vec![path.clone()],
+            fs::read_dir(&dir)
+                .expect("socket dir")
+                .map(|entry| entry.expect("dir entry").path())
+                .collect::<Vec<_>>()
+        );
+
+        let (_client, accepted) = tokio::join!(UnixStream::connect(&path), listener.accept());
+        accepted.expect("accepted connection");
+
+        drop(socket_file);
+        assert!(!path.exists());
+        _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,8 +1,9 @@
 //! This module provides proof-of-coverage (PoC) beaconing support.
 use crate::{
+    events,
     gateway::{self, BeaconResp},
     message_cache::MessageCache,
-    region_watcher,
+    metrics, region_watcher,
     service::{entropy::EntropyService, poc::PocIotService, Reconnect},
     settings::Settings,
     sync, Base64, DecodeError, PacketUp, PublicKey, RegionParams, Result,
@@ -203,7 +204,15 @@ impl Beaconer {
         let (powe, tmst) = self
             .transmit
             .transmit_beacon(beacon.clone())
-            .inspect_err(|err| warn!(%err, "transmit beacon"))
+            .inspect_err(|err| {
+                metrics::beacon("failed");
+                events::beacon(&beacon_id, "failed", None);
+                warn!(%err, "transmit beacon")
+            })
+            .inspect_ok(|resp| {
+                metrics::beacon("sent");
+                events::beacon(&beacon_id, "sent", Some(resp.powe));
+            })
             .map_ok(|BeaconResp { powe, tmst }| (powe, tmst))
             .await?;
 
@@ -275,8 +284,16 @@ impl Beaconer {
 
         let _ = Self::mk_witness_report(packet, beacon_data, self.service.gateway_key().clone())
             .and_then(|report| self.service.submit_witness(report))
-            .inspect_err(|err| warn!(beacon_id, %err, "submit poc witness report"))
-            .inspect_ok(|_| info!(beacon_id, "poc witness report submitted"))
+            .inspect_err(|err| {
+                metrics::witness("failed");
+                events::witness(&beacon_id, "failed");
+                warn!(beacon_id, %err, "submit poc witness report")
+            })
+            .inspect_ok(|_| {
+                metrics::witness("submitted");
+                events::witness(&beacon_id, "submitted");
+                info!(beacon_id, "poc witness report submitted")
+            })
             .await;
     }
 
@@ -376,8 +393,8 @@ trait BeaconData {
 
 impl BeaconData for PacketUp {
     fn beacon_data(&self) -> Option<Vec<u8>> {
-        match PacketUp::parse_frame(lorawan::Direction::Uplink, self.payload()) {
-            Ok(lorawan::PHYPayloadFrame::Proprietary(payload)) => Some(payload.into()),
+        match self.frame().map(|frame| &frame.payload) {
+            Some(lorawan::PHYPayloadFrame::Proprietary(payload)) => Some(payload.to_vec()),
             _ => None,
         }
     }
@@ -31,7 +31,15 @@ impl Cmd {
 }
 
 fn print_txn(mode: &StakingMode, txn: BlockchainTxnAddGatewayV1) -> Result {
-    let table = json!({
+    print_json(&txn_json(mode, txn)?)
+}
+
+/// Returns the given add gateway transaction with its addresses as json
+pub(crate) fn txn_json(
+    mode: &StakingMode,
+    txn: BlockchainTxnAddGatewayV1,
+) -> Result<serde_json::Value> {
+    Ok(json!({
         "mode": mode.to_string(),
         "address": PublicKey::from_bytes(&txn.gateway)?.to_string(),
         "payer": PublicKey::from_bytes(&txn.payer).and_then(solana_pubkey)?,
@@ -39,11 +47,11 @@ fn print_txn(mode: &StakingMode, txn: BlockchainTxnAddGatewayV1) -> Result {
         "txn": BlockchainTxn {
             txn: Some(Txn::AddGateway(txn))
         }.encode_to_vec().to_b64()
-    });
-    print_json(&table)
+    }))
 }
 
-fn parse_pubkey(str: &str) -> Result<PublicKey> {
+/// Parses a helium or solana address
+pub(crate) fn parse_pubkey(str: &str) -> Result<PublicKey> {
     use helium_crypto::{ed25519, ReadFrom};
     use std::{io::Cursor, str::FromStr};
 
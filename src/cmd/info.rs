@@ -18,6 +18,7 @@ pub enum InfoKey {
     Name,
     Region,
     Router,
+    Clients,
 }
 
 /// Info command. Retrieve all or a subset of information from the running
@@ -49,6 +50,7 @@ impl fmt::Display for InfoKey {
             Self::Name => "name",
             Self::Region => "region",
             Self::Router => "router",
+            Self::Clients => "clients",
         };
         f.write_str(s)
     }
@@ -90,6 +92,10 @@ impl InfoKey {
                 let router = client.router().await?;
                 json!(router)
             }
+            Self::Clients => {
+                let clients = client.clients().await?;
+                json!(clients)
+            }
         };
         Ok(v)
     }
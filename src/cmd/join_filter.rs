@@ -0,0 +1,18 @@
+use crate::{api::LocalClient, cmd::*, settings::Settings, Result};
+
+/// Show the join filter rules and the number of join requests each rule
+/// matched, optionally reloading the join rules file first.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    /// Reload the join rules file before showing the rules
+    #[arg(long)]
+    pub reload: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let mut client = LocalClient::new(&settings.api).await?;
+        let status = client.join_filter(self.reload).await?;
+        print_json(&status)
+    }
+}
@@ -1,6 +1,8 @@
 pub mod add;
 pub mod info;
+pub mod join_filter;
 pub mod key;
+pub mod monitor;
 pub mod server;
 
 use crate::Result;
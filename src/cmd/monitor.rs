@@ -0,0 +1,150 @@
+use crate::{
+    api::LocalClient,
+    events::{Event, EventKind},
+    packet,
+    settings::{DevAddrRange, Settings},
+    Result,
+};
+use futures::TryStreamExt;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq)]
+pub enum Kind {
+    Uplink,
+    Ignored,
+    Downlink,
+    Beacon,
+    Witness,
+}
+
+impl Kind {
+    fn matches(&self, kind: &EventKind) -> bool {
+        matches!(
+            (self, kind),
+            (Self::Uplink, EventKind::Uplink(_))
+                | (Self::Ignored, EventKind::Ignored(_))
+                | (Self::Downlink, EventKind::Downlink(_))
+                | (Self::Beacon, EventKind::Beacon(_))
+                | (Self::Witness, EventKind::Witness(_))
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum, PartialEq, Eq)]
+pub enum Format {
+    /// One aligned line per event
+    #[default]
+    Table,
+    /// One json object per line
+    Json,
+}
+
+/// Monitor live gateway traffic. Prints uplinks, ignored packets, downlinks,
+/// beacons and witness reports as they happen until interrupted.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    /// Event kinds to show. All kinds are shown if not given
+    #[arg(long = "kind", value_enum)]
+    pub kinds: Vec<Kind>,
+    /// Only show uplinks with a DevAddr in the given range, for example
+    /// "48000000-48FFFFFF" or a single DevAddr
+    #[arg(long = "dev-addr")]
+    pub dev_addrs: Vec<DevAddrRange>,
+    /// Only show uplinks of the given frame type, for example "join_request"
+    /// or "confirmed_up"
+    #[arg(long)]
+    pub mtype: Vec<String>,
+    /// Output format
+    #[arg(long, value_enum, default_value_t)]
+    pub format: Format,
+}
+
+impl Cmd {
+    pub async fn run(&self, shutdown: &triggered::Listener, settings: Settings) -> Result {
+        let mut client = LocalClient::new(&settings.api).await?;
+        let mut events = client.events().await?;
+        if self.format == Format::Table {
+            println!("{:<24} {:<8} {:<16} DETAILS", "TIME", "KIND", "MAC");
+        }
+        loop {
+            let event = tokio::select! {
+                _ = shutdown.clone() => return Ok(()),
+                event = events.try_next() => match event? {
+                    Some(event) => event,
+                    None => return Ok(()),
+                },
+            };
+            if !self.matches(&event) {
+                continue;
+            }
+            match self.format {
+                Format::Table => println!("{}", table_row(&event)),
+                Format::Json => println!("{}", serde_json::to_string(&event)?),
+            }
+        }
+    }
+
+    /// Whether the event passes the kind filter and, for uplinks, the DevAddr
+    /// and frame type filters
+    fn matches(&self, event: &Event) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.iter().any(|kind| kind.matches(&event.kind)) {
+            return false;
+        }
+        let EventKind::Uplink(uplink) = &event.kind else {
+            return true;
+        };
+        let dev_addr_ok = self.dev_addrs.is_empty()
+            || uplink.dev_addr.is_some_and(|dev_addr| {
+                self.dev_addrs.iter().any(|range| range.contains(dev_addr))
+            });
+        let mtype_ok = self.mtype.is_empty() || self.mtype.contains(&uplink.mtype);
+        dev_addr_ok && mtype_ok
+    }
+}
+
+fn table_row(event: &Event) -> String {
+    let time = OffsetDateTime::from_unix_timestamp_nanos(event.timestamp as i128 * 1_000_000)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_else(|| event.timestamp.to_string());
+    let (mac, details) = match &event.kind {
+        EventKind::Uplink(uplink) => {
+            let mut details = if uplink.mtype.is_empty() {
+                "undecoded".to_string()
+            } else {
+                uplink.mtype.clone()
+            };
+            if let (Some(dev_addr), Some(fcnt)) = (uplink.dev_addr, uplink.fcnt) {
+                details.push_str(&format!(" dev_addr={dev_addr} fcnt={fcnt}"));
+            }
+            details.push_str(&format!(
+                " {:.2} MHz {} rssi={} snr={:.1}",
+                packet::to_mhz(uplink.frequency),
+                uplink.datarate,
+                uplink.rssi,
+                uplink.snr
+            ));
+            (uplink.mac.as_str(), details)
+        }
+        EventKind::Ignored(ignored) => (ignored.mac.as_str(), ignored.reason.clone()),
+        EventKind::Downlink(downlink) => (
+            downlink.mac.as_str(),
+            format!(
+                "{} {} {:.2} MHz power={}",
+                downlink.window,
+                downlink.outcome,
+                packet::to_mhz(downlink.frequency),
+                downlink.tx_power
+            ),
+        ),
+        EventKind::Beacon(beacon) => {
+            let mut details = format!("{} {}", beacon.beacon_id, beacon.status);
+            if let Some(tx_power) = beacon.tx_power {
+                details.push_str(&format!(" power={tx_power}"));
+            }
+            ("", details)
+        }
+        EventKind::Witness(witness) => ("", format!("{} {}", witness.beacon_id, witness.status)),
+    };
+    format!("{time:<24} {:<8} {mac:<16} {details}", event.kind.as_str())
+}
@@ -21,6 +21,12 @@ pub enum Error {
     Service(#[from] ServiceError),
     #[error("semtech udp error: {0}")]
     Semtech(#[from] Box<semtech_udp::server_runtime::Error>),
+    #[error("semtech udp client error: {0}")]
+    SemtechClient(#[from] Box<semtech_udp::client_runtime::Error>),
+    #[error("http error: {0}")]
+    Http(#[from] hyper::Error),
+    #[error("websocket error: {0}")]
+    WebSocket(#[from] Box<tokio_tungstenite::tungstenite::Error>),
     #[error("{0}")]
     Beacon(#[from] beacon::Error),
     #[error("gateway error: {0}")]
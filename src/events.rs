@@ -0,0 +1,221 @@
+//! Live gateway events for the local api.
+//!
+//! Subsystems publish structured events for received and ignored uplinks,
+//! downlink dispatch results, beacon transmissions and witness reports next to
+//! their metrics. Events are broadcast to all subscribers and dropped when
+//! there are none, so publishing never blocks.
+use crate::{packet, PacketUp};
+use lorawan::{DevAddr, MType, PHYPayloadFrame};
+use semtech_udp::{pull_resp, MacAddress};
+use serde::Serialize;
+use std::{
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast;
+
+/// Number of events kept for subscribers that fall behind
+const EVENTS_MAX: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    /// Unix time in milliseconds of the event
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EventKind {
+    Uplink(UplinkEvent),
+    Ignored(IgnoredEvent),
+    Downlink(DownlinkEvent),
+    Beacon(BeaconEvent),
+    Witness(WitnessEvent),
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Uplink(_) => "uplink",
+            Self::Ignored(_) => "ignored",
+            Self::Downlink(_) => "downlink",
+            Self::Beacon(_) => "beacon",
+            Self::Witness(_) => "witness",
+        }
+    }
+}
+
+/// A received uplink
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UplinkEvent {
+    /// Gateway id (MAC) of the packet forwarder that received the uplink
+    pub mac: String,
+    /// Concentrator timestamp in microseconds
+    pub tmst: u32,
+    /// Frequency in Hz
+    pub frequency: u32,
+    pub datarate: String,
+    pub rssi: i32,
+    pub snr: f32,
+    /// Frame type, empty if the frame could not be decoded
+    pub mtype: String,
+    /// Device address of data frames
+    pub dev_addr: Option<DevAddr>,
+    /// Frame counter of data frames
+    pub fcnt: Option<u16>,
+}
+
+/// A received packet that was not forwarded, with the reason why
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IgnoredEvent {
+    pub mac: String,
+    pub reason: String,
+}
+
+/// The result of a downlink dispatch
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DownlinkEvent {
+    pub mac: String,
+    /// Receive window of the downlink (rx1, rx2 or upstream)
+    pub window: String,
+    /// Dispatch outcome as counted in the downlink metrics
+    pub outcome: String,
+    /// Frequency in Hz
+    pub frequency: u32,
+    /// Requested transmit power in dBm
+    pub tx_power: u32,
+}
+
+/// A beacon transmission
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BeaconEvent {
+    pub beacon_id: String,
+    /// Transmit status (sent or failed)
+    pub status: String,
+    /// Transmit power used in dBm if sent
+    pub tx_power: Option<i32>,
+}
+
+/// A witness report for a received beacon
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WitnessEvent {
+    pub beacon_id: String,
+    /// Report status (submitted or failed)
+    pub status: String,
+}
+
+fn sender() -> &'static broadcast::Sender<Event> {
+    static EVENTS: OnceLock<broadcast::Sender<Event>> = OnceLock::new();
+    EVENTS.get_or_init(|| broadcast::channel(EVENTS_MAX).0)
+}
+
+/// Subscribes to events published from now on
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    sender().subscribe()
+}
+
+fn publish(kind: EventKind) {
+    // Nobody is listening most of the time
+    if sender().receiver_count() == 0 {
+        return;
+    }
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default();
+    _ = sender().send(Event { timestamp, kind });
+}
+
+pub fn uplink(packet: &PacketUp, mac: MacAddress) {
+    // Avoid decoding the frame when nobody is listening
+    if sender().receiver_count() == 0 {
+        return;
+    }
+    let frame = packet.frame();
+    let data = frame.and_then(|frame| match &frame.payload {
+        PHYPayloadFrame::MACPayload(mac_payload) => Some(mac_payload),
+        _ => None,
+    });
+    publish(EventKind::Uplink(UplinkEvent {
+        mac: mac.to_string(),
+        tmst: packet.timestamp as u32,
+        frequency: packet.frequency,
+        datarate: packet.datarate().as_str_name().to_string(),
+        rssi: packet.rssi,
+        snr: packet.snr,
+        mtype: frame
+            .map(|frame| mtype_str(frame.mtype()))
+            .unwrap_or_default()
+            .to_string(),
+        dev_addr: data.map(|mac_payload| mac_payload.dev_addr()),
+        fcnt: data.map(|mac_payload| mac_payload.fhdr.fcnt),
+    }))
+}
+
+pub fn ignored(mac: MacAddress, reason: &str) {
+    publish(EventKind::Ignored(IgnoredEvent {
+        mac: mac.to_string(),
+        reason: reason.to_string(),
+    }))
+}
+
+pub fn downlink(mac: MacAddress, window: &str, outcome: &str, txpk: &pull_resp::TxPk) {
+    publish(EventKind::Downlink(DownlinkEvent {
+        mac: mac.to_string(),
+        window: window.to_string(),
+        outcome: outcome.to_string(),
+        frequency: packet::to_hz(txpk.freq) as u32,
+        tx_power: txpk.powe as u32,
+    }))
+}
+
+pub fn beacon(beacon_id: &str, status: &str, tx_power: Option<i32>) {
+    publish(EventKind::Beacon(BeaconEvent {
+        beacon_id: beacon_id.to_string(),
+        status: status.to_string(),
+        tx_power,
+    }))
+}
+
+pub fn witness(beacon_id: &str, status: &str) {
+    publish(EventKind::Witness(WitnessEvent {
+        beacon_id: beacon_id.to_string(),
+        status: status.to_string(),
+    }))
+}
+
+fn mtype_str(mtype: MType) -> &'static str {
+    match mtype {
+        MType::JoinRequest => "join_request",
+        MType::JoinAccept => "join_accept",
+        MType::UnconfirmedUp => "unconfirmed_up",
+        MType::UnconfirmedDown => "unconfirmed_down",
+        MType::ConfirmedUp => "confirmed_up",
+        MType::ConfirmedDown => "confirmed_down",
+        MType::RejoinRequest => "rejoin_request",
+        MType::Proprietary => "proprietary",
+        MType::Invalid(_) => "invalid",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_publish() {
+        let mac = MacAddress::new(0xAA, 0x55, 0x5A, 0, 0, 0, 0, 1);
+        // Events without subscribers are dropped
+        witness("beacon", "submitted");
+        let mut events = subscribe();
+        ignored(mac, "no_region");
+        let event = events.try_recv().expect("event");
+        assert_eq!("ignored", event.kind.as_str());
+        let json = serde_json::to_value(&event).expect("json");
+        assert_eq!("ignored", json["kind"]);
+        assert_eq!("no_region", json["reason"]);
+        assert!(events.try_recv().is_err());
+    }
+}
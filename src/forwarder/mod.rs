@@ -0,0 +1,117 @@
+//! Packet forwarder transports.
+//!
+//! A transport accepts connections from packet forwarders and turns their
+//! traffic into gateway events, and gateway downlinks into packet forwarder
+//! transmissions. The gateway only uses the [`Forwarder`] trait so the
+//! transport can be selected in settings.
+use crate::{
+    region_watcher,
+    settings::{ForwarderProtocol, Settings},
+    Result,
+};
+use semtech_udp::{pull_resp::TxPk, server_runtime::UdpRuntime, MacAddress};
+use std::time::Duration;
+use tonic::async_trait;
+
+pub mod semtech;
+pub mod station;
+
+pub use semtech_udp::server_runtime::Event;
+
+/// The longest receive delay a downlink window can have after an uplink. The
+/// LoRaWAN RXTimingSetup command allows up to 15 seconds for rx1 and rx2 is
+/// one second later.
+pub const MAX_RX_DELAY: Duration = Duration::from_secs(16);
+
+pub type TxResult = std::result::Result<Option<u32>, TxError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TxError {
+    #[error("too early")]
+    TooEarly,
+    #[error("too late")]
+    TooLate,
+    #[error("transmitted with adjusted power")]
+    AdjustedTransmitPower(Option<i32>, Option<u32>),
+    #[error("transmit ack timeout")]
+    Timeout,
+    #[error("no packet to transmit")]
+    NoPacket,
+    #[error("packet forwarder disconnected")]
+    Disconnected,
+    #[error("unsupported transmit: {0}")]
+    Unsupported(String),
+    #[error("semtech udp error: {0}")]
+    Semtech(Box<semtech_udp::server_runtime::Error>),
+    #[error("transmit refused: {0}")]
+    Regulatory(#[from] crate::tx_budget::RegulatoryError),
+}
+
+#[async_trait]
+pub trait Forwarder: Send {
+    /// Returns the next event from the connected packet forwarders.
+    async fn recv(&mut self) -> Event;
+
+    /// Prepares a downlink for transmission through the packet forwarder with
+    /// the given mac.
+    fn prepare_downlink(&self, mac: MacAddress) -> Downlink;
+}
+
+#[async_trait]
+pub trait DownlinkDispatch: Send {
+    async fn dispatch(&mut self, packet: TxPk, timeout: Option<Duration>) -> TxResult;
+}
+
+/// A downlink that is prepared for a packet forwarder and can be dispatched
+/// from a separate task.
+pub struct Downlink {
+    packet: Option<TxPk>,
+    dispatcher: Box<dyn DownlinkDispatch>,
+}
+
+impl Downlink {
+    pub fn new<D: DownlinkDispatch + 'static>(dispatcher: D) -> Self {
+        Self {
+            packet: None,
+            dispatcher: Box::new(dispatcher),
+        }
+    }
+
+    pub fn set_packet(&mut self, packet: TxPk) {
+        self.packet = Some(packet);
+    }
+
+    /// Transmits the downlink packet and waits up to the given timeout for
+    /// the packet forwarder to acknowledge it. Returns the concentrator
+    /// timestamp of the transmission if known.
+    pub async fn dispatch(mut self, timeout: Option<Duration>) -> TxResult {
+        let packet = self.packet.take().ok_or(TxError::NoPacket)?;
+        self.dispatcher.dispatch(packet, timeout).await
+    }
+}
+
+/// Returns the receive delay of a downlink window scheduled at the given
+/// concentrator timestamp if it is a response to the uplink received at the
+/// given timestamp, i.e. when it is scheduled a whole number of seconds after
+/// the uplink on the same concentrator clock.
+pub fn rx_delay(uplink_tmst: u32, window_tmst: u32) -> Option<Duration> {
+    let delay = Duration::from_micros(window_tmst.wrapping_sub(uplink_tmst) as u64);
+    (delay > Duration::ZERO && delay <= MAX_RX_DELAY && delay.subsec_micros() == 0).then_some(delay)
+}
+
+/// Constructs the packet forwarder transport selected in the given settings,
+/// listening on the configured listen address.
+pub async fn new(
+    settings: &Settings,
+    region_watch: region_watcher::MessageReceiver,
+) -> Result<Box<dyn Forwarder>> {
+    let forwarder: Box<dyn Forwarder> = match settings.forwarder {
+        ForwarderProtocol::Semtech => {
+            Box::new(UdpRuntime::new(&settings.listen).await.map_err(Box::new)?)
+        }
+        ForwarderProtocol::Station => {
+            Box::new(station::StationRuntime::new(&settings.listen, region_watch).await?)
+        }
+    };
+    Ok(forwarder)
+}
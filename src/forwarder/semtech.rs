@@ -0,0 +1,45 @@
+//! Semtech UDP (GWMP) packet forwarder transport.
+use super::{Downlink, DownlinkDispatch, Event, Forwarder, TxError, TxResult};
+use semtech_udp::{
+    pull_resp::TxPk,
+    server_runtime::{self, Error as SemtechError, UdpRuntime},
+    tx_ack::Error as TxAckErr,
+    MacAddress,
+};
+use std::time::Duration;
+use tonic::async_trait;
+
+#[async_trait]
+impl Forwarder for UdpRuntime {
+    async fn recv(&mut self) -> Event {
+        UdpRuntime::recv(self).await
+    }
+
+    fn prepare_downlink(&self, mac: MacAddress) -> Downlink {
+        Downlink::new(SemtechDownlink(Some(self.prepare_empty_downlink(mac))))
+    }
+}
+
+struct SemtechDownlink(Option<server_runtime::Downlink>);
+
+#[async_trait]
+impl DownlinkDispatch for SemtechDownlink {
+    async fn dispatch(&mut self, packet: TxPk, timeout: Option<Duration>) -> TxResult {
+        let mut downlink = self.0.take().ok_or(TxError::NoPacket)?;
+        downlink.set_packet(packet);
+        downlink.dispatch(timeout).await.map_err(TxError::from)
+    }
+}
+
+impl From<SemtechError> for TxError {
+    fn from(value: SemtechError) -> Self {
+        match value {
+            SemtechError::Ack(TxAckErr::TooEarly) => Self::TooEarly,
+            SemtechError::Ack(TxAckErr::TooLate) => Self::TooLate,
+            SemtechError::Ack(TxAckErr::AdjustedTransmitPower(power_used, tmst)) => {
+                Self::AdjustedTransmitPower(power_used, tmst)
+            }
+            other => Self::Semtech(Box::new(other)),
+        }
+    }
+}
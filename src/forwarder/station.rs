@@ -0,0 +1,775 @@
+//! LoRa Basics Station (LNS protocol) packet forwarder transport.
+//!
+//! A station first connects to the `/router-info` endpoint to discover the
+//! traffic endpoint for its router id. It then connects to that
+//! `/traffic/<id>` endpoint, announces itself with a `version` message and is
+//! configured with a `router_config` message derived from the current region
+//! parameters. Uplinks arrive as `updf`, `jreq` and `propdf` messages and are
+//! converted to Semtech `RxPk` packets. Downlinks are sent as `dnmsg` messages
+//! and acknowledged by the station with a `dntxed` message.
+use super::{rx_delay, Downlink, DownlinkDispatch, Event, Forwarder, TxError, TxResult};
+use crate::{region_watcher, Error, Region, RegionParams, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{SinkExt, StreamExt};
+use semtech_udp::{pull_resp::TxPk, push_data::RxPk, MacAddress};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::server::{Request, Response},
+        Message,
+    },
+    WebSocketStream,
+};
+use tonic::async_trait;
+use tracing::{debug, info, warn};
+
+const EVENT_CHANNEL_SIZE: usize = 20;
+const DOWNLINK_CHANNEL_SIZE: usize = 10;
+/// Time to wait before accepting again after a failed accept
+const ACCEPT_RETRY_WAIT: Duration = Duration::from_millis(100);
+/// Number of recent uplinks per station to keep the timing context for
+const UPLINK_CONTEXT_MAX: usize = 50;
+/// Seconds between the unix and the GPS epoch (1980-01-06)
+const GPS_EPOCH_OFFSET: u64 = 315_964_800;
+/// Leap seconds GPS time is ahead of UTC
+const GPS_LEAP_SECONDS: u64 = 18;
+/// Widest span of channels a single SX1301 radio can serve, keeping the
+/// intermediate frequency of each channel within +/- 400 kHz
+const RADIO_CHANNEL_SPAN: u64 = 800_000;
+const MULTI_SF_CHANNELS: usize = 8;
+
+type Clients = Arc<Mutex<HashMap<MacAddress, mpsc::Sender<StationDownlink>>>>;
+type WebSocket = WebSocketStream<TcpStream>;
+
+#[derive(Debug)]
+struct StationDownlink {
+    packet: TxPk,
+    ack: oneshot::Sender<TxResult>,
+}
+
+pub struct StationRuntime {
+    events: mpsc::Receiver<Event>,
+    clients: Clients,
+}
+
+impl StationRuntime {
+    pub async fn new(listen: &str, region_watch: region_watcher::MessageReceiver) -> Result<Self> {
+        let listener = TcpListener::bind(listen).await?;
+        let (events_tx, events) = mpsc::channel(EVENT_CHANNEL_SIZE);
+        let clients = Clients::default();
+        tokio::spawn(run_listener(
+            listener,
+            events_tx,
+            clients.clone(),
+            region_watch,
+        ));
+        Ok(Self { events, clients })
+    }
+}
+
+#[async_trait]
+impl Forwarder for StationRuntime {
+    async fn recv(&mut self) -> Event {
+        match self.events.recv().await {
+            Some(event) => event,
+            // The listener only stops when this runtime is dropped
+            None => futures::future::pending().await,
+        }
+    }
+
+    fn prepare_downlink(&self, mac: MacAddress) -> Downlink {
+        let client = self
+            .clients
+            .lock()
+            .expect("station clients lock")
+            .get(&mac)
+            .cloned();
+        Downlink::new(StationDownlinkSender(client))
+    }
+}
+
+struct StationDownlinkSender(Option<mpsc::Sender<StationDownlink>>);
+
+#[async_trait]
+impl DownlinkDispatch for StationDownlinkSender {
+    async fn dispatch(&mut self, packet: TxPk, timeout: Option<Duration>) -> TxResult {
+        let client = self.0.take().ok_or(TxError::Disconnected)?;
+        let (ack, ack_rx) = oneshot::channel();
+        client
+            .send(StationDownlink { packet, ack })
+            .await
+            .map_err(|_| TxError::Disconnected)?;
+        let ack_result = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, ack_rx)
+                .await
+                .map_err(|_| TxError::Timeout)?,
+            None => ack_rx.await,
+        };
+        ack_result.map_err(|_| TxError::Disconnected)?
+    }
+}
+
+async fn run_listener(
+    listener: TcpListener,
+    events: mpsc::Sender<Event>,
+    clients: Clients,
+    region_watch: region_watcher::MessageReceiver,
+) {
+    while !events.is_closed() {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(%err, "failed to accept station connection");
+                tokio::time::sleep(ACCEPT_RETRY_WAIT).await;
+                continue;
+            }
+        };
+        let connection = Connection {
+            addr,
+            events: events.clone(),
+            clients: clients.clone(),
+            region_watch: region_watch.clone(),
+        };
+        tokio::spawn(async move {
+            if let Err(err) = connection.run(stream).await {
+                warn!(%addr, %err, "station connection error");
+            }
+        });
+    }
+}
+
+struct Connection {
+    addr: SocketAddr,
+    events: mpsc::Sender<Event>,
+    clients: Clients,
+    region_watch: region_watcher::MessageReceiver,
+}
+
+impl Connection {
+    async fn run(self, stream: TcpStream) -> Result {
+        let local_addr = stream.local_addr()?;
+        let mut path = String::new();
+        let ws = tokio_tungstenite::accept_hdr_async(stream, |req: &Request, resp: Response| {
+            path = req.uri().path().to_string();
+            Ok(resp)
+        })
+        .await
+        .map_err(Box::new)?;
+
+        if path == "/router-info" {
+            return router_info(ws, local_addr).await;
+        }
+        match path.strip_prefix("/traffic/").and_then(parse_eui) {
+            Some(eui) => self.traffic(ws, eui).await,
+            None => Err(Error::custom(format!("unknown station endpoint {path}"))),
+        }
+    }
+
+    async fn traffic(self, mut ws: WebSocket, eui: u64) -> Result {
+        let mac = MacAddress::from(eui.to_be_bytes());
+        let (downlink_tx, mut downlinks) = mpsc::channel(DOWNLINK_CHANNEL_SIZE);
+        self.clients
+            .lock()
+            .expect("station clients lock")
+            .insert(mac, downlink_tx.clone());
+        _ = self.events.send(Event::NewClient((mac, self.addr))).await;
+
+        let mut region_watch = self.region_watch.clone();
+        let mut session = Session::new(mac);
+        let result = loop {
+            tokio::select! {
+                message = ws.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        let params = region_watcher::current_value(&region_watch);
+                        if let Err(err) = session
+                            .handle_message(&mut ws, &self.events, &params, &text)
+                            .await
+                        {
+                            break Err(err);
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | None => break Ok(()),
+                    Some(Ok(_)) => (),
+                    Some(Err(err)) => break Err(Box::new(err).into()),
+                },
+                downlink = downlinks.recv() => match downlink {
+                    Some(downlink) => {
+                        let params = region_watcher::current_value(&region_watch);
+                        if let Err(err) = session.send_downlink(&mut ws, &params, downlink).await {
+                            break Err(err);
+                        }
+                    }
+                    None => break Ok(()),
+                },
+                region_change = region_watch.changed() => match region_change {
+                    Ok(()) if session.announced => {
+                        let params = region_watcher::current_value(&region_watch);
+                        if let Err(err) = session.send_router_config(&mut ws, &params).await {
+                            break Err(err);
+                        }
+                    }
+                    Ok(()) => (),
+                    Err(_) => break Ok(()),
+                },
+            }
+        };
+
+        {
+            // A reconnected station may already have replaced this connection
+            let mut clients = self.clients.lock().expect("station clients lock");
+            if clients
+                .get(&mac)
+                .is_some_and(|client| client.same_channel(&downlink_tx))
+            {
+                clients.remove(&mac);
+            }
+        }
+        _ = self
+            .events
+            .send(Event::ClientDisconnected((mac, self.addr)))
+            .await;
+        result
+    }
+}
+
+async fn router_info(mut ws: WebSocket, local_addr: SocketAddr) -> Result {
+    while let Some(message) = ws.next().await {
+        let Message::Text(text) = message.map_err(Box::new)? else {
+            continue;
+        };
+        let request: Value = serde_json::from_str(&text)?;
+        let router = request.get("router").and_then(|router| match router {
+            Value::Number(number) => number.as_u64(),
+            Value::String(str) => parse_eui(str),
+            _ => None,
+        });
+        let response = match router {
+            Some(eui) => json!({
+                "router": format_eui(eui),
+                "muxs": "muxs-::0",
+                "uri": format!("ws://{local_addr}/traffic/{eui:016x}"),
+            }),
+            None => json!({
+                "router": request.get("router"),
+                "error": "invalid router id",
+            }),
+        };
+        ws.send(Message::Text(response.to_string().into()))
+            .await
+            .map_err(Box::new)?;
+        break;
+    }
+    Ok(())
+}
+
+/// Per connection state for a station
+struct Session {
+    mac: MacAddress,
+    /// Whether the station has sent its version message and expects a router
+    /// config
+    announced: bool,
+    next_diid: u64,
+    /// Timing context (tmst, xtime, rctx) of recent uplinks
+    uplinks: VecDeque<(u32, i64, i64)>,
+    /// Downlinks waiting for a dntxed message
+    pending: HashMap<u64, oneshot::Sender<TxResult>>,
+}
+
+impl Session {
+    fn new(mac: MacAddress) -> Self {
+        Self {
+            mac,
+            announced: false,
+            next_diid: 1,
+            uplinks: VecDeque::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    async fn handle_message(
+        &mut self,
+        ws: &mut WebSocket,
+        events: &mpsc::Sender<Event>,
+        params: &RegionParams,
+        text: &str,
+    ) -> Result {
+        let message: Value = serde_json::from_str(text)?;
+        let mac = self.mac;
+        match message["msgtype"].as_str().unwrap_or_default() {
+            "version" => {
+                info!(
+                    %mac,
+                    station = message["station"].as_str(),
+                    firmware = message["firmware"].as_str(),
+                    model = message["model"].as_str(),
+                    "station connected"
+                );
+                self.announced = true;
+                self.send_router_config(ws, params).await?;
+            }
+            msgtype @ ("updf" | "jreq" | "propdf") => {
+                match self.uplink_rxpk(msgtype, &message, params) {
+                    Some(rxpk) => {
+                        _ = events.send(Event::PacketReceived(rxpk, mac)).await;
+                    }
+                    None => warn!(%mac, msgtype, "ignoring invalid station uplink"),
+                }
+            }
+            "dntxed" => {
+                let ack = message["diid"]
+                    .as_u64()
+                    .and_then(|diid| self.pending.remove(&diid));
+                if let Some(ack) = ack {
+                    let tmst = message["xtime"].as_i64().map(|xtime| xtime as u32);
+                    _ = ack.send(Ok(tmst));
+                }
+            }
+            "timesync" => {
+                let response = json!({
+                    "msgtype": "timesync",
+                    "txtime": message["txtime"],
+                    "gpstime": gps_time()?,
+                });
+                send_json(ws, &response).await?;
+            }
+            msgtype => debug!(%mac, msgtype, "ignoring station message"),
+        }
+        Ok(())
+    }
+
+    async fn send_router_config(&self, ws: &mut WebSocket, params: &RegionParams) -> Result {
+        match router_config(params) {
+            Some(config) => send_json(ws, &config).await,
+            None => {
+                warn!(mac = %self.mac, region = %params.region, "no station router config for region");
+                Ok(())
+            }
+        }
+    }
+
+    async fn send_downlink(
+        &mut self,
+        ws: &mut WebSocket,
+        params: &RegionParams,
+        downlink: StationDownlink,
+    ) -> Result {
+        let diid = self.next_diid;
+        self.next_diid += 1;
+        match self.dnmsg(diid, &downlink.packet, params) {
+            Ok(dnmsg) => {
+                // Drop acks for downlinks that timed out waiting for dntxed
+                self.pending.retain(|_, ack| !ack.is_closed());
+                self.pending.insert(diid, downlink.ack);
+                send_json(ws, &dnmsg).await
+            }
+            Err(err) => {
+                _ = downlink.ack.send(Err(err));
+                Ok(())
+            }
+        }
+    }
+
+    fn uplink_rxpk(
+        &mut self,
+        msgtype: &str,
+        message: &Value,
+        params: &RegionParams,
+    ) -> Option<RxPk> {
+        let payload = match msgtype {
+            "updf" => updf_payload(message),
+            "jreq" => jreq_payload(message),
+            _ => message["FRMPayload"]
+                .as_str()
+                .and_then(|hex| hex::decode(hex).ok()),
+        }?;
+        let region = StationRegion::from_region(&params.region)?;
+        let (sf, bw) = region.datarate(message["DR"].as_u64()? as usize)?;
+        let upinfo = &message["upinfo"];
+        let xtime = upinfo["xtime"].as_i64()?;
+        let rctx = upinfo["rctx"].as_i64().unwrap_or(0);
+        // The concentrator counter is in the lower bits of xtime
+        let tmst = xtime as u32;
+        self.uplinks.push_back((tmst, xtime, rctx));
+        if self.uplinks.len() > UPLINK_CONTEXT_MAX {
+            self.uplinks.pop_front();
+        }
+
+        let rxpk = json!({
+            "tmst": tmst,
+            "chan": 0,
+            "rfch": 0,
+            "freq": crate::packet::to_mhz(message["Freq"].as_f64()?),
+            "stat": 1,
+            "modu": "LORA",
+            "datr": format!("SF{sf}BW{bw}"),
+            "codr": "4/5",
+            "rssi": upinfo["rssi"].as_f64()? as i32,
+            "lsnr": upinfo["snr"].as_f64()?,
+            "size": payload.len(),
+            "data": STANDARD.encode(&payload),
+        });
+        serde_json::from_value(rxpk).ok()
+    }
+
+    fn dnmsg(
+        &self,
+        diid: u64,
+        packet: &TxPk,
+        params: &RegionParams,
+    ) -> std::result::Result<Value, TxError> {
+        let unsupported = |msg: &str| TxError::Unsupported(msg.to_string());
+        let txpk =
+            serde_json::to_value(packet).map_err(|err| TxError::Unsupported(err.to_string()))?;
+        if txpk["ipol"].as_bool() == Some(false) {
+            return Err(unsupported("non-inverted polarity"));
+        }
+        let pdu = txpk["data"]
+            .as_str()
+            .and_then(|data| STANDARD.decode(data).ok())
+            .ok_or_else(|| unsupported("invalid data"))?;
+        let freq = txpk["freq"]
+            .as_f64()
+            .map(crate::packet::to_hz)
+            .ok_or_else(|| unsupported("invalid frequency"))?;
+        let datr = txpk["datr"].as_str().unwrap_or_default();
+        let dr = StationRegion::from_region(&params.region)
+            .and_then(|region| region.datarate_index(datr))
+            .ok_or_else(|| TxError::Unsupported(format!("datarate {datr}")))?;
+
+        let dnmsg = match txpk["tmst"].as_u64() {
+            Some(tmst) => {
+                let (delay, xtime, rctx) = self
+                    .uplinks
+                    .iter()
+                    .rev()
+                    .find_map(|(uplink_tmst, xtime, rctx)| {
+                        rx_delay(*uplink_tmst, tmst as u32).map(|delay| (delay, xtime, rctx))
+                    })
+                    .ok_or_else(|| unsupported("no matching uplink"))?;
+                json!({
+                    "msgtype": "dnmsg",
+                    "DevEui": format_eui(0),
+                    "dC": 0,
+                    "diid": diid,
+                    "pdu": hex::encode(pdu),
+                    "RxDelay": delay.as_secs(),
+                    "RX1DR": dr,
+                    "RX1Freq": freq,
+                    "priority": 0,
+                    "xtime": xtime,
+                    "rctx": rctx,
+                })
+            }
+            // Immediate transmissions are sent as class C downlinks
+            None => json!({
+                "msgtype": "dnmsg",
+                "DevEui": format_eui(0),
+                "dC": 2,
+                "diid": diid,
+                "pdu": hex::encode(pdu),
+                "RX2DR": dr,
+                "RX2Freq": freq,
+                "priority": 0,
+                "rctx": self.uplinks.back().map_or(0, |(_, _, rctx)| *rctx),
+            }),
+        };
+        Ok(dnmsg)
+    }
+}
+
+async fn send_json(ws: &mut WebSocket, value: &Value) -> Result {
+    ws.send(Message::Text(value.to_string().into()))
+        .await
+        .map_err(|err| Box::new(err).into())
+}
+
+/// Data rates as (spreading factor, bandwidth, downlink only). Undefined data
+/// rates have a spreading factor of -1
+type DataRates = &'static [(i32, u32, u32)];
+
+const EU_DATARATES: DataRates = &[
+    (12, 125, 0),
+    (11, 125, 0),
+    (10, 125, 0),
+    (9, 125, 0),
+    (8, 125, 0),
+    (7, 125, 0),
+    (7, 250, 0),
+];
+
+const US_DATARATES: DataRates = &[
+    (10, 125, 0),
+    (9, 125, 0),
+    (8, 125, 0),
+    (7, 125, 0),
+    (8, 500, 0),
+    (-1, 0, 0),
+    (-1, 0, 0),
+    (-1, 0, 0),
+    (12, 500, 1),
+    (11, 500, 1),
+    (10, 500, 1),
+    (9, 500, 1),
+    (8, 500, 1),
+    (7, 500, 1),
+];
+
+const AU_DATARATES: DataRates = &[
+    (12, 125, 0),
+    (11, 125, 0),
+    (10, 125, 0),
+    (9, 125, 0),
+    (8, 125, 0),
+    (7, 125, 0),
+    (8, 500, 0),
+    (-1, 0, 0),
+    (12, 500, 1),
+    (11, 500, 1),
+    (10, 500, 1),
+    (9, 500, 1),
+    (8, 500, 1),
+    (7, 500, 1),
+];
+
+/// Basics Station region name, frequency range and data rate table
+struct StationRegion {
+    name: &'static str,
+    freq_range: [u32; 2],
+    datarates: DataRates,
+}
+
+impl StationRegion {
+    fn from_region(region: &Region) -> Option<Self> {
+        let (name, freq_range, datarates) = match region.to_string().as_str() {
+            "US915" => ("US902", [902_000_000, 928_000_000], US_DATARATES),
+            "AU915" => ("AU915", [915_000_000, 928_000_000], AU_DATARATES),
+            "EU868" => ("EU863", [863_000_000, 870_000_000], EU_DATARATES),
+            "EU433" => ("EU433", [433_050_000, 434_790_000], EU_DATARATES),
+            "CN470" => ("CN470", [470_000_000, 510_000_000], EU_DATARATES),
+            "KR920" => ("KR920", [920_900_000, 923_300_000], EU_DATARATES),
+            "IN865" => ("IN865", [865_000_000, 867_000_000], EU_DATARATES),
+            "AS923_1" => ("AS923-1", [915_000_000, 928_000_000], EU_DATARATES),
+            "AS923_2" => ("AS923-2", [915_000_000, 928_000_000], EU_DATARATES),
+            "AS923_3" => ("AS923-3", [915_000_000, 928_000_000], EU_DATARATES),
+            "AS923_4" => ("AS923-4", [915_000_000, 928_000_000], EU_DATARATES),
+            _ => return None,
+        };
+        Some(Self {
+            name,
+            freq_range,
+            datarates,
+        })
+    }
+
+    fn datarate(&self, index: usize) -> Option<(i32, u32)> {
+        self.datarates
+            .get(index)
+            .filter(|(sf, _, _)| *sf > 0)
+            .map(|(sf, bw, _)| (*sf, *bw))
+    }
+
+    /// Returns the data rate index for a semtech data rate string like
+    /// "SF7BW125", preferring downlink only data rates
+    fn datarate_index(&self, datr: &str) -> Option<usize> {
+        let matches = |(sf, bw, _): &(i32, u32, u32)| format!("SF{sf}BW{bw}") == datr;
+        self.datarates
+            .iter()
+            .position(|dr| dr.2 == 1 && matches(dr))
+            .or_else(|| self.datarates.iter().position(matches))
+    }
+}
+
+fn router_config(params: &RegionParams) -> Option<Value> {
+    let region = StationRegion::from_region(&params.region)?;
+    let mut frequencies: Vec<u64> = params
+        .params
+        .iter()
+        .map(|param| param.channel_frequency)
+        .collect();
+    frequencies.sort_unstable();
+    frequencies.dedup();
+    frequencies.truncate(MULTI_SF_CHANNELS);
+    let datarates: Vec<Value> = (0..16)
+        .map(|index| match region.datarates.get(index) {
+            Some((sf, bw, dnonly)) => json!([sf, bw, dnonly]),
+            None => json!([-1, 0, 0]),
+        })
+        .collect();
+    Some(json!({
+        "msgtype": "router_config",
+        "NetID": null,
+        "JoinEui": null,
+        "region": region.name,
+        "hwspec": "sx1301/1",
+        "freq_range": region.freq_range,
+        "DRs": datarates,
+        "sx1301_conf": [sx1301_conf(&frequencies)?],
+    }))
+}
+
+/// Builds a concentrator configuration that spreads the given (sorted)
+/// channel frequencies over the two radios of an SX1301
+fn sx1301_conf(frequencies: &[u64]) -> Option<Value> {
+    let mut radios: Vec<(u64, u64)> = vec![];
+    let mut channels: Vec<(u64, usize)> = vec![];
+    for frequency in frequencies {
+        match radios.last_mut() {
+            Some((min, max)) if frequency - *min <= RADIO_CHANNEL_SPAN => *max = *frequency,
+            _ if radios.len() < 2 => radios.push((*frequency, *frequency)),
+            _ => break,
+        }
+        channels.push((*frequency, radios.len() - 1));
+    }
+    let centers: Vec<u64> = radios.iter().map(|(min, max)| (min + max) / 2).collect();
+    let first_center = *centers.first()?;
+
+    let mut conf = serde_json::Map::new();
+    for radio in 0..2 {
+        conf.insert(
+            format!("radio_{radio}"),
+            json!({
+                "enable": radio < centers.len(),
+                "freq": centers.get(radio).unwrap_or(&first_center),
+            }),
+        );
+    }
+    for index in 0..MULTI_SF_CHANNELS {
+        let channel = match channels.get(index) {
+            Some((frequency, radio)) => json!({
+                "enable": true,
+                "radio": radio,
+                "if": *frequency as i64 - centers[*radio] as i64,
+            }),
+            None => json!({ "enable": false }),
+        };
+        conf.insert(format!("chan_multiSF_{index}"), channel);
+    }
+    conf.insert("chan_Lora_std".to_string(), json!({ "enable": false }));
+    conf.insert("chan_FSK".to_string(), json!({ "enable": false }));
+    Some(Value::Object(conf))
+}
+
+/// Rebuilds the PHYPayload of an `updf` message
+fn updf_payload(message: &Value) -> Option<Vec<u8>> {
+    let mut payload = vec![message["MHdr"].as_u64()? as u8];
+    payload.extend_from_slice(&(message["DevAddr"].as_i64()? as u32).to_le_bytes());
+    payload.push(message["FCtrl"].as_u64()? as u8);
+    payload.extend_from_slice(&(message["FCnt"].as_u64()? as u16).to_le_bytes());
+    payload.extend(hex::decode(message["FOpts"].as_str()?).ok()?);
+    let fport = message["FPort"].as_i64()?;
+    if fport >= 0 {
+        payload.push(fport as u8);
+        payload.extend(hex::decode(message["FRMPayload"].as_str()?).ok()?);
+    }
+    payload.extend_from_slice(&(message["MIC"].as_i64()? as i32).to_le_bytes());
+    Some(payload)
+}
+
+/// Rebuilds the PHYPayload of a `jreq` message
+fn jreq_payload(message: &Value) -> Option<Vec<u8>> {
+    let mut payload = vec![message["MHdr"].as_u64()? as u8];
+    payload.extend_from_slice(&parse_eui(message["JoinEui"].as_str()?)?.to_le_bytes());
+    payload.extend_from_slice(&parse_eui(message["DevEui"].as_str()?)?.to_le_bytes());
+    payload.extend_from_slice(&(message["DevNonce"].as_u64()? as u16).to_le_bytes());
+    payload.extend_from_slice(&(message["MIC"].as_i64()? as i32).to_le_bytes());
+    Some(payload)
+}
+
+/// Parses an EUI in any of the station formats ("01-02-..", "0102:..", or
+/// plain hex)
+fn parse_eui(str: &str) -> Option<u64> {
+    let hex: String = str.chars().filter(char::is_ascii_hexdigit).collect();
+    if hex.is_empty() || hex.len() != str.chars().filter(|c| *c != '-' && *c != ':').count() {
+        return None;
+    }
+    u64::from_str_radix(&hex, 16).ok()
+}
+
+fn format_eui(eui: u64) -> String {
+    eui.to_be_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+/// Current GPS time in microseconds
+fn gps_time() -> Result<u64> {
+    let unix_time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let gps_time =
+        unix_time + Duration::from_secs(GPS_LEAP_SECONDS) - Duration::from_secs(GPS_EPOCH_OFFSET);
+    Ok(gps_time.as_micros() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_eui() {
+        assert_eq!(
+            Some(0x0102030405060708),
+            parse_eui("01-02-03-04-05-06-07-08")
+        );
+        assert_eq!(Some(0x0102030405060708), parse_eui("0102:0304:0506:0708"));
+        assert_eq!(Some(1), parse_eui("::1"));
+        assert_eq!(None, parse_eui("router-1"));
+        assert_eq!("01-02-03-04-05-06-07-08", format_eui(0x0102030405060708));
+    }
+
+    #[test]
+    fn test_updf_payload() {
+        // Unconfirmed uplink from the lorawan crate test packets
+        let expected: &[u8] = &[
+            64, 71, 165, 101, 0, 128, 130, 41, 2, 214, 3, 27, 61, 140, 165, 211, 143, 196, 1, 134,
+            56, 31, 122, 222,
+        ];
+        let message = json!({
+            "msgtype": "updf",
+            "MHdr": 64,
+            "DevAddr": 0x0065A547,
+            "FCtrl": 128,
+            "FCnt": 10626,
+            "FOpts": "",
+            "FPort": 2,
+            "FRMPayload": "d6031b3d8ca5d38fc40186",
+            "MIC": i32::from_le_bytes([56, 31, 122, 222]),
+        });
+        assert_eq!(Some(expected.to_vec()), updf_payload(&message));
+    }
+
+    #[test]
+    fn test_sx1301_conf() {
+        let frequencies = [
+            903_900_000,
+            904_100_000,
+            904_300_000,
+            904_500_000,
+            904_700_000,
+            904_900_000,
+            905_100_000,
+            905_300_000,
+        ];
+        let conf = sx1301_conf(&frequencies).expect("sx1301 conf");
+        // Radios are centered between the lowest and highest channel they serve
+        assert_eq!(json!(904_300_000), conf["radio_0"]["freq"]);
+        assert_eq!(json!(905_100_000), conf["radio_1"]["freq"]);
+        assert_eq!(json!(-400_000), conf["chan_multiSF_0"]["if"]);
+        assert_eq!(json!(0), conf["chan_multiSF_4"]["radio"]);
+        assert_eq!(json!(400_000), conf["chan_multiSF_4"]["if"]);
+        assert_eq!(json!(1), conf["chan_multiSF_5"]["radio"]);
+        assert_eq!(json!(-200_000), conf["chan_multiSF_5"]["if"]);
+        assert_eq!(json!(1), conf["chan_multiSF_7"]["radio"]);
+        assert_eq!(json!(200_000), conf["chan_multiSF_7"]["if"]);
+    }
+}
@@ -1,20 +1,30 @@
 use crate::{
-    beaconer, packet, packet_router, region_watcher, sync, DecodeError, Error, PacketDown,
-    PacketUp, PublicKey, RegionParams, Result, Settings,
+    beaconer, events,
+    forwarder::{self, Downlink, Event, Forwarder, TxError, TxResult},
+    message_cache::MessageCache,
+    metrics, packet, packet_router, region_watcher,
+    settings::UpstreamSettings,
+    sync,
+    tx_budget::{RegulatoryError, TxBudget},
+    uplink_filter::{JoinFilterStatus, UplinkFilter, Verdict},
+    upstream, DecodeError, Error, PacketDown, PacketUp, PublicKey, RegionParams, Result, Settings,
 };
 use beacon::Beacon;
 use lorawan::PHYPayload;
 use semtech_udp::{
     pull_resp::{self, Time},
-    server_runtime::{Error as SemtechError, Event, UdpRuntime},
-    tx_ack,
-    tx_ack::Error as TxAckErr,
-    CodingRate, MacAddress, Modulation,
+    push_data, CodingRate, MacAddress, Modulation,
+};
+use serde::Serialize;
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
 };
-use std::time::{Duration, Instant};
 use tracing::{debug, info, warn};
 
 pub const DOWNLINK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Number of recent uplinks to remember the receiving packet forwarder for.
+const UPLINK_SOURCES_MAX: u16 = 50;
 
 #[derive(Debug)]
 pub struct BeaconResp {
@@ -22,10 +32,22 @@ pub struct BeaconResp {
     pub tmst: u32,
 }
 
+/// A packet forwarder connected to the gateway
+#[derive(Debug, Clone, Serialize)]
+pub struct ForwarderClient {
+    pub mac: String,
+    pub addr: SocketAddr,
+    /// Whether this client is used to transmit beacons
+    pub primary: bool,
+}
+
 #[derive(Debug)]
 pub enum Message {
     Downlink(PacketDown),
     TransmitBeacon(Beacon, sync::ResponseSender<Result<BeaconResp>>),
+    Clients(sync::ResponseSender<Vec<ForwarderClient>>),
+    JoinFilter(bool, sync::ResponseSender<Result<JoinFilterStatus>>),
+    Transmit(pull_resp::TxPk, sync::ResponseSender<TxResult>),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -34,6 +56,10 @@ pub enum GatewayError {
     NoBeaconTxPower,
     #[error("beacon transmit failed")]
     BeaconTxFailure,
+    #[error("no packet forwarder connected")]
+    NoClient,
+    #[error("transmit refused: {0}")]
+    Regulatory(#[from] RegulatoryError),
 }
 
 pub type MessageSender = sync::MessageSender<Message>;
@@ -57,6 +83,133 @@ impl MessageSender {
         self.request(move |tx| Message::TransmitBeacon(beacon, tx))
             .await?
     }
+
+    pub async fn clients(&self) -> Result<Vec<ForwarderClient>> {
+        self.request(Message::Clients).await
+    }
+
+    /// Returns the join filter rules and their match counts, optionally
+    /// reloading the join rules file first.
+    pub async fn join_filter(&self, reload: bool) -> Result<JoinFilterStatus> {
+        self.request(move |tx| Message::JoinFilter(reload, tx))
+            .await?
+    }
+
+    /// Transmits a downlink from another network server on the packet
+    /// forwarder that received the uplink it responds to.
+    pub async fn transmit(&self, txpk: pull_resp::TxPk) -> Result<TxResult> {
+        self.request(move |tx| Message::Transmit(txpk, tx)).await
+    }
+}
+
+/// The packet forwarder an uplink was received on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct UplinkSource {
+    tmst: u32,
+    mac: MacAddress,
+}
+
+impl UplinkSource {
+    /// A downlink window belongs to this uplink if it is scheduled a whole
+    /// number of seconds, the LoRaWAN receive delay, after the uplink on the
+    /// same concentrator clock.
+    fn is_rx_window(&self, window_tmst: u32) -> bool {
+        forwarder::rx_delay(self.tmst, window_tmst).is_some()
+    }
+}
+
+/// Connected packet forwarders and the uplinks recently received on them
+struct Clients {
+    /// Connected packet forwarders in order of connection
+    connected: Vec<(MacAddress, SocketAddr)>,
+    /// Configured packet forwarder to use for beacons
+    primary_forwarder: Option<String>,
+    /// Recently received uplinks and the forwarder they were received on
+    uplink_sources: MessageCache<UplinkSource>,
+}
+
+impl Clients {
+    fn new(primary_forwarder: Option<String>) -> Self {
+        Self {
+            connected: vec![],
+            primary_forwarder,
+            uplink_sources: MessageCache::new(UPLINK_SOURCES_MAX),
+        }
+    }
+
+    fn connect(&mut self, mac: MacAddress, addr: SocketAddr) {
+        self.connected.retain(|(client_mac, _)| *client_mac != mac);
+        self.connected.push((mac, addr));
+    }
+
+    fn update(&mut self, mac: MacAddress, addr: SocketAddr) {
+        match self
+            .connected
+            .iter_mut()
+            .find(|(client_mac, _)| *client_mac == mac)
+        {
+            Some(client) => client.1 = addr,
+            None => self.connected.push((mac, addr)),
+        }
+    }
+
+    fn disconnect(&mut self, mac: MacAddress) {
+        self.connected.retain(|(client_mac, _)| *client_mac != mac);
+    }
+
+    /// Records the packet forwarder an uplink with the given concentrator
+    /// timestamp was received on
+    fn uplink(&mut self, mac: MacAddress, tmst: u32, received: Instant) {
+        self.uplink_sources
+            .push_back(UplinkSource { tmst, mac }, received);
+    }
+
+    /// Returns the packet forwarder to use for beacons. This is the configured
+    /// primary forwarder if it is connected, or the longest connected
+    /// forwarder otherwise.
+    fn primary(&self) -> Option<MacAddress> {
+        self.primary_forwarder
+            .as_ref()
+            .and_then(|primary| {
+                self.connected
+                    .iter()
+                    .find(|(mac, _)| normalize_mac(&mac.to_string()) == normalize_mac(primary))
+            })
+            .or_else(|| self.connected.first())
+            .map(|(mac, _)| *mac)
+    }
+
+    /// Returns the packet forwarder that received the uplink a downlink
+    /// window at the given timestamp is a response to, falling back to the
+    /// primary forwarder for unmatched (like class C) downlinks.
+    fn downlink(&self, window_tmst: Option<u32>) -> Option<MacAddress> {
+        window_tmst
+            .and_then(|window_tmst| {
+                self.uplink_sources
+                    .iter()
+                    .rev()
+                    .find(|source| source.is_rx_window(window_tmst))
+            })
+            .map(|source| source.mac)
+            .filter(|mac| {
+                self.connected
+                    .iter()
+                    .any(|(client_mac, _)| client_mac == mac)
+            })
+            .or_else(|| self.primary())
+    }
+
+    fn forwarder_clients(&self) -> Vec<ForwarderClient> {
+        let primary = self.primary();
+        self.connected
+            .iter()
+            .map(|(mac, addr)| ForwarderClient {
+                mac: mac.to_string(),
+                addr: *addr,
+                primary: primary == Some(*mac),
+            })
+            .collect()
+    }
 }
 
 pub struct Gateway {
@@ -64,11 +217,19 @@ pub struct Gateway {
     messages: MessageReceiver,
     uplinks: packet_router::MessageSender,
     beacons: beaconer::MessageSender,
-    downlink_mac: MacAddress,
-    udp_runtime: UdpRuntime,
+    /// Connected packet forwarders
+    clients: Clients,
+    /// Packet forwarder transport
+    forwarder: Box<dyn Forwarder>,
     listen_address: String,
     region_watch: region_watcher::MessageReceiver,
     region_params: RegionParams,
+    /// Regulatory airtime budget for transmissions
+    tx_budget: TxBudget,
+    /// Filter for uplinks to forward to the packet router
+    uplink_filter: UplinkFilter,
+    /// Secondary network server to offer matching uplinks to
+    upstream: Option<(UpstreamSettings, upstream::MessageSender)>,
 }
 
 impl Gateway {
@@ -78,6 +239,7 @@ impl Gateway {
         region_watch: region_watcher::MessageReceiver,
         uplinks: packet_router::MessageSender,
         beacons: beaconer::MessageSender,
+        upstream: Option<upstream::MessageSender>,
     ) -> Result<Self> {
         let region_params = region_watcher::current_value(&region_watch);
         let public_key = settings.keypair.public_key().clone();
@@ -86,11 +248,14 @@ impl Gateway {
             messages,
             uplinks,
             beacons,
-            downlink_mac: Default::default(),
+            clients: Clients::new(settings.primary_forwarder.clone()),
             listen_address: settings.listen.clone(),
-            udp_runtime: UdpRuntime::new(&settings.listen).await.map_err(Box::new)?,
+            forwarder: forwarder::new(settings, region_watch.clone()).await?,
             region_watch,
+            tx_budget: TxBudget::new(&region_params),
             region_params,
+            uplink_filter: UplinkFilter::new(&settings.filter)?,
+            upstream: settings.upstream.clone().zip(upstream),
         };
         Ok(gateway)
     }
@@ -103,8 +268,8 @@ impl Gateway {
                     info!( "shutting down");
                     return Ok(())
                 },
-                event = self.udp_runtime.recv() =>
-                    self.handle_udp_event(event).await?,
+                event = self.forwarder.recv() =>
+                    self.handle_event(event).await?,
                 message = self.messages.recv() => match message {
                     Some(message) => self.handle_message(message).await,
                     None => {
@@ -120,6 +285,7 @@ impl Gateway {
                         if self.region_params != new_region_params {
                             info!(region = RegionParams::to_string(&new_region_params), "region updated");
                         }
+                        self.tx_budget.set_region(&new_region_params);
                         self.region_params = new_region_params;
                     }
                     Err(_) => warn!("region watch disconnected")
@@ -128,39 +294,51 @@ impl Gateway {
         }
     }
 
-    async fn handle_udp_event(&mut self, event: Event) -> Result {
+    async fn handle_event(&mut self, event: Event) -> Result {
         match event {
             Event::UnableToParseUdpFrame(e, buf) => {
                 warn!(raw_bytes = ?buf, "ignoring semtech udp parsing error {e}");
             }
             Event::NewClient((mac, addr)) => {
                 info!(%mac, %addr, "new packet forwarder client");
-                self.downlink_mac = mac;
+                self.clients.connect(mac, addr);
             }
             Event::UpdateClient((mac, addr)) => {
-                info!(%mac, %addr, "mac existed, but IP updated")
+                info!(%mac, %addr, "mac existed, but IP updated");
+                self.clients.update(mac, addr);
             }
             Event::ClientDisconnected((mac, addr)) => {
-                info!(%mac, %addr, "disconnected packet forwarder")
+                info!(%mac, %addr, "disconnected packet forwarder");
+                self.clients.disconnect(mac);
             }
-            Event::PacketReceived(rxpk, _gateway_mac) => {
-                match PacketUp::from_rxpk(rxpk, &self.public_key, self.region_params.region) {
+            Event::PacketReceived(rxpk, gateway_mac) => {
+                metrics::uplink("received");
+                match PacketUp::from_rxpk(&rxpk, &self.public_key, self.region_params.region) {
                     Ok(packet) if packet.is_potential_beacon() => {
-                        self.handle_potential_beacon(packet).await;
+                        self.handle_potential_beacon(packet, gateway_mac).await;
                     }
                     Ok(packet) if packet.is_uplink() => {
-                        self.handle_uplink(packet, Instant::now()).await
+                        self.handle_uplink(packet, rxpk, gateway_mac, Instant::now())
+                            .await
                     }
                     Ok(packet) => {
+                        metrics::uplink("ignored");
+                        events::ignored(gateway_mac, "non_uplink");
                         info!(%packet, "ignoring non-uplink packet");
                     }
                     Err(Error::Decode(DecodeError::CrcDisabled)) => {
+                        metrics::uplink("ignored");
+                        events::ignored(gateway_mac, "crc_disabled");
                         debug!("ignoring packet with disabled crc");
                     }
                     Err(Error::Decode(DecodeError::InvalidDataRate(datarate))) => {
+                        metrics::uplink("ignored");
+                        events::ignored(gateway_mac, "invalid_datarate");
                         debug!(%datarate, "ignoring packet with invalid datarate");
                     }
                     Err(err) => {
+                        metrics::uplink("ignored");
+                        events::ignored(gateway_mac, "invalid_packet");
                         warn!(%err, "ignoring push_data");
                     }
                 }
@@ -175,30 +353,60 @@ impl Gateway {
         Ok(())
     }
 
-    async fn handle_potential_beacon(&mut self, packet: PacketUp) {
+    async fn handle_potential_beacon(&mut self, packet: PacketUp, mac: MacAddress) {
         if self.region_params.is_unknown() {
-            info!(downlink_mac = %self.downlink_mac, uplink = %packet, "ignored potential beacon, no region");
+            info!(%mac, uplink = %packet, "ignored potential beacon, no region");
             return;
         }
-        info!(downlink_mac = %self.downlink_mac, uplink = %packet, "received potential beacon");
+        info!(%mac, uplink = %packet, "received potential beacon");
         self.beacons.received_beacon(packet).await
     }
 
-    async fn handle_uplink(&mut self, packet: PacketUp, received: Instant) {
+    async fn handle_uplink(
+        &mut self,
+        packet: PacketUp,
+        rxpk: push_data::RxPk,
+        mac: MacAddress,
+        received: Instant,
+    ) {
         if self.region_params.is_unknown() {
+            metrics::uplink("ignored");
+            events::ignored(mac, "no_region");
             info!(
-                downlink_mac = %self.downlink_mac,
+                %mac,
                 uplink = %packet,
                 region = %self.region_params,
                 "ignored uplink");
             return;
         }
         info!(
-            downlink_mac = %self.downlink_mac,
+            %mac,
             uplink = %packet,
             region = %self.region_params,
             "received uplink");
-        self.uplinks.uplink(packet, received).await;
+        events::uplink(&packet, mac);
+        self.clients.uplink(mac, packet.timestamp as u32, received);
+        // The upstream network server is offered uplinks regardless of the
+        // packet router filters
+        if let Some((settings, upstream)) = &self.upstream {
+            if settings.matches(&packet) && !upstream.uplink(packet.clone(), rxpk) {
+                warn!(%mac, uplink = %packet, "upstream not keeping up, dropping uplink");
+            }
+        }
+        let priority = match self.uplink_filter.check(&packet) {
+            Verdict::Forward(priority) => priority,
+            Verdict::Drop => {
+                metrics::uplink("filtered");
+                events::ignored(mac, "filtered");
+                debug!(%mac, uplink = %packet, "filtered uplink");
+                return;
+            }
+        };
+        let mac_commands = packet.mac_commands();
+        if !mac_commands.is_empty() {
+            debug!(%mac, ?mac_commands, "uplink mac commands");
+        }
+        self.uplinks.uplink(packet, priority, received).await;
     }
 
     async fn handle_message(&mut self, message: Message) {
@@ -207,7 +415,20 @@ impl Gateway {
             Message::TransmitBeacon(beacon, tx_resp) => {
                 self.handle_transmit_beacon(beacon, tx_resp).await
             }
+            Message::Clients(tx_resp) => tx_resp.send(self.clients.forwarder_clients()),
+            Message::JoinFilter(reload, tx_resp) => tx_resp.send(self.join_filter(reload)),
+            Message::Transmit(txpk, tx_resp) => self.handle_transmit(txpk, tx_resp),
+        }
+    }
+
+    fn join_filter(&mut self, reload: bool) -> Result<JoinFilterStatus> {
+        if reload {
+            self.uplink_filter
+                .reload()
+                .inspect_err(|err| warn!(%err, "failed to reload join rules"))?;
+            info!("reloaded join rules");
         }
+        Ok(self.uplink_filter.join_status())
     }
 
     fn max_tx_power(&mut self) -> Result<u32> {
@@ -228,6 +449,12 @@ impl Gateway {
             }
         };
 
+        let Some(beacon_mac) = self.clients.primary() else {
+            warn!("beacon transmit, no packet forwarder connected");
+            responder.send(Err(GatewayError::NoClient.into()));
+            return;
+        };
+
         let packet = match beacon_to_pull_resp(&beacon, tx_power as u64) {
             Ok(packet) => packet,
             Err(err) => {
@@ -237,14 +464,30 @@ impl Gateway {
             }
         };
 
-        let beacon_tx = self.udp_runtime.prepare_downlink(packet, self.downlink_mac);
+        let reservation = match self.tx_budget.reserve(&packet) {
+            Ok(reservation) => reservation,
+            Err(err) => {
+                warn!(%err, "beacon transmit refused");
+                responder.send(Err(GatewayError::from(err).into()));
+                return;
+            }
+        };
+
+        let mut beacon_tx = self.forwarder.prepare_downlink(beacon_mac);
+        beacon_tx.set_packet(packet);
 
+        let tx_budget = self.tx_budget.clone();
         tokio::spawn(async move {
             let beacon_id = beacon.beacon_id();
-            match beacon_tx.dispatch(Some(DOWNLINK_TIMEOUT)).await {
+            let result = beacon_tx.dispatch(Some(DOWNLINK_TIMEOUT)).await;
+            if !transmitted(&result) {
+                tx_budget.release(reservation);
+            }
+            match result {
                 Ok(tmst) => {
                     info!(
                         beacon_id,
+                        %beacon_mac,
                         %tx_power,
                         ?tmst,
                         "beacon transmitted"
@@ -256,10 +499,7 @@ impl Gateway {
                     tmst
                 }
                 Err(err) => {
-                    if let semtech_udp::server_runtime::Error::Ack(
-                        tx_ack::Error::AdjustedTransmitPower(power_used, tmst),
-                    ) = err
-                    {
+                    if let TxError::AdjustedTransmitPower(power_used, tmst) = err {
                         match power_used {
                             None => {
                                 warn!("packet transmitted with adjusted power, but packet forwarder does not indicate power used.");
@@ -298,29 +538,46 @@ impl Gateway {
             }
         };
 
-        let (mut downlink_rx1, mut downlink_rx2) = (
+        let Some(downlink_mac) = self.clients.downlink(downlink.rx1_timestamp()) else {
+            warn!("downlink transmit, no packet forwarder connected");
+            return;
+        };
+
+        let (downlink_rx1, downlink_rx2) = (
             // first downlink
-            self.udp_runtime.prepare_empty_downlink(self.downlink_mac),
+            self.forwarder.prepare_downlink(downlink_mac),
             // 2nd downlink window if requested by the router response
-            self.udp_runtime.prepare_empty_downlink(self.downlink_mac),
+            self.forwarder.prepare_downlink(downlink_mac),
         );
 
-        let downlink_mac = self.downlink_mac;
-
+        let tx_budget = self.tx_budget.clone();
         tokio::spawn(async move {
             if let Ok(txpk) = downlink.to_rx1_pull_resp(tx_power) {
                 info!(%downlink_mac, "rx1 downlink {txpk}",);
 
-                downlink_rx1.set_packet(txpk);
-                match downlink_rx1.dispatch(Some(DOWNLINK_TIMEOUT)).await {
-                    // On a too early or too late error retry on the rx2 slot if available.
-                    Err(SemtechError::Ack(TxAckErr::TooEarly | TxAckErr::TooLate)) => {
+                match dispatch_downlink(&tx_budget, downlink_mac, "rx1", downlink_rx1, txpk).await {
+                    // On a too early, too late or refused transmit retry on
+                    // the rx2 slot if available.
+                    Err(err @ (TxError::TooEarly | TxError::TooLate | TxError::Regulatory(_))) => {
+                        if let TxError::Regulatory(err) = err {
+                            warn!(%downlink_mac, %err, "rx1 downlink refused");
+                        }
                         if let Ok(Some(txpk)) = downlink.to_rx2_pull_resp(tx_power) {
                             info!(%downlink_mac, "rx2 downlink {txpk}");
 
-                            downlink_rx2.set_packet(txpk);
-                            match downlink_rx2.dispatch(Some(DOWNLINK_TIMEOUT)).await {
-                                Err(SemtechError::Ack(TxAckErr::AdjustedTransmitPower(_, _))) => {
+                            match dispatch_downlink(
+                                &tx_budget,
+                                downlink_mac,
+                                "rx2",
+                                downlink_rx2,
+                                txpk,
+                            )
+                            .await
+                            {
+                                Err(TxError::Regulatory(err)) => {
+                                    warn!(%downlink_mac, %err, "rx2 downlink refused");
+                                }
+                                Err(TxError::AdjustedTransmitPower(_, _)) => {
                                     warn!("rx2 downlink sent with adjusted transmit power");
                                 }
                                 Err(err) => warn!(%err, "ignoring rx2 downlink error"),
@@ -328,7 +585,7 @@ impl Gateway {
                             }
                         }
                     }
-                    Err(SemtechError::Ack(TxAckErr::AdjustedTransmitPower(_, _))) => {
+                    Err(TxError::AdjustedTransmitPower(_, _)) => {
                         warn!("rx1 downlink sent with adjusted transmit power");
                     }
                     Err(err) => {
@@ -339,6 +596,86 @@ impl Gateway {
             }
         });
     }
+
+    fn handle_transmit(
+        &mut self,
+        mut txpk: pull_resp::TxPk,
+        responder: sync::ResponseSender<TxResult>,
+    ) {
+        // Immediate and gps time downlinks have no receive window to match
+        let window_tmst = txpk.time.tmst().filter(|_| !txpk.is_immediate());
+        let Some(downlink_mac) = self.clients.downlink(window_tmst) else {
+            warn!("upstream downlink transmit, no packet forwarder connected");
+            responder.send(Err(TxError::Disconnected));
+            return;
+        };
+        if let Ok(tx_power) = self.max_tx_power() {
+            txpk.powe = txpk.powe.min(tx_power as u64);
+        }
+        let downlink = self.forwarder.prepare_downlink(downlink_mac);
+        let tx_budget = self.tx_budget.clone();
+        tokio::spawn(async move {
+            info!(%downlink_mac, "upstream downlink {txpk}");
+            let result =
+                dispatch_downlink(&tx_budget, downlink_mac, "upstream", downlink, txpk).await;
+            if let Err(err) = &result {
+                warn!(%downlink_mac, %err, "upstream downlink failed");
+            }
+            responder.send(result);
+        });
+    }
+}
+
+/// Dispatches a downlink packet in the given receive window if its airtime
+/// fits in the regulatory transmit budget
+async fn dispatch_downlink(
+    tx_budget: &TxBudget,
+    mac: MacAddress,
+    window: &str,
+    mut downlink: Downlink,
+    txpk: pull_resp::TxPk,
+) -> TxResult {
+    let result = match tx_budget.reserve(&txpk) {
+        Ok(reservation) => {
+            downlink.set_packet(txpk.clone());
+            let result = downlink.dispatch(Some(DOWNLINK_TIMEOUT)).await;
+            // Only charge the budget for transmitted packets so a too early
+            // or too late rx1 attempt does not count against the rx2 retry
+            if !transmitted(&result) {
+                tx_budget.release(reservation);
+            }
+            result
+        }
+        Err(err) => Err(err.into()),
+    };
+    let outcome = match &result {
+        Ok(_) => "ok",
+        Err(TxError::TooEarly) => "too_early",
+        Err(TxError::TooLate) => "too_late",
+        Err(TxError::AdjustedTransmitPower(_, _)) => "adjusted_power",
+        Err(TxError::Regulatory(_)) => "refused",
+        Err(_) => "error",
+    };
+    metrics::downlink(window, outcome);
+    events::downlink(mac, window, outcome, &txpk);
+    result
+}
+
+/// Whether a dispatch result means the packet may have been transmitted. A
+/// missing transmit ack is assumed to be a transmission.
+fn transmitted(result: &TxResult) -> bool {
+    matches!(
+        result,
+        Ok(_) | Err(TxError::AdjustedTransmitPower(_, _) | TxError::Timeout)
+    )
+}
+
+/// Normalizes a hex mac address string by dropping separators and case
+fn normalize_mac(mac: &str) -> String {
+    mac.chars()
+        .filter(char::is_ascii_hexdigit)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
 }
 
 pub fn beacon_to_pull_resp(beacon: &Beacon, tx_power: u64) -> Result<pull_resp::TxPk> {
@@ -361,3 +698,57 @@ pub fn beacon_to_pull_resp(beacon: &Beacon, tx_power: u64) -> Result<pull_resp::
         ncrc: None,
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PRIMARY: MacAddress = MacAddress::new(0xAA, 0x55, 0x5A, 0, 0, 0, 0, 1);
+    const SECONDARY: MacAddress = MacAddress::new(0xAA, 0x55, 0x5A, 0, 0, 0, 0, 2);
+
+    fn connected_clients(primary_forwarder: Option<&str>) -> Clients {
+        let mut clients = Clients::new(primary_forwarder.map(str::to_string));
+        clients.connect(PRIMARY, "127.0.0.1:1001".parse().unwrap());
+        clients.connect(SECONDARY, "127.0.0.1:1002".parse().unwrap());
+        clients
+    }
+
+    #[test]
+    fn test_downlink_client() {
+        let mut clients = connected_clients(None);
+        let now = Instant::now();
+        clients.uplink(PRIMARY, 1_000_000, now);
+        clients.uplink(SECONDARY, 1_500_000, now);
+
+        // Rx1 and rx2 windows go to the forwarder that heard the uplink
+        assert_eq!(Some(PRIMARY), clients.downlink(Some(2_000_000)));
+        assert_eq!(Some(SECONDARY), clients.downlink(Some(2_500_000)));
+        assert_eq!(Some(SECONDARY), clients.downlink(Some(3_500_000)));
+        // The concentrator clock wraps around
+        clients.uplink(SECONDARY, u32::MAX - 499_999, now);
+        assert_eq!(Some(SECONDARY), clients.downlink(Some(500_000)));
+    }
+
+    #[test]
+    fn test_downlink_client_fallback() {
+        let mut clients = connected_clients(Some("aa:55:5a:00:00:00:00:02"));
+        clients.uplink(PRIMARY, 1_000_000, Instant::now());
+
+        // Class C downlinks and windows that match no uplink go to the
+        // primary forwarder
+        assert_eq!(Some(SECONDARY), clients.downlink(None));
+        assert_eq!(Some(SECONDARY), clients.downlink(Some(1_250_000)));
+        assert_eq!(Some(PRIMARY), clients.downlink(Some(2_000_000)));
+
+        // As do downlinks for a forwarder that disconnected
+        clients.disconnect(PRIMARY);
+        assert_eq!(Some(SECONDARY), clients.downlink(Some(2_000_000)));
+
+        // Without the configured primary the longest connected forwarder is used
+        let mut clients = connected_clients(Some("aa:55:5a:00:00:00:00:03"));
+        assert_eq!(Some(PRIMARY), clients.downlink(None));
+        clients.disconnect(PRIMARY);
+        clients.disconnect(SECONDARY);
+        assert_eq!(None, clients.downlink(None));
+    }
+}
@@ -1,10 +1,13 @@
 pub mod beaconer;
 pub mod cmd;
 pub mod error;
+pub mod events;
+pub mod forwarder;
 pub mod gateway;
 pub mod keyed_uri;
 pub mod keypair;
 pub mod message_cache;
+pub mod metrics;
 pub mod packet;
 
 pub mod packet_router;
@@ -13,6 +16,10 @@ pub mod server;
 pub mod service;
 pub mod settings;
 pub mod sync;
+pub mod syslog;
+pub mod tx_budget;
+pub mod uplink_filter;
+pub mod upstream;
 
 mod api;
 mod base64;
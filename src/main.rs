@@ -1,9 +1,14 @@
 use clap::Parser;
-use gateway_rs::{cmd, error::Result, settings::Settings};
+use gateway_rs::{
+    cmd,
+    error::Result,
+    settings::{LogFormat, LogMethod, LogSettings, Settings},
+    syslog::Syslog,
+};
 use std::path::PathBuf;
 use tokio::{io::AsyncReadExt, signal, time::Duration};
 use tracing::{debug, error, Level};
-use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt::MakeWriter, prelude::*, Layer, Registry};
 
 #[derive(Debug, Parser)]
 #[command(version = env!("CARGO_PKG_VERSION"))]
@@ -28,25 +33,51 @@ pub enum Cmd {
     Info(cmd::info::Cmd),
     Server(cmd::server::Cmd),
     Add(Box<cmd::add::Cmd>),
+    JoinFilter(cmd::join_filter::Cmd),
+    Monitor(cmd::monitor::Cmd),
 }
 
-fn setup_tracing(settings: &Settings) -> tracing_appender::non_blocking::WorkerGuard {
-    let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stdout());
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+fn setup_tracing(
+    settings: &Settings,
+) -> Result<Option<tracing_appender::non_blocking::WorkerGuard>> {
+    let log = &settings.log;
     let filter = tracing_subscriber::filter::Targets::new()
-        .with_target(env!("CARGO_BIN_NAME"), settings.log.level)
-        .with_target("gateway_rs", settings.log.level)
+        .with_target(env!("CARGO_BIN_NAME"), log.level)
+        .with_target("gateway_rs", log.level)
         .with_default(Level::INFO);
 
-    let stdout_log = tracing_subscriber::fmt::layer()
-        .compact()
-        .with_timer(settings.log.time_formatter())
-        .with_writer(non_blocking);
+    let (log_layer, guard): (BoxedLayer, _) = match log.method {
+        LogMethod::Stdio => {
+            let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stdout());
+            (fmt_layer(log, non_blocking), Some(guard))
+        }
+        LogMethod::Syslog => {
+            let (syslog, guard) = Syslog::new(&log.syslog, env!("CARGO_BIN_NAME"))?;
+            (fmt_layer(log, syslog), Some(guard))
+        }
+        LogMethod::Journald => (Box::new(tracing_journald::layer()?), None),
+    };
 
     tracing_subscriber::registry()
-        .with(stdout_log)
+        .with(log_layer)
         .with(filter)
         .init();
-    guard
+    Ok(guard)
+}
+
+fn fmt_layer<W>(log: &LogSettings, writer: W) -> BoxedLayer
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_timer(log.time_formatter())
+        .with_writer(writer);
+    match log.format {
+        LogFormat::Compact => Box::new(layer.compact()),
+        LogFormat::Json => Box::new(layer.json().flatten_event(true)),
+    }
 }
 
 pub fn main() -> Result {
@@ -62,7 +93,7 @@ pub fn main() -> Result {
     // logger, simply calling `exit()` early prevents any error
     // logging from reaching its destination.
     let retcode = {
-        let _guard = setup_tracing(&settings);
+        let _guard = setup_tracing(&settings)?;
 
         let runtime = tokio::runtime::Builder::new_current_thread()
             .enable_all()
@@ -106,5 +137,7 @@ pub async fn run(cli: Cli, settings: Settings, shutdown_listener: &triggered::Li
         Cmd::Info(cmd) => cmd.run(settings).await,
         Cmd::Add(cmd) => cmd.run(settings).await,
         Cmd::Server(cmd) => cmd.run(shutdown_listener, settings).await,
+        Cmd::JoinFilter(cmd) => cmd.run(settings).await,
+        Cmd::Monitor(cmd) => cmd.run(shutdown_listener, settings).await,
     }
 }
@@ -24,6 +24,10 @@ impl<T: PartialEq> CacheMessage<T> {
     pub fn hold_time(&self) -> Duration {
         self.received.elapsed()
     }
+
+    pub fn received(&self) -> Instant {
+        self.received
+    }
 }
 
 impl<T: PartialEq> Deref for CacheMessage<T> {
@@ -111,6 +115,12 @@ impl<T: PartialEq> MessageCache<T> {
         self.cache.front()
     }
 
+    /// Returns an iterator over the messages in the cache from the oldest
+    /// (first) to the most recent message
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &CacheMessage<T>> {
+        self.cache.iter()
+    }
+
     pub fn len(&self) -> usize {
         self.cache.len()
     }
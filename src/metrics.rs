@@ -0,0 +1,168 @@
+//! Prometheus metrics for the gateway subsystems, served in the Prometheus
+//! text format on an optional local HTTP listener.
+use crate::{Error, Result};
+use futures::TryFutureExt;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, StatusCode,
+};
+use prometheus::{Encoder, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use std::{convert::Infallible, net::SocketAddr, sync::OnceLock};
+use tracing::{info, warn};
+
+const NAMESPACE: &str = "helium_gateway";
+
+struct Metrics {
+    registry: Registry,
+    /// Uplinks by status (received, ignored, filtered, forwarded, upstream)
+    uplinks: IntCounterVec,
+    /// Number of uplinks queued for the packet router
+    router_queue: IntGauge,
+    /// Queued uplinks dropped by the packet router by reason (full, expired,
+    /// deprioritized) and uplink class (join, confirmed, unconfirmed)
+    router_dropped: IntCounterVec,
+    /// Downlinks by receive window (rx1, rx2, upstream) and outcome
+    downlinks: IntCounterVec,
+    /// Beacon transmissions by status (sent, failed)
+    beacons: IntCounterVec,
+    /// Witness reports by status (submitted, failed)
+    witnesses: IntCounterVec,
+    /// Service reconnects by conduit module
+    reconnects: IntCounterVec,
+    /// Region params fetches by result (ok, error)
+    region_fetches: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry =
+            Registry::new_custom(Some(NAMESPACE.to_string()), None).expect("metrics registry");
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            let counter = IntCounterVec::new(Opts::new(name, help), labels).expect("valid counter");
+            registry
+                .register(Box::new(counter.clone()))
+                .expect("unique counter");
+            counter
+        };
+        let metrics = Self {
+            uplinks: counter("uplinks_total", "Uplinks by status", &["status"]),
+            router_dropped: counter(
+                "router_dropped_total",
+                "Queued uplinks dropped by the packet router",
+                &["reason", "class"],
+            ),
+            downlinks: counter(
+                "downlinks_total",
+                "Downlinks by window and outcome",
+                &["window", "outcome"],
+            ),
+            beacons: counter("beacons_total", "Beacon transmissions", &["status"]),
+            witnesses: counter("witnesses_total", "Witness reports", &["status"]),
+            reconnects: counter("reconnects_total", "Service reconnects", &["module"]),
+            region_fetches: counter("region_fetches_total", "Region params fetches", &["result"]),
+            router_queue: IntGauge::new("router_queue", "Uplinks queued for the packet router")
+                .expect("valid gauge"),
+            registry,
+        };
+        metrics
+            .registry
+            .register(Box::new(metrics.router_queue.clone()))
+            .expect("unique gauge");
+        metrics
+    }
+}
+
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+pub fn uplink(status: &str) {
+    metrics().uplinks.with_label_values(&[status]).inc();
+}
+
+pub fn router_queue(depth: usize) {
+    metrics().router_queue.set(depth as i64);
+}
+
+pub fn router_dropped(reason: &str, class: &str, count: usize) {
+    metrics()
+        .router_dropped
+        .with_label_values(&[reason, class])
+        .inc_by(count as u64);
+}
+
+/// Returns the number of dropped queued uplinks of the given reason and class
+#[cfg(test)]
+pub fn router_dropped_count(reason: &str, class: &str) -> u64 {
+    metrics()
+        .router_dropped
+        .with_label_values(&[reason, class])
+        .get()
+}
+
+pub fn downlink(window: &str, outcome: &str) {
+    metrics()
+        .downlinks
+        .with_label_values(&[window, outcome])
+        .inc();
+}
+
+pub fn beacon(status: &str) {
+    metrics().beacons.with_label_values(&[status]).inc();
+}
+
+pub fn witness(status: &str) {
+    metrics().witnesses.with_label_values(&[status]).inc();
+}
+
+pub fn reconnect(module: &str) {
+    metrics().reconnects.with_label_values(&[module]).inc();
+}
+
+pub fn region_fetch(result: &str) {
+    metrics().region_fetches.with_label_values(&[result]).inc();
+}
+
+/// Returns all metrics encoded in the Prometheus text format
+pub fn encode() -> Result<String> {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&metrics().registry.gather(), &mut buffer)
+        .map_err(Error::custom)?;
+    String::from_utf8(buffer).map_err(Error::custom)
+}
+
+/// Serves the metrics on `/metrics` at the given listen address until
+/// shutdown
+pub async fn run(listen_addr: SocketAddr, shutdown: &triggered::Listener) -> Result {
+    info!(listen = %listen_addr, "starting metrics server");
+    let make_service =
+        make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle_request)) });
+    hyper::Server::try_bind(&listen_addr)?
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown.clone())
+        .map_err(Error::from)
+        .await
+}
+
+async fn handle_request(request: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => match encode() {
+            Ok(body) => Response::builder()
+                .header(CONTENT_TYPE, TextEncoder::new().format_type())
+                .body(Body::from(body)),
+            Err(err) => {
+                warn!(%err, "failed to encode metrics");
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::empty())
+            }
+        },
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.expect("valid response"))
+}
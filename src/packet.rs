@@ -3,7 +3,7 @@ use helium_proto::services::{
     poc_lora,
     router::{PacketRouterPacketDownV1, PacketRouterPacketUpV1},
 };
-use lorawan::{Direction, PHYPayloadFrame, MHDR};
+use lorawan::{Direction, PHYPayload, PHYPayloadFrame, MHDR};
 use semtech_udp::{
     pull_resp::{self, PhyData, Time},
     push_data::{self, CRC},
@@ -14,11 +14,20 @@ use std::{
     convert::TryFrom,
     fmt,
     ops::Deref,
+    sync::OnceLock,
     time::{SystemTime, UNIX_EPOCH},
 };
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct PacketUp(PacketRouterPacketUpV1);
+/// An uplink packet along with its lorawan frame, which is parsed once on
+/// first use
+#[derive(Debug, Clone)]
+pub struct PacketUp(PacketRouterPacketUpV1, OnceLock<Option<PHYPayload>>);
+
+impl PartialEq for PacketUp {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct PacketDown(PacketRouterPacketDownV1);
@@ -42,6 +51,12 @@ impl From<&PacketUp> for PacketRouterPacketUpV1 {
     }
 }
 
+impl From<PacketRouterPacketUpV1> for PacketUp {
+    fn from(value: PacketRouterPacketUpV1) -> Self {
+        Self(value, OnceLock::new())
+    }
+}
+
 impl From<PacketRouterPacketDownV1> for PacketDown {
     fn from(value: PacketRouterPacketDownV1) -> Self {
         Self(value)
@@ -84,7 +99,7 @@ impl TryFrom<PacketUp> for poc_lora::LoraWitnessReportReqV1 {
 }
 
 impl PacketUp {
-    pub fn from_rxpk(rxpk: push_data::RxPk, gateway: &PublicKey, region: Region) -> Result<Self> {
+    pub fn from_rxpk(rxpk: &push_data::RxPk, gateway: &PublicKey, region: Region) -> Result<Self> {
         match rxpk.get_crc_status() {
             CRC::OK => (),
             CRC::Disabled => return Err(DecodeError::crc_disabled()),
@@ -107,29 +122,33 @@ impl PacketUp {
             gateway: gateway.into(),
             signature: vec![],
         };
-        Ok(Self(packet))
+        Ok(Self::from(packet))
+    }
+
+    /// Returns the lorawan uplink frame of the payload, if it parses
+    pub fn frame(&self) -> Option<&PHYPayload> {
+        self.1
+            .get_or_init(|| PHYPayload::read(Direction::Uplink, &mut self.payload()).ok())
+            .as_ref()
     }
 
     pub fn is_potential_beacon(&self) -> bool {
-        Self::parse_header(self.payload())
-            .map(|header| {
-                header.mtype() == lorawan::MType::Proprietary
-                    && self.payload().len() == beacon::BEACON_PAYLOAD_SIZE + Self::header_size()
-            })
-            .unwrap_or(false)
+        self.frame().is_some_and(|frame| {
+            frame.mtype() == lorawan::MType::Proprietary
+                && self.payload().len() == beacon::BEACON_PAYLOAD_SIZE + Self::header_size()
+        })
     }
 
     pub fn is_uplink(&self) -> bool {
         // An uplinkable packet is a parseable lorawan uplink frame which is not
-        // a proprietary frame
-        Self::parse_frame(Direction::Uplink, self.payload())
-            .map(|frame| {
-                !matches!(
-                    frame,
-                    PHYPayloadFrame::Proprietary(_) | PHYPayloadFrame::JoinAccept(_),
-                )
-            })
-            .unwrap_or(false)
+        // a proprietary frame. This includes data frames as well as join and
+        // rejoin requests
+        self.frame().is_some_and(|frame| {
+            !matches!(
+                frame.payload,
+                PHYPayloadFrame::Proprietary(_) | PHYPayloadFrame::JoinAccept(_),
+            )
+        })
     }
 
     pub fn payload(&self) -> &[u8] {
@@ -152,6 +171,18 @@ impl PacketUp {
             .map_err(Error::from)
     }
 
+    /// Returns the MAC commands in the FOpts of a data uplink. FPort 0
+    /// payloads are encrypted and are not decoded.
+    pub fn mac_commands(&self) -> Vec<lorawan::MacCommand> {
+        match self.frame().map(|frame| &frame.payload) {
+            Some(PHYPayloadFrame::MACPayload(payload)) => {
+                lorawan::MacCommand::read_all(Direction::Uplink, &mut payload.fhdr.fopts.clone())
+                    .unwrap_or_default()
+            }
+            _ => vec![],
+        }
+    }
+
     pub fn hash(&self) -> Vec<u8> {
         Sha256::digest(&self.0.payload).to_vec()
     }
@@ -173,6 +204,16 @@ impl PacketDown {
         )
     }
 
+    /// Returns the concentrator timestamp of the rx1 window for downlinks
+    /// that are scheduled relative to an uplink.
+    pub fn rx1_timestamp(&self) -> Option<u32> {
+        self.0
+            .rx1
+            .as_ref()
+            .filter(|rx1| !rx1.immediate)
+            .map(|rx1| rx1.timestamp as u32)
+    }
+
     pub fn to_rx2_pull_resp(&self, tx_power: u32) -> Result<Option<pull_resp::TxPk>> {
         let rx2 = match self.0.rx2.as_ref() {
             Some(window) => window,
@@ -293,3 +334,33 @@ pub(crate) mod datarate {
         Ok(rate)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_uplink() {
+        let packet = |payload: &[u8]| {
+            PacketUp::from(PacketRouterPacketUpV1 {
+                payload: payload.to_vec(),
+                ..Default::default()
+            })
+        };
+        let uplink = lorawan::PHYPayload::data_uplink(0x48000001)
+            .fport(1)
+            .payload(&b"uplink"[..])
+            .session_keys(lorawan::AES128([1; 16]), lorawan::AES128([2; 16]))
+            .build()
+            .unwrap();
+        assert!(packet(&Vec::<u8>::try_from(uplink).unwrap()).is_uplink());
+        // Rejoin request type 0
+        assert!(packet(&[
+            0xc0, 0x00, 0x13, 0x00, 0x00, 0x7f, 0x8c, 0x03, 0x20, 0xb0, 0xd5, 0xb3, 0x70, 0x05,
+            0x00, 0x01, 0x02, 0x03, 0x04,
+        ])
+        .is_uplink());
+        let beacon = Vec::<u8>::try_from(lorawan::PHYPayload::proprietary(b"beacon")).unwrap();
+        assert!(!packet(&beacon).is_uplink());
+    }
+}
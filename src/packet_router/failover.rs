@@ -0,0 +1,186 @@
+//! Failover across an ordered list of packet router endpoints.
+//!
+//! The first endpoint is the primary. After a number of consecutive failed
+//! connects or session offers the next endpoint in the list is used. While a
+//! fallback endpoint is active the primary is periodically retried.
+use crate::{settings::RouterSettings, Error, Result};
+use http::Uri;
+use serde::Serialize;
+use std::{fmt, str::FromStr};
+use tokio::time::{self, Duration, Instant};
+
+/// Why the active router endpoint is used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointReason {
+    /// The primary endpoint is used as configured
+    #[default]
+    Primary,
+    /// The previous endpoint failed too many times
+    Failover,
+    /// The primary endpoint is retried after running on a fallback endpoint
+    PrimaryRetry,
+}
+
+impl EndpointReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Primary => "primary",
+            Self::Failover => "failover",
+            Self::PrimaryRetry => "primary_retry",
+        }
+    }
+}
+
+impl fmt::Display for EndpointReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for EndpointReason {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "primary" => Ok(Self::Primary),
+            "failover" => Ok(Self::Failover),
+            "primary_retry" => Ok(Self::PrimaryRetry),
+            other => Err(Error::custom(format!(
+                "invalid endpoint reason \"{other}\""
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Failover {
+    uris: Vec<Uri>,
+    active: usize,
+    reason: EndpointReason,
+    /// Consecutive failures on the active endpoint
+    failures: u32,
+    failover_after: u32,
+    primary_retry: Duration,
+    next_primary_retry: Instant,
+}
+
+impl Failover {
+    pub fn new(settings: &RouterSettings) -> Self {
+        let primary_retry = Duration::from_secs(settings.primary_retry);
+        Self {
+            uris: settings.uris(),
+            active: 0,
+            reason: EndpointReason::Primary,
+            failures: 0,
+            failover_after: settings.failover_after.max(1),
+            primary_retry,
+            next_primary_retry: Instant::now() + primary_retry,
+        }
+    }
+
+    pub fn uri(&self) -> &Uri {
+        &self.uris[self.active]
+    }
+
+    pub fn uris(&self) -> &[Uri] {
+        &self.uris
+    }
+
+    pub fn reason(&self) -> EndpointReason {
+        self.reason
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Whether a fallback endpoint is active
+    pub fn is_fallback(&self) -> bool {
+        self.active != 0
+    }
+
+    /// Records a successful session with the active endpoint
+    pub fn success(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Records a failed connect or session offer with the active endpoint.
+    /// Returns the endpoint to fail over to when the active endpoint failed
+    /// too many times.
+    pub fn failure(&mut self) -> Option<&Uri> {
+        self.failures += 1;
+        if self.failures < self.failover_after || self.uris.len() < 2 {
+            return None;
+        }
+        self.active = (self.active + 1) % self.uris.len();
+        self.reason = EndpointReason::Failover;
+        self.failures = 0;
+        self.next_primary_retry = Instant::now() + self.primary_retry;
+        Some(self.uri())
+    }
+
+    /// Waits until the primary endpoint should be retried
+    pub fn wait_primary_retry(&self) -> time::Sleep {
+        time::sleep_until(self.next_primary_retry)
+    }
+
+    /// Switches back to the primary endpoint, returning its uri
+    pub fn retry_primary(&mut self) -> &Uri {
+        self.active = 0;
+        self.reason = EndpointReason::PrimaryRetry;
+        self.failures = 0;
+        self.uri()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings(failover_after: u32) -> RouterSettings {
+        RouterSettings {
+            uri: Uri::from_static("http://primary:8080"),
+            failover_uris: vec![Uri::from_static("http://fallback:8080")],
+            failover_after,
+            primary_retry: 600,
+            queue: 20,
+            store: None,
+            quota: Default::default(),
+            evict: vec![],
+        }
+    }
+
+    #[test]
+    fn test_failover() {
+        let mut failover = Failover::new(&settings(2));
+        assert_eq!("http://primary:8080/", failover.uri().to_string());
+        assert_eq!(None, failover.failure());
+        failover.success();
+        assert_eq!(None, failover.failure());
+        assert_eq!(
+            Some("http://fallback:8080/".to_string()),
+            failover.failure().map(Uri::to_string)
+        );
+        assert!(failover.is_fallback());
+        assert_eq!(EndpointReason::Failover, failover.reason());
+
+        assert_eq!("http://primary:8080/", failover.retry_primary().to_string());
+        assert_eq!(EndpointReason::PrimaryRetry, failover.reason());
+        assert!(!failover.is_fallback());
+        assert_eq!(None, failover.failure());
+        assert!(failover.failure().is_some());
+        assert!(failover.is_fallback());
+    }
+
+    #[test]
+    fn test_single_endpoint() {
+        let mut settings = settings(1);
+        settings.failover_uris.clear();
+        let mut failover = Failover::new(&settings);
+        assert_eq!(None, failover.failure());
+        assert_eq!(None, failover.failure());
+        assert_eq!(2, failover.failures());
+        assert_eq!(EndpointReason::Primary, failover.reason());
+    }
+}
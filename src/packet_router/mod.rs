@@ -1,25 +1,41 @@
 use crate::{
     gateway,
-    message_cache::{CacheMessage, MessageCache},
+    message_cache::CacheMessage,
+    metrics,
     service::{packet_router::PacketRouterService, Reconnect},
     sync, Base64, PacketUp, PublicKey, Result, Settings,
 };
+use failover::{EndpointReason, Failover};
 use futures::TryFutureExt;
 use helium_proto::services::router::{
     envelope_down_v1, PacketRouterPacketDownV1, PacketRouterPacketUpV1, PacketRouterSessionOfferV1,
 };
 use serde::Serialize;
 use std::{ops::Deref, time::Instant as StdInstant};
-use tokio::time::Duration;
-
+use store::PacketStore;
 use tracing::{debug, info, warn};
 
-const STORE_GC_INTERVAL: Duration = Duration::from_secs(60);
+pub mod failover;
+pub mod queue;
+pub mod store;
+
+pub use queue::{ClassCounts, UplinkClass};
+
+/// Queueing priority of an uplink
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    #[default]
+    Normal,
+    /// Only queued when the queue has room. Low priority uplinks never cause
+    /// other queued uplinks to be dropped.
+    Low,
+}
 
 #[derive(Debug)]
 pub enum Message {
     Uplink {
         packet: PacketUp,
+        priority: Priority,
         received: StdInstant,
     },
     Status(sync::ResponseSender<RouterStatus>),
@@ -27,10 +43,17 @@ pub enum Message {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct RouterStatus {
+    /// The active router endpoint
     #[serde(with = "http_serde::uri")]
     pub uri: http::Uri,
     pub connected: bool,
     pub session_key: Option<PublicKey>,
+    /// All router endpoints in failover order
+    pub endpoints: Vec<String>,
+    /// Why the active endpoint is used
+    pub reason: EndpointReason,
+    /// Consecutive failed connects or session offers on the active endpoint
+    pub failures: u32,
 }
 
 pub type MessageSender = sync::MessageSender<Message>;
@@ -41,8 +64,13 @@ pub fn message_channel() -> (MessageSender, MessageReceiver) {
 }
 
 impl MessageSender {
-    pub async fn uplink(&self, packet: PacketUp, received: StdInstant) {
-        self.send(Message::Uplink { packet, received }).await
+    pub async fn uplink(&self, packet: PacketUp, priority: Priority, received: StdInstant) {
+        self.send(Message::Uplink {
+            packet,
+            priority,
+            received,
+        })
+        .await
     }
 
     pub async fn status(&self) -> Result<RouterStatus> {
@@ -55,7 +83,11 @@ pub struct PacketRouter {
     transmit: gateway::MessageSender,
     service: PacketRouterService,
     reconnect: Reconnect,
-    store: MessageCache<PacketUp>,
+    failover: Failover,
+    /// Whether the router connection was (re)established without a session
+    /// offer having been handled yet
+    awaiting_session: bool,
+    store: PacketStore,
 }
 
 impl PacketRouter {
@@ -63,24 +95,26 @@ impl PacketRouter {
         settings: &Settings,
         messages: MessageReceiver,
         transmit: gateway::MessageSender,
-    ) -> Self {
+    ) -> Result<Self> {
         let router_settings = &settings.router;
-        let service =
-            PacketRouterService::new(router_settings.uri.clone(), settings.keypair.clone());
-        let store = MessageCache::new(router_settings.queue);
+        let failover = Failover::new(router_settings);
+        let service = PacketRouterService::new(failover.uri().clone(), settings.keypair.clone());
+        let store = PacketStore::new(router_settings)?;
         let reconnect = Reconnect::default();
-        Self {
+        Ok(Self {
             service,
             transmit,
             messages,
             store,
             reconnect,
-        }
+            failover,
+            awaiting_session: false,
+        })
     }
 
     #[tracing::instrument(skip_all)]
     pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
-        info!(uri = %self.service.uri, "starting");
+        info!(uri = %self.service.uri, queued = self.store.len(), "starting");
 
         loop {
             tokio::select! {
@@ -89,43 +123,52 @@ impl PacketRouter {
                     return Ok(())
                 },
                 message = self.messages.recv() => match message {
-                    Some(Message::Uplink{packet, received}) =>
-                        if self.handle_uplink(packet, received).await.is_err() {
+                    Some(Message::Uplink{packet, priority, received}) =>
+                        if self.handle_uplink(packet, priority, received).await.is_err() {
                             self.service.disconnect();
                             warn!("router disconnected");
                             self.reconnect.update_next_time(true);
                         },
-                    Some(Message::Status(tx_resp)) => {
-                        let status = RouterStatus {
-                            uri: self.service.uri.clone(),
-                            connected: self.service.is_connected(),
-                            session_key: self.service.session_key().cloned(),
-                        };
-                        tx_resp.send(status)
-                    }
+                    Some(Message::Status(tx_resp)) => tx_resp.send(self.status()),
                     None => warn!("ignoring closed message channel"),
                 },
                 _ = self.reconnect.wait() => {
                     let reconnect_result = self.handle_reconnect().await;
                     self.reconnect.update_next_time(reconnect_result.is_err());
+                    self.awaiting_session = reconnect_result.is_ok();
+                    if reconnect_result.is_err() {
+                        self.handle_failure();
+                    }
                 },
+                _ = self.failover.wait_primary_retry(), if self.failover.is_fallback() =>
+                    self.handle_primary_retry(),
                 router_message = self.service.recv() => match router_message {
                     Ok(envelope_down_v1::Data::Packet(message)) => self.handle_downlink(message).await,
                     Ok(envelope_down_v1::Data::SessionOffer(message)) => {
                         let session_result = self.handle_session_offer(message).await;
+                        self.awaiting_session = false;
                         if session_result.is_ok() {
                             // (Re)set retry count to max to maximize time to
                             // next disconnect from service
                             self.reconnect.retry_count = self.reconnect.max_retries;
+                            self.failover.success();
                         } else {
                             // Failed fto handle session offer, disconnect
                             self.service.disconnect();
                         }
                         self.reconnect.update_next_time(session_result.is_err());
+                        if session_result.is_err() {
+                            self.handle_failure();
+                        }
                     },
                     Err(err) => {
                         warn!(?err, "router error");
                         self.reconnect.update_next_time(true);
+                        // A connection that closes before a session offer
+                        // counts as a failed connect
+                        if std::mem::take(&mut self.awaiting_session) {
+                            self.handle_failure();
+                        }
                     },
                 }
             }
@@ -142,8 +185,62 @@ impl PacketRouter {
             .await
     }
 
-    async fn handle_uplink(&mut self, uplink: PacketUp, received: StdInstant) -> Result {
-        self.store.push_back(uplink, received);
+    fn status(&self) -> RouterStatus {
+        RouterStatus {
+            uri: self.service.uri.clone(),
+            connected: self.service.is_connected(),
+            session_key: self.service.session_key().cloned(),
+            endpoints: self
+                .failover
+                .uris()
+                .iter()
+                .map(|uri| uri.to_string())
+                .collect(),
+            reason: self.failover.reason(),
+            failures: self.failover.failures(),
+        }
+    }
+
+    /// Records a failed connect or session offer, failing over to the next
+    /// router endpoint when the active one failed too often.
+    fn handle_failure(&mut self) {
+        let from = self.service.uri.clone();
+        if let Some(uri) = self.failover.failure().cloned() {
+            warn!(%from, to = %uri, "failing over to router");
+            self.switch_uri(uri);
+        }
+    }
+
+    fn handle_primary_retry(&mut self) {
+        let uri = self.failover.retry_primary().clone();
+        info!(%uri, "retrying primary router");
+        self.switch_uri(uri);
+    }
+
+    /// Switches to the given router endpoint and connects to it soon
+    fn switch_uri(&mut self, uri: http::Uri) {
+        self.service.set_uri(uri);
+        self.awaiting_session = false;
+        self.reconnect.retry_count = 0;
+        self.reconnect.update_next_time(false);
+    }
+
+    async fn handle_uplink(
+        &mut self,
+        uplink: PacketUp,
+        priority: Priority,
+        received: StdInstant,
+    ) -> Result {
+        if priority == Priority::Low && self.store.is_full() {
+            let mut dropped = ClassCounts::default();
+            dropped.add(UplinkClass::from(&uplink));
+            router_dropped("deprioritized", dropped);
+            debug!(uplink = %uplink, "dropped low priority uplink, queue full");
+        } else {
+            let dropped = self.store.push_back(uplink, received);
+            router_dropped("full", dropped);
+        }
+        metrics::router_queue(self.store.len());
         if self.service.is_connected() {
             self.send_waiting_packets().await?;
         }
@@ -162,16 +259,28 @@ impl PacketRouter {
     }
 
     async fn send_waiting_packets(&mut self) -> Result {
-        while let (removed, Some(packet)) = self.store.pop_front(STORE_GC_INTERVAL) {
-            if removed > 0 {
-                info!(removed, "discarded queued packets");
+        loop {
+            let (removed, packet) = self.store.pop_front();
+            if removed.total() > 0 {
+                router_dropped("expired", removed);
+                info!(%removed, "discarded queued packets");
             }
+            let Some(packet) = packet else {
+                break;
+            };
             if let Err(err) = self.send_packet(&packet).await {
                 warn!(%err, "failed to send uplink");
-                self.store.push_front(packet);
+                let dropped = self.store.push_front(packet);
+                if dropped.total() > 0 {
+                    router_dropped("full", dropped);
+                    info!(%dropped, "discarded failed packet, queue full");
+                }
+                metrics::router_queue(self.store.len());
                 return Err(err);
             }
+            metrics::uplink("forwarded");
         }
+        metrics::router_queue(self.store.len());
         Ok(())
     }
 
@@ -183,3 +292,58 @@ impl PacketRouter {
         self.service.send_uplink(uplink).await
     }
 }
+
+fn router_dropped(reason: &str, dropped: ClassCounts) {
+    for (class, count) in dropped.iter() {
+        metrics::router_dropped(reason, class.as_str(), count);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        settings::{RouterQuotaSettings, RouterSettings},
+        Keypair,
+    };
+    use std::{sync::Arc, time::Duration};
+
+    #[tokio::test]
+    async fn test_expired_dropped() {
+        let settings = RouterSettings {
+            uri: http::Uri::from_static("http://127.0.0.1:8080"),
+            failover_uris: vec![],
+            failover_after: 3,
+            primary_retry: 900,
+            queue: 20,
+            store: None,
+            quota: RouterQuotaSettings::default(),
+            evict: vec![UplinkClass::Join, UplinkClass::Unconfirmed],
+        };
+        let mut router = PacketRouter {
+            messages: message_channel().1,
+            transmit: gateway::message_channel().0,
+            service: PacketRouterService::new(settings.uri.clone(), Arc::new(Keypair::new())),
+            reconnect: Reconnect::default(),
+            failover: Failover::new(&settings),
+            awaiting_session: false,
+            store: PacketStore::new(&settings).expect("packet store"),
+        };
+        let received = StdInstant::now() - Duration::from_secs(120);
+        for timestamp in 0..2 {
+            let packet = PacketUp::from(PacketRouterPacketUpV1 {
+                timestamp,
+                payload: vec![0; 20],
+                ..Default::default()
+            });
+            router.store.push_back(packet, received);
+        }
+
+        // Only expired packets are queued, they are all reported as dropped
+        let before = metrics::router_dropped_count("expired", UplinkClass::Unconfirmed.as_str());
+        router.send_waiting_packets().await.expect("send waiting");
+        let after = metrics::router_dropped_count("expired", UplinkClass::Unconfirmed.as_str());
+        assert_eq!(2, after - before);
+        assert!(router.store.is_empty());
+    }
+}
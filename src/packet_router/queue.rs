@@ -0,0 +1,376 @@
+//! In-memory uplink queue for the packet router that is aware of the class of
+//! the queued frames.
+//!
+//! Every class of frame can be limited to a quota of queued packets. When a
+//! class is at its quota the oldest packet of that class is dropped. When the
+//! queue as a whole is full the oldest packet of the first class in the
+//! eviction order that is not ranked above the new packet is dropped, so a
+//! flood of join requests can not push out queued data frames. The same
+//! quotas and eviction order apply to the on-disk queue in the `store` module.
+use crate::{message_cache::CacheMessage, settings::RouterSettings, PacketUp};
+use lorawan::MType;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Class of a queued uplink frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UplinkClass {
+    /// Join and rejoin requests
+    Join,
+    /// Confirmed data uplinks
+    Confirmed,
+    /// Unconfirmed data uplinks and frames that could not be parsed
+    Unconfirmed,
+}
+
+impl UplinkClass {
+    pub const ALL: [UplinkClass; 3] = [Self::Join, Self::Confirmed, Self::Unconfirmed];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Join => "join",
+            Self::Confirmed => "confirmed",
+            Self::Unconfirmed => "unconfirmed",
+        }
+    }
+
+    pub(super) fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl fmt::Display for UplinkClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&PacketUp> for UplinkClass {
+    fn from(packet: &PacketUp) -> Self {
+        match packet.frame().map(|frame| frame.mtype()) {
+            Some(MType::JoinRequest | MType::RejoinRequest) => Self::Join,
+            Some(MType::ConfirmedUp) => Self::Confirmed,
+            _ => Self::Unconfirmed,
+        }
+    }
+}
+
+/// Number of packets of each uplink class
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClassCounts([usize; 3]);
+
+impl ClassCounts {
+    pub fn add(&mut self, class: UplinkClass) {
+        self.0[class.index()] += 1;
+    }
+
+    pub fn remove(&mut self, class: UplinkClass) {
+        self.0[class.index()] -= 1;
+    }
+
+    pub fn set(&mut self, class: UplinkClass, count: usize) {
+        self.0[class.index()] = count;
+    }
+
+    pub fn get(&self, class: UplinkClass) -> usize {
+        self.0[class.index()]
+    }
+
+    pub fn total(&self) -> usize {
+        self.0.iter().sum()
+    }
+
+    /// Returns the classes with a non zero count
+    pub fn iter(&self) -> impl Iterator<Item = (UplinkClass, usize)> + '_ {
+        UplinkClass::ALL
+            .into_iter()
+            .map(|class| (class, self.get(class)))
+            .filter(|(_, count)| *count > 0)
+    }
+}
+
+impl fmt::Display for ClassCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (class, count)) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{class}={count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Per class quotas and eviction order of an uplink queue
+#[derive(Debug, Clone)]
+pub struct ClassPolicy {
+    quotas: [usize; 3],
+    /// Classes in the order packets are evicted from a full queue
+    evict: Vec<UplinkClass>,
+}
+
+impl ClassPolicy {
+    /// Creates the policy from the router settings. Classes without a quota
+    /// are limited to the given number of packets.
+    pub fn new(settings: &RouterSettings, max_packets: usize) -> Self {
+        let quota = |quota: Option<u16>| quota.map_or(max_packets, usize::from);
+        // Classes missing from the eviction order are evicted last
+        let mut evict = settings.evict.clone();
+        let missing: Vec<UplinkClass> = UplinkClass::ALL
+            .into_iter()
+            .filter(|class| !evict.contains(class))
+            .collect();
+        evict.extend(missing);
+        Self {
+            quotas: [
+                quota(settings.quota.join),
+                quota(settings.quota.confirmed),
+                quota(settings.quota.unconfirmed),
+            ],
+            evict,
+        }
+    }
+
+    /// Returns whether the given class has reached its quota
+    pub fn at_quota(&self, class: UplinkClass, queued: &ClassCounts) -> bool {
+        queued.get(class) >= self.quotas[class.index()]
+    }
+
+    /// Returns the class to evict a packet of to make room for a packet of
+    /// the given class, if any.
+    pub fn evict_class(&self, class: UplinkClass, queued: &ClassCounts) -> Option<UplinkClass> {
+        self.evict
+            .iter()
+            .take_while(|evict| **evict != class)
+            .chain(std::iter::once(&class))
+            .find(|evict| queued.get(**evict) > 0)
+            .copied()
+    }
+}
+
+#[derive(Debug)]
+pub struct UplinkQueue {
+    packets: VecDeque<(UplinkClass, CacheMessage<PacketUp>)>,
+    max_packets: usize,
+    policy: ClassPolicy,
+    /// Number of queued packets of each class
+    queued: ClassCounts,
+}
+
+impl UplinkQueue {
+    pub fn new(settings: &RouterSettings) -> Self {
+        let max_packets = settings.queue as usize;
+        Self {
+            packets: VecDeque::new(),
+            max_packets,
+            policy: ClassPolicy::new(settings, max_packets),
+            queued: ClassCounts::default(),
+        }
+    }
+
+    /// Queues a packet, returning the classes of the packets that were dropped
+    /// to make room for it. The packet itself is dropped if there is no queued
+    /// packet it may evict.
+    pub fn push_back(&mut self, packet: PacketUp, received: Instant) -> ClassCounts {
+        let class = UplinkClass::from(&packet);
+        let mut dropped = ClassCounts::default();
+        if self.policy.at_quota(class, &self.queued) {
+            dropped.add(class);
+            // Nothing to replace with a zero quota
+            if !self.remove_oldest(class) {
+                return dropped;
+            }
+        } else if self.len() >= self.max_packets {
+            match self.policy.evict_class(class, &self.queued) {
+                Some(evict) => {
+                    self.remove_oldest(evict);
+                    dropped.add(evict);
+                }
+                None => {
+                    dropped.add(class);
+                    return dropped;
+                }
+            }
+        }
+        self.packets
+            .push_back((class, CacheMessage::new(packet, received)));
+        self.queued.add(class);
+        dropped
+    }
+
+    fn remove_oldest(&mut self, class: UplinkClass) -> bool {
+        let Some(index) = self.packets.iter().position(|(c, _)| *c == class) else {
+            return false;
+        };
+        self.packets.remove(index);
+        self.queued.remove(class);
+        true
+    }
+
+    /// Pushes a packet that failed delivery back on the front of the queue,
+    /// returning the class of the packet if it was dropped because the queue
+    /// is full.
+    pub fn push_front(&mut self, packet: CacheMessage<PacketUp>) -> ClassCounts {
+        let class = UplinkClass::from(&*packet);
+        let mut dropped = ClassCounts::default();
+        if self.len() >= self.max_packets {
+            dropped.add(class);
+            return dropped;
+        }
+        self.packets.push_front((class, packet));
+        self.queued.add(class);
+        dropped
+    }
+
+    /// Removes and returns the oldest packet not held longer than the given
+    /// duration, and the classes of the expired packets that were discarded.
+    pub fn pop_front(
+        &mut self,
+        max_age: Duration,
+    ) -> (ClassCounts, Option<CacheMessage<PacketUp>>) {
+        let mut expired = ClassCounts::default();
+        while let Some((class, packet)) = self.packets.pop_front() {
+            self.queued.remove(class);
+            if packet.hold_time() <= max_age {
+                return (expired, Some(packet));
+            }
+            expired.add(class);
+        }
+        (expired, None)
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.max_packets
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::settings::RouterQuotaSettings;
+    use helium_proto::services::router::PacketRouterPacketUpV1;
+    use lorawan::{PHYPayload, AES128};
+
+    fn settings(queue: u16, quota: RouterQuotaSettings) -> RouterSettings {
+        RouterSettings {
+            uri: http::Uri::from_static("http://127.0.0.1:8080"),
+            failover_uris: vec![],
+            failover_after: 3,
+            primary_retry: 900,
+            queue,
+            store: None,
+            quota,
+            evict: vec![UplinkClass::Join, UplinkClass::Unconfirmed],
+        }
+    }
+
+    fn packet(class: UplinkClass, timestamp: u64) -> PacketUp {
+        let key = || AES128([1; 16]);
+        let frame = match class {
+            UplinkClass::Join => PHYPayload::join_request(1, 2).app_key(key()).build(),
+            UplinkClass::Confirmed => PHYPayload::data_uplink(1)
+                .confirmed()
+                .session_keys(key(), key())
+                .build(),
+            UplinkClass::Unconfirmed => PHYPayload::data_uplink(1)
+                .session_keys(key(), key())
+                .build(),
+        }
+        .expect("frame");
+        PacketUp::from(PacketRouterPacketUpV1 {
+            timestamp,
+            payload: Vec::<u8>::try_from(frame).expect("payload"),
+            ..Default::default()
+        })
+    }
+
+    fn timestamps(queue: &UplinkQueue) -> Vec<u64> {
+        queue.packets.iter().map(|(_, p)| p.timestamp).collect()
+    }
+
+    #[test]
+    fn test_eviction_order() {
+        let mut queue = UplinkQueue::new(&settings(3, RouterQuotaSettings::default()));
+        let now = Instant::now();
+        queue.push_back(packet(UplinkClass::Confirmed, 0), now);
+        queue.push_back(packet(UplinkClass::Unconfirmed, 1), now);
+        queue.push_back(packet(UplinkClass::Join, 2), now);
+
+        // A confirmed uplink evicts the join request first
+        let dropped = queue.push_back(packet(UplinkClass::Confirmed, 3), now);
+        assert_eq!(1, dropped.get(UplinkClass::Join));
+        assert_eq!(vec![0, 1, 3], timestamps(&queue));
+
+        // A join request may not evict data uplinks
+        let dropped = queue.push_back(packet(UplinkClass::Join, 4), now);
+        assert_eq!(1, dropped.get(UplinkClass::Join));
+        assert_eq!(vec![0, 1, 3], timestamps(&queue));
+
+        // An unconfirmed uplink evicts the oldest unconfirmed uplink
+        let dropped = queue.push_back(packet(UplinkClass::Unconfirmed, 5), now);
+        assert_eq!(1, dropped.get(UplinkClass::Unconfirmed));
+        assert_eq!(vec![0, 3, 5], timestamps(&queue));
+    }
+
+    #[test]
+    fn test_quota() {
+        let quota = RouterQuotaSettings {
+            join: Some(1),
+            ..Default::default()
+        };
+        let mut queue = UplinkQueue::new(&settings(10, quota));
+        let now = Instant::now();
+        queue.push_back(packet(UplinkClass::Join, 0), now);
+        queue.push_back(packet(UplinkClass::Unconfirmed, 1), now);
+        let dropped = queue.push_back(packet(UplinkClass::Join, 2), now);
+        assert_eq!(1, dropped.total());
+        assert_eq!(vec![1, 2], timestamps(&queue));
+
+        let (expired, first) = queue.pop_front(Duration::from_secs(60));
+        assert_eq!(0, expired.total());
+        assert_eq!(Some(1), first.map(|packet| packet.timestamp));
+    }
+
+    #[test]
+    fn test_push_front_full() {
+        let mut queue = UplinkQueue::new(&settings(2, RouterQuotaSettings::default()));
+        let now = Instant::now();
+        queue.push_back(packet(UplinkClass::Confirmed, 0), now);
+        queue.push_back(packet(UplinkClass::Unconfirmed, 1), now);
+        let (_, first) = queue.pop_front(Duration::from_secs(60));
+        let first = first.expect("first packet");
+        queue.push_back(packet(UplinkClass::Unconfirmed, 2), now);
+
+        // A failed packet is dropped and reported when the queue filled up
+        let dropped = queue.push_front(first);
+        assert_eq!(1, dropped.get(UplinkClass::Confirmed));
+        assert_eq!(vec![1, 2], timestamps(&queue));
+    }
+
+    #[test]
+    fn test_expired() {
+        let mut queue = UplinkQueue::new(&settings(10, RouterQuotaSettings::default()));
+        let old = Instant::now() - Duration::from_secs(120);
+        queue.push_back(packet(UplinkClass::Join, 0), old);
+        queue.push_back(packet(UplinkClass::Confirmed, 1), old);
+        queue.push_back(packet(UplinkClass::Confirmed, 2), Instant::now());
+        let (expired, next) = queue.pop_front(Duration::from_secs(60));
+        assert_eq!(1, expired.get(UplinkClass::Join));
+        assert_eq!(1, expired.get(UplinkClass::Confirmed));
+        assert_eq!(Some(2), next.map(|packet| packet.timestamp));
+        assert!(queue.is_empty());
+    }
+}
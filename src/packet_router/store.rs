@@ -0,0 +1,796 @@
+//! Uplink queue for the packet router.
+//!
+//! Uplinks are queued in memory by default, with per class quotas and eviction
+//! order as described in the `queue` module. When a store path is configured
+//! uplinks are also written to append-only segment files so that queued
+//! uplinks survive router outages and gateway restarts.
+//!
+//! The on-disk queue keeps a log of segment files per uplink class, in a
+//! subdirectory of the store path named after the class. Packets are delivered
+//! oldest first across the logs, and the same quotas and eviction order apply
+//! as for the in-memory queue. Since both delivery and eviction remove the
+//! oldest packet of a class, packets only ever leave a log at its front.
+//!
+//! Each segment file is a sequence of records, each a little endian `u32`
+//! length followed by the little endian `u64` unix time in milliseconds the
+//! uplink was received at and the protobuf encoded uplink. The number of
+//! records removed from the oldest segment of a log is kept in a cursor file.
+use super::queue::{ClassCounts, ClassPolicy, UplinkClass, UplinkQueue};
+use crate::{
+    message_cache::CacheMessage,
+    settings::{RouterSettings, RouterStoreSettings},
+    PacketUp, Result,
+};
+use helium_proto::services::router::PacketRouterPacketUpV1;
+use prost::Message;
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+/// Maximum age of an uplink held in the in-memory queue
+const MEMORY_MAX_AGE: Duration = Duration::from_secs(60);
+const SEGMENT_EXTENSION: &str = "seg";
+const CURSOR_FILE: &str = "cursor";
+/// Size of the record header (length and received time)
+const RECORD_HEADER_SIZE: usize = 12;
+/// Maximum size of a record. This leaves ample room for an encoded uplink of a
+/// LoRaWAN frame of at most 255 bytes with its metadata and signature, and
+/// bounds what a corrupt length prefix can make a segment read allocate.
+const MAX_RECORD_SIZE: usize = RECORD_HEADER_SIZE + 2048;
+
+#[derive(Debug)]
+pub enum PacketStore {
+    Memory(UplinkQueue),
+    Disk(DiskStore),
+}
+
+impl PacketStore {
+    pub fn new(settings: &RouterSettings) -> Result<Self> {
+        match &settings.store {
+            Some(store_settings) => Ok(Self::Disk(DiskStore::open(
+                store_settings,
+                // The disk store is only limited by size
+                ClassPolicy::new(settings, usize::MAX),
+            )?)),
+            None => Ok(Self::Memory(UplinkQueue::new(settings))),
+        }
+    }
+
+    /// Queues a packet, returning the classes of the packets that were
+    /// dropped to make room for it, or the packet itself if it was dropped.
+    pub fn push_back(&mut self, packet: PacketUp, received: Instant) -> ClassCounts {
+        match self {
+            Self::Memory(queue) => queue.push_back(packet, received),
+            Self::Disk(store) => store.push_back(packet, received),
+        }
+    }
+
+    /// Pushes a packet that failed delivery back on the front of the queue,
+    /// returning the class of the packet if it could not be queued again.
+    pub fn push_front(&mut self, packet: CacheMessage<PacketUp>) -> ClassCounts {
+        match self {
+            Self::Memory(queue) => queue.push_front(packet),
+            Self::Disk(store) => store.push_front(packet),
+        }
+    }
+
+    /// Removes and returns the oldest queued packet that is not past the
+    /// maximum age of the queue, and the classes of the expired packets that
+    /// were discarded.
+    pub fn pop_front(&mut self) -> (ClassCounts, Option<CacheMessage<PacketUp>>) {
+        match self {
+            Self::Memory(queue) => queue.pop_front(MEMORY_MAX_AGE),
+            Self::Disk(store) => store.pop_front(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Memory(queue) => queue.len(),
+            Self::Disk(store) => store.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns whether queueing another packet may drop older packets
+    pub fn is_full(&self) -> bool {
+        match self {
+            Self::Memory(queue) => queue.is_full(),
+            Self::Disk(store) => store.is_full(),
+        }
+    }
+}
+
+/// A disk backed uplink queue with a segment log per uplink class. All queued
+/// uplinks are also kept in memory, bounded by the configured maximum store
+/// size.
+#[derive(Debug)]
+pub struct DiskStore {
+    max_size: u64,
+    max_age: Duration,
+    policy: ClassPolicy,
+    /// Segment logs indexed by uplink class
+    logs: [SegmentLog; 3],
+}
+
+impl DiskStore {
+    pub fn open(settings: &RouterStoreSettings, policy: ClassPolicy) -> Result<Self> {
+        let open_log = |class: UplinkClass| {
+            SegmentLog::open(settings.path.join(class.as_str()), settings.segment_size)
+        };
+        let store = Self {
+            max_size: settings.max_size,
+            max_age: Duration::from_secs(settings.max_age),
+            policy,
+            logs: [
+                open_log(UplinkClass::Join)?,
+                open_log(UplinkClass::Confirmed)?,
+                open_log(UplinkClass::Unconfirmed)?,
+            ],
+        };
+        info!(
+            path = %settings.path.display(),
+            queued = store.len(),
+            "opened uplink store"
+        );
+        Ok(store)
+    }
+
+    fn log(&mut self, class: UplinkClass) -> &mut SegmentLog {
+        &mut self.logs[class.index()]
+    }
+
+    /// Returns the number of queued packets of each class
+    fn queued(&self) -> ClassCounts {
+        let mut queued = ClassCounts::default();
+        for class in UplinkClass::ALL {
+            queued.set(class, self.logs[class.index()].len());
+        }
+        queued
+    }
+
+    /// Returns the total record size of the queued packets
+    fn queued_size(&self) -> u64 {
+        self.logs.iter().map(|log| log.queued_size).sum()
+    }
+
+    /// Returns the class of the oldest queued packet
+    fn oldest_class(&self) -> Option<UplinkClass> {
+        UplinkClass::ALL
+            .into_iter()
+            .filter_map(|class| Some((class, self.logs[class.index()].front()?.received())))
+            .min_by_key(|(_, received)| *received)
+            .map(|(class, _)| class)
+    }
+
+    fn save_cursors(&mut self) {
+        for log in self.logs.iter_mut() {
+            if let Err(err) = log.save_cursor() {
+                warn!(%err, "failed to update uplink store cursor");
+            }
+        }
+    }
+
+    /// Evicts packets by class quota and eviction order until a record of the
+    /// given class and size fits in the store. Returns the classes of the
+    /// dropped packets and whether the record fits. The class of the new
+    /// packet is counted as dropped when it does not fit.
+    fn make_room(&mut self, class: UplinkClass, size: u64) -> (ClassCounts, bool) {
+        let mut dropped = ClassCounts::default();
+        if self.policy.at_quota(class, &self.queued()) {
+            dropped.add(class);
+            // Nothing to replace with a zero quota
+            if self.log(class).pop_front().is_none() {
+                return (dropped, false);
+            }
+        }
+        while self.queued_size() + size > self.max_size {
+            let Some(evict) = self.policy.evict_class(class, &self.queued()) else {
+                dropped.add(class);
+                return (dropped, false);
+            };
+            self.log(evict).pop_front();
+            dropped.add(evict);
+        }
+        (dropped, true)
+    }
+
+    /// Queues a packet, returning the classes of the packets that were
+    /// dropped to make room for it. The packet itself is dropped if there is
+    /// no queued packet it may evict.
+    pub fn push_back(&mut self, packet: PacketUp, received: Instant) -> ClassCounts {
+        let class = UplinkClass::from(&packet);
+        let record = match encode_record(&packet, received) {
+            Ok(record) => record,
+            Err(err) => {
+                warn!(%err, "failed to store uplink");
+                let mut dropped = ClassCounts::default();
+                dropped.add(class);
+                return dropped;
+            }
+        };
+        let (mut dropped, fits) = self.make_room(class, record.len() as u64);
+        if fits {
+            let packet = CacheMessage::new(packet, received);
+            if let Err(err) = self.log(class).append(&record, packet) {
+                warn!(%err, "failed to store uplink");
+                dropped.add(class);
+            }
+        }
+        self.save_cursors();
+        dropped
+    }
+
+    pub fn push_front(&mut self, packet: CacheMessage<PacketUp>) -> ClassCounts {
+        let class = UplinkClass::from(&*packet);
+        let mut dropped = ClassCounts::default();
+        if !self.log(class).push_front(packet) {
+            dropped.add(class);
+        }
+        self.save_cursors();
+        dropped
+    }
+
+    pub fn pop_front(&mut self) -> (ClassCounts, Option<CacheMessage<PacketUp>>) {
+        let mut expired = ClassCounts::default();
+        let mut front = None;
+        while let Some(class) = self.oldest_class() {
+            let Some(packet) = self.log(class).pop_front() else {
+                break;
+            };
+            if packet.hold_time() <= self.max_age {
+                front = Some(packet);
+                break;
+            }
+            expired.add(class);
+        }
+        self.save_cursors();
+        (expired, front)
+    }
+
+    pub fn len(&self) -> usize {
+        self.logs.iter().map(|log| log.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns whether the queued packets take up the maximum store size.
+    /// Delivered records still held in a segment are not counted.
+    pub fn is_full(&self) -> bool {
+        self.queued_size() >= self.max_size
+    }
+}
+
+#[derive(Debug)]
+struct Segment {
+    id: u64,
+    size: u64,
+    /// Number of records in the segment
+    records: usize,
+}
+
+/// A queued packet and where it is stored
+#[derive(Debug)]
+struct StoredPacket {
+    /// Id of the segment holding the packet record
+    segment: u64,
+    /// Size of the packet record
+    size: u64,
+    packet: CacheMessage<PacketUp>,
+}
+
+/// An append-only log of packet records in segment files. Packets are only
+/// removed from the front of the log.
+#[derive(Debug)]
+struct SegmentLog {
+    path: PathBuf,
+    segment_size: u64,
+    /// Segments from oldest to the one being appended to
+    segments: VecDeque<Segment>,
+    /// Number of records removed from the oldest segment
+    removed: usize,
+    packets: VecDeque<StoredPacket>,
+    /// Total record size of the queued packets
+    queued_size: u64,
+    writer: Option<File>,
+    /// Whether the cursor changed since it was last saved
+    cursor_changed: bool,
+}
+
+impl SegmentLog {
+    fn open(path: PathBuf, segment_size: u64) -> Result<Self> {
+        fs::create_dir_all(&path)?;
+        let mut log = Self {
+            path,
+            segment_size,
+            segments: VecDeque::new(),
+            removed: 0,
+            packets: VecDeque::new(),
+            queued_size: 0,
+            writer: None,
+            cursor_changed: false,
+        };
+        log.load()?;
+        Ok(log)
+    }
+
+    fn load(&mut self) -> Result {
+        let mut ids: Vec<u64> = fs::read_dir(&self.path)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension()? != SEGMENT_EXTENSION {
+                    return None;
+                }
+                path.file_stem()?.to_str()?.parse().ok()
+            })
+            .collect();
+        ids.sort_unstable();
+
+        let (cursor_id, cursor_removed) = self.read_cursor();
+        for id in ids {
+            if id < cursor_id {
+                // Fully removed segment that was not deleted
+                fs::remove_file(self.segment_path(id))?;
+                continue;
+            }
+            let (size, packets) = match read_segment(&self.segment_path(id)) {
+                Ok(segment) => segment,
+                Err(err) => {
+                    warn!(id, %err, "discarding unreadable uplink segment");
+                    fs::remove_file(self.segment_path(id))?;
+                    continue;
+                }
+            };
+            self.segments.push_back(Segment {
+                id,
+                size,
+                records: packets.len(),
+            });
+            self.packets
+                .extend(packets.into_iter().map(|(size, packet)| StoredPacket {
+                    segment: id,
+                    size,
+                    packet,
+                }));
+        }
+
+        if self.segments.front().map(|segment| segment.id) == Some(cursor_id) {
+            self.removed = cursor_removed;
+            for _ in 0..cursor_removed {
+                self.packets.pop_front();
+            }
+        }
+        self.queued_size = self.packets.iter().map(|stored| stored.size).sum();
+        // Never append to a segment that may have a truncated record at the
+        // end, always start a new one
+        let next_id = self.segments.back().map_or(0, |segment| segment.id + 1);
+        self.start_segment(next_id)?;
+        self.remove_segments()?;
+        self.save_cursor()
+    }
+
+    fn segment_path(&self, id: u64) -> PathBuf {
+        self.path.join(format!("{id:020}.{SEGMENT_EXTENSION}"))
+    }
+
+    fn read_cursor(&self) -> (u64, usize) {
+        fs::read_to_string(self.path.join(CURSOR_FILE))
+            .ok()
+            .and_then(|cursor| {
+                let (id, removed) = cursor.trim().split_once(' ')?;
+                Some((id.parse().ok()?, removed.parse().ok()?))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Writes the cursor if it changed since it was last saved
+    fn save_cursor(&mut self) -> Result {
+        if !self.cursor_changed {
+            return Ok(());
+        }
+        let id = self.segments.front().map_or(0, |segment| segment.id);
+        let tmp_path = self.path.join(format!("{CURSOR_FILE}.tmp"));
+        fs::write(&tmp_path, format!("{id} {}", self.removed))?;
+        fs::rename(tmp_path, self.path.join(CURSOR_FILE))?;
+        self.cursor_changed = false;
+        Ok(())
+    }
+
+    fn start_segment(&mut self, id: u64) -> Result {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.segment_path(id))?;
+        self.segments.push_back(Segment {
+            id,
+            size: 0,
+            records: 0,
+        });
+        self.writer = Some(file);
+        Ok(())
+    }
+
+    /// Deletes fully removed segments other than the one being appended to
+    fn remove_segments(&mut self) -> Result {
+        while self.segments.len() > 1
+            && self
+                .segments
+                .front()
+                .is_some_and(|segment| segment.records <= self.removed)
+        {
+            if let Some(segment) = self.segments.pop_front() {
+                self.removed -= segment.records;
+                self.cursor_changed = true;
+                fs::remove_file(self.segment_path(segment.id))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the id of the segment holding the record at the given index
+    /// from the start of the oldest segment
+    fn segment_of(&self, index: usize) -> Option<u64> {
+        let mut start = 0;
+        self.segments.iter().find_map(|segment| {
+            start += segment.records;
+            (index < start).then_some(segment.id)
+        })
+    }
+
+    /// Appends a packet record to the current segment
+    fn append(&mut self, record: &[u8], packet: CacheMessage<PacketUp>) -> Result {
+        if self
+            .segments
+            .back()
+            .is_some_and(|segment| segment.size >= self.segment_size)
+        {
+            let next_id = self.segments.back().map_or(0, |segment| segment.id + 1);
+            self.start_segment(next_id)?;
+        }
+        let (Some(writer), Some(segment)) = (self.writer.as_mut(), self.segments.back_mut()) else {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
+        };
+        writer.write_all(record)?;
+        writer.flush()?;
+        segment.size += record.len() as u64;
+        segment.records += 1;
+        self.packets.push_back(StoredPacket {
+            segment: segment.id,
+            size: record.len() as u64,
+            packet,
+        });
+        self.queued_size += record.len() as u64;
+        Ok(())
+    }
+
+    /// Pushes the packet last removed from the log back on its front,
+    /// returning whether it was queued again
+    fn push_front(&mut self, packet: CacheMessage<PacketUp>) -> bool {
+        let Some((index, segment)) = self
+            .removed
+            .checked_sub(1)
+            .and_then(|index| Some((index, self.segment_of(index)?)))
+        else {
+            return false;
+        };
+        self.removed = index;
+        self.cursor_changed = true;
+        let size = packet_record_size(&packet);
+        self.queued_size += size;
+        self.packets.push_front(StoredPacket {
+            segment,
+            size,
+            packet,
+        });
+        true
+    }
+
+    fn front(&self) -> Option<&CacheMessage<PacketUp>> {
+        self.packets.front().map(|stored| &stored.packet)
+    }
+
+    fn pop_front(&mut self) -> Option<CacheMessage<PacketUp>> {
+        // Segments are deleted before removing a packet so the last removed
+        // packet can still be pushed back to its segment
+        if let Err(err) = self.remove_segments() {
+            warn!(%err, "failed to delete removed uplink segments");
+        }
+        let StoredPacket { size, packet, .. } = self.packets.pop_front()?;
+        self.removed += 1;
+        self.queued_size -= size;
+        self.cursor_changed = true;
+        Some(packet)
+    }
+
+    fn len(&self) -> usize {
+        self.packets.len()
+    }
+}
+
+/// Encodes the segment record of a packet
+fn encode_record(packet: &PacketUp, received: Instant) -> Result<Vec<u8>> {
+    let received_millis = SystemTime::now()
+        .checked_sub(received.elapsed())
+        .unwrap_or_else(SystemTime::now)
+        .duration_since(UNIX_EPOCH)?
+        .as_millis() as u64;
+    let encoded = PacketRouterPacketUpV1::from(packet).encode_to_vec();
+    if RECORD_HEADER_SIZE + encoded.len() > MAX_RECORD_SIZE {
+        // Would not be read back from the segment
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidData).into());
+    }
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + encoded.len());
+    record.extend_from_slice(&((encoded.len() + 8) as u32).to_le_bytes());
+    record.extend_from_slice(&received_millis.to_le_bytes());
+    record.extend_from_slice(&encoded);
+    Ok(record)
+}
+
+/// Size of the record of a packet in a segment
+fn packet_record_size(packet: &PacketUp) -> u64 {
+    (RECORD_HEADER_SIZE + PacketRouterPacketUpV1::from(packet).encoded_len()) as u64
+}
+
+/// Reads all complete records from a segment file, returning the size of the
+/// complete records and the record size and packet with its original received
+/// time of each record.
+fn read_segment(path: &Path) -> Result<(u64, Vec<(u64, CacheMessage<PacketUp>)>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut size = 0;
+    let mut packets = vec![];
+    let mut len_buf = [0u8; 4];
+    while reader.read_exact(&mut len_buf).is_ok() {
+        let len = u32::from_le_bytes(len_buf) as usize;
+        if len < 8 || len_buf.len() + len > MAX_RECORD_SIZE {
+            // Corrupt length, the rest of the segment can not be read
+            break;
+        }
+        let mut record = vec![0u8; len];
+        if reader.read_exact(&mut record).is_err() {
+            // Truncated record from an interrupted write
+            break;
+        }
+        let (received_millis, encoded) = record.split_at(8);
+        let received_millis = u64::from_le_bytes(received_millis.try_into().expect("8 byte slice"));
+        let received = UNIX_EPOCH + Duration::from_millis(received_millis);
+        // Keep hold time accurate across restarts by rebasing the original
+        // receive time on the monotonic clock
+        let age = SystemTime::now()
+            .duration_since(received)
+            .unwrap_or_default();
+        let received = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+        let packet = PacketUp::from(PacketRouterPacketUpV1::decode(encoded)?);
+        let record_size = (len_buf.len() + len) as u64;
+        packets.push((record_size, CacheMessage::new(packet, received)));
+        size += record_size;
+    }
+    Ok((size, packets))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::settings::RouterQuotaSettings;
+    use lorawan::{PHYPayload, AES128};
+
+    fn test_settings(name: &str) -> RouterStoreSettings {
+        let path = std::env::temp_dir().join(format!("gateway-rs-{name}-{}", std::process::id()));
+        _ = fs::remove_dir_all(&path);
+        RouterStoreSettings {
+            path,
+            max_size: 10_000,
+            max_age: 3600,
+            segment_size: 200,
+        }
+    }
+
+    fn open(settings: &RouterStoreSettings) -> DiskStore {
+        let router_settings = RouterSettings {
+            uri: http::Uri::from_static("http://127.0.0.1:8080"),
+            failover_uris: vec![],
+            failover_after: 3,
+            primary_retry: 900,
+            queue: 20,
+            store: Some(settings.clone()),
+            quota: RouterQuotaSettings::default(),
+            evict: vec![UplinkClass::Join, UplinkClass::Unconfirmed],
+        };
+        DiskStore::open(settings, ClassPolicy::new(&router_settings, usize::MAX))
+            .expect("disk store")
+    }
+
+    fn packet(timestamp: u64) -> PacketUp {
+        PacketUp::from(PacketRouterPacketUpV1 {
+            timestamp,
+            payload: vec![0; 20],
+            ..Default::default()
+        })
+    }
+
+    fn class_packet(class: UplinkClass, timestamp: u64) -> PacketUp {
+        let key = || AES128([1; 16]);
+        let frame = match class {
+            UplinkClass::Join => PHYPayload::join_request(1, 2).app_key(key()).build(),
+            UplinkClass::Confirmed => PHYPayload::data_uplink(1)
+                .confirmed()
+                .session_keys(key(), key())
+                .build(),
+            UplinkClass::Unconfirmed => PHYPayload::data_uplink(1)
+                .session_keys(key(), key())
+                .build(),
+        }
+        .expect("frame");
+        PacketUp::from(PacketRouterPacketUpV1 {
+            timestamp,
+            payload: Vec::<u8>::try_from(frame).expect("payload"),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_disk_store_restart() {
+        let settings = test_settings("restart");
+        let received = Instant::now() - Duration::from_secs(10);
+        {
+            let mut store = open(&settings);
+            for timestamp in 0..10 {
+                store.push_back(packet(timestamp), received);
+            }
+            let (_, first) = store.pop_front();
+            assert_eq!(Some(0), first.map(|packet| packet.timestamp));
+            let (_, second) = store.pop_front();
+            let second = second.expect("second packet");
+            // Failed delivery
+            store.push_front(second);
+        }
+
+        let mut store = open(&settings);
+        assert_eq!(9, store.len());
+        let (dropped, next) = store.pop_front();
+        let next = next.expect("next packet");
+        assert_eq!(0, dropped.total());
+        assert_eq!(1, next.timestamp);
+        assert!(next.hold_time() >= Duration::from_secs(10));
+        _ = fs::remove_dir_all(&settings.path);
+    }
+
+    #[test]
+    fn test_read_segment_corrupt_length() {
+        let settings = test_settings("corrupt");
+        fs::create_dir_all(&settings.path).expect("store dir");
+        let path = settings.path.join(format!("{:020}.{SEGMENT_EXTENSION}", 0));
+        let received = Instant::now();
+        let mut segment = encode_record(&packet(0), received).expect("record");
+        segment.extend(encode_record(&packet(1), received).expect("record"));
+        let complete_size = segment.len() as u64;
+        // A garbage length prefix followed by some bytes
+        segment.extend_from_slice(&u32::MAX.to_le_bytes());
+        segment.extend_from_slice(&[0xAB; 32]);
+        fs::write(&path, &segment).expect("write segment");
+
+        let (size, packets) = read_segment(&path).expect("read segment");
+        assert_eq!(complete_size, size);
+        let timestamps: Vec<u64> = packets.iter().map(|(_, p)| p.timestamp).collect();
+        assert_eq!(vec![0, 1], timestamps);
+
+        // Records too large to read back are not stored
+        let too_large = PacketUp::from(PacketRouterPacketUpV1 {
+            payload: vec![0; MAX_RECORD_SIZE],
+            ..Default::default()
+        });
+        assert!(encode_record(&too_large, received).is_err());
+        _ = fs::remove_dir_all(&settings.path);
+    }
+
+    #[test]
+    fn test_disk_store_full() {
+        // Room for exactly eight packets
+        let settings = RouterStoreSettings {
+            max_size: (0..8)
+                .map(|timestamp| packet_record_size(&packet(timestamp)))
+                .sum(),
+            ..test_settings("full")
+        };
+        let mut store = open(&settings);
+        for timestamp in 0..8 {
+            store.push_back(packet(timestamp), Instant::now());
+        }
+        assert!(store.is_full());
+        // Delivered records do not count towards the store size
+        for _ in 0..6 {
+            assert!(store.pop_front().1.is_some());
+        }
+        assert!(!store.is_full());
+        _ = fs::remove_dir_all(&settings.path);
+    }
+
+    #[test]
+    fn test_disk_store_evict_past_delivered_segment() {
+        let settings = RouterStoreSettings {
+            max_size: 500,
+            max_age: 60,
+            ..test_settings("evict-delivered")
+        };
+        let expired = Instant::now() - Duration::from_secs(120);
+        let mut store = open(&settings);
+        for timestamp in 1..=8 {
+            store.push_back(packet(timestamp), expired);
+        }
+        for timestamp in 100..103 {
+            store.push_back(packet(timestamp), Instant::now());
+        }
+        // Discards the expired packets across the first segment boundary
+        let (dropped, next) = store.pop_front();
+        assert_eq!(8, dropped.total());
+        assert_eq!(Some(100), next.map(|packet| packet.timestamp));
+
+        // Fill the store until the oldest queued packet is evicted
+        let evicted = (200..300).find(|timestamp| {
+            let dropped = store.push_back(packet(*timestamp), Instant::now());
+            dropped.total() > 0
+        });
+        assert!(evicted.is_some());
+        // Failed delivery
+        let (_, next) = store.pop_front();
+        let next = next.expect("next packet");
+        assert_eq!(102, next.timestamp);
+        store.push_front(next);
+        drop(store);
+
+        let mut store = open(&settings);
+        let (dropped, next) = store.pop_front();
+        assert_eq!(0, dropped.total());
+        assert_eq!(Some(102), next.map(|packet| packet.timestamp));
+        let (_, next) = store.pop_front();
+        assert_eq!(Some(200), next.map(|packet| packet.timestamp));
+        _ = fs::remove_dir_all(&settings.path);
+    }
+
+    #[test]
+    fn test_disk_store_eviction_order() {
+        let size = |class| packet_record_size(&class_packet(class, 1));
+        let settings = RouterStoreSettings {
+            max_size: size(UplinkClass::Confirmed)
+                + size(UplinkClass::Unconfirmed)
+                + size(UplinkClass::Join),
+            ..test_settings("eviction-order")
+        };
+        let start = Instant::now() - Duration::from_secs(10);
+        let received = |timestamp| start + Duration::from_secs(timestamp);
+        let mut store = open(&settings);
+        let mut push =
+            |class, timestamp| store.push_back(class_packet(class, timestamp), received(timestamp));
+        assert_eq!(0, push(UplinkClass::Confirmed, 1).total());
+        assert_eq!(0, push(UplinkClass::Unconfirmed, 2).total());
+        assert_eq!(0, push(UplinkClass::Join, 3).total());
+        // A confirmed uplink evicts the join request first
+        assert_eq!(1, push(UplinkClass::Confirmed, 4).get(UplinkClass::Join));
+        // A join request may not evict data uplinks
+        assert_eq!(1, push(UplinkClass::Join, 5).get(UplinkClass::Join));
+        // An unconfirmed uplink evicts the oldest unconfirmed uplink
+        assert_eq!(
+            1,
+            push(UplinkClass::Unconfirmed, 6).get(UplinkClass::Unconfirmed)
+        );
+        drop(store);
+
+        // Packets are delivered oldest first across classes after a restart
+        let mut store = open(&settings);
+        let timestamps: Vec<u64> = std::iter::from_fn(|| store.pop_front().1)
+            .map(|packet| packet.timestamp)
+            .collect();
+        assert_eq!(vec![1, 4, 6], timestamps);
+        _ = fs::remove_dir_all(&settings.path);
+    }
+}
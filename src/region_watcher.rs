@@ -1,6 +1,14 @@
-use crate::{settings::Settings, KeyedUri, Keypair, Region, RegionParams, Result};
+use crate::{
+    metrics, settings::Settings, KeyedUri, Keypair, PublicKey, Region, RegionParams, Result, Verify,
+};
 use exponential_backoff::Backoff;
-use std::{sync::Arc, time::Duration};
+use helium_proto::{services::iot_config::GatewayRegionParamsResV1, Message};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{sync::watch, time};
 use tracing::{info, warn};
 
@@ -24,11 +32,17 @@ pub struct RegionWatcher {
     default_region: Region,
     request_retry: u32,
     watch: MessageSender,
+    /// File to cache the last fetched region params in
+    region_cache: Option<PathBuf>,
 }
 
 impl RegionWatcher {
     pub fn new(settings: &Settings) -> Self {
-        let default_params = RegionParams::from(settings.region);
+        let default_params = settings
+            .region_cache
+            .as_deref()
+            .and_then(|path| load_cached_params(path, &settings.config.pubkey))
+            .unwrap_or_else(|| RegionParams::from(settings.region));
         let (watch, _) = watch::channel(default_params);
         Self {
             keypair: settings.keypair.clone(),
@@ -37,6 +51,7 @@ impl RegionWatcher {
             request_retry: 1,
             default_region: settings.region,
             watch,
+            region_cache: settings.region_cache.clone(),
         }
     }
 
@@ -97,8 +112,9 @@ impl RegionWatcher {
 
         tokio::select! {
             _ = shutdown.clone() => Ok(None),
-            response = service.region_params(current_region, self.keypair.clone()) => match response.map(Some) {
+            response = service.region_params(current_region, self.keypair.clone()) => match response.and_then(|resp| self.cache_params(resp)).map(Some) {
                 Err(err) => {
+                    metrics::region_fetch("error");
                     warn!(
                         pubkey = %service_uri.pubkey,
                         uri = %service_uri.uri,
@@ -109,6 +125,7 @@ impl RegionWatcher {
                     Err(err)
                 }
                 Ok(other) => {
+                    metrics::region_fetch("ok");
                     let region = other.as_ref().map(|params| params.region).unwrap_or_default();
                     info!(
                         pubkey = %service_uri.pubkey,
@@ -122,4 +139,108 @@ impl RegionWatcher {
         }
         }
     }
+
+    /// Converts a verified region params response and writes it to the region
+    /// cache file if configured.
+    fn cache_params(&self, resp: GatewayRegionParamsResV1) -> Result<RegionParams> {
+        let params = RegionParams::try_from(resp.clone())?;
+        if let Some(path) = &self.region_cache {
+            if let Err(err) = write_cached_params(path, &resp) {
+                warn!(path = %path.display(), %err, "failed to cache region_params");
+            }
+        }
+        Ok(params)
+    }
+}
+
+fn write_cached_params(path: &Path, resp: &GatewayRegionParamsResV1) -> Result {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, resp.encode_to_vec())?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Loads region params cached by a previous run. The cached response is
+/// verified against the config service key before it is used.
+fn load_cached_params(path: &Path, pubkey: &PublicKey) -> Option<RegionParams> {
+    let bytes = fs::read(path).ok()?;
+    let result = GatewayRegionParamsResV1::decode(bytes.as_slice())
+        .map_err(crate::Error::from)
+        .and_then(|resp| {
+            resp.verify(pubkey)?;
+            Ok(RegionParams::try_from(resp)?)
+        });
+    match result {
+        Ok(params) => {
+            info!(path = %path.display(), region = %params.region, "loaded cached region_params");
+            Some(params)
+        }
+        Err(err) => {
+            warn!(path = %path.display(), %err, "ignoring invalid cached region_params");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use helium_crypto::Sign;
+    use helium_proto::{BlockchainRegionParamV1, BlockchainRegionParamsV1};
+
+    fn signed_params(keypair: &Keypair) -> GatewayRegionParamsResV1 {
+        let mut resp = GatewayRegionParamsResV1 {
+            region: helium_proto::Region::Eu868.into(),
+            params: Some(BlockchainRegionParamsV1 {
+                region_params: vec![BlockchainRegionParamV1 {
+                    channel_frequency: 868_100_000,
+                    bandwidth: 125_000,
+                    max_eirp: 160,
+                    ..Default::default()
+                }],
+            }),
+            gain: 12,
+            timestamp: 1_700_000_000,
+            ..Default::default()
+        };
+        resp.signature = keypair
+            .sign(&resp.encode_to_vec())
+            .expect("signed region params");
+        resp
+    }
+
+    #[test]
+    fn test_region_cache() {
+        let dir = std::env::temp_dir().join(format!("gateway-rs-region-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("cache dir");
+        let path = dir.join("region_params.bin");
+        let keypair = Keypair::new();
+        let pubkey = keypair.public_key();
+        let resp = signed_params(&keypair);
+
+        // A missing cache is ignored
+        assert!(load_cached_params(&path, pubkey).is_none());
+
+        // The written cache replaces the file and loads when verified
+        write_cached_params(&path, &resp).expect("write cache");
+        assert!(!path.with_extension("tmp").exists());
+        let expected = RegionParams::try_from(resp.clone()).expect("region params");
+        assert_eq!(Some(expected), load_cached_params(&path, pubkey));
+
+        // A cache signed by another key is ignored
+        assert!(load_cached_params(&path, Keypair::new().public_key()).is_none());
+
+        // As is a cache that was changed after signing
+        let mut tampered = resp.clone();
+        tampered.gain = 60;
+        write_cached_params(&path, &tampered).expect("write cache");
+        assert!(load_cached_params(&path, pubkey).is_none());
+
+        // Or is not a region params response at all
+        fs::write(&path, b"not region params").expect("write cache");
+        assert!(load_cached_params(&path, pubkey).is_none());
+
+        _ = fs::remove_dir_all(&dir);
+    }
 }
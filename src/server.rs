@@ -1,9 +1,10 @@
 use crate::{
     api::LocalServer,
-    beaconer, gateway, packet_router, region_watcher,
+    beaconer, gateway, metrics, packet_router, region_watcher,
     settings::{self, Settings},
-    Result,
+    upstream, Result,
 };
+use std::net::SocketAddr;
 use tracing::info;
 
 #[tracing::instrument(skip_all)]
@@ -11,6 +12,7 @@ pub async fn run(shutdown: &triggered::Listener, settings: &Settings) -> Result
     let (gateway_tx, gateway_rx) = gateway::message_channel();
     let (router_tx, router_rx) = packet_router::message_channel();
     let (beacon_tx, beacon_rx) = beaconer::message_channel();
+    let (upstream_tx, upstream_rx) = upstream::message_channel();
 
     let mut region_watcher = region_watcher::RegionWatcher::new(settings);
     let region_rx = region_watcher.watcher();
@@ -18,7 +20,13 @@ pub async fn run(shutdown: &triggered::Listener, settings: &Settings) -> Result
     let mut beaconer =
         beaconer::Beaconer::new(settings, beacon_rx, region_rx.clone(), gateway_tx.clone());
 
-    let mut router = packet_router::PacketRouter::new(settings, router_rx, gateway_tx.clone());
+    let mut router = packet_router::PacketRouter::new(settings, router_rx, gateway_tx.clone())?;
+
+    let mut upstream = settings
+        .upstream
+        .as_ref()
+        .map(|upstream| upstream::Upstream::new(upstream, upstream_rx, gateway_tx.clone()))
+        .transpose()?;
 
     let mut gateway = gateway::Gateway::new(
         settings,
@@ -26,9 +34,21 @@ pub async fn run(shutdown: &triggered::Listener, settings: &Settings) -> Result
         region_rx.clone(),
         router_tx.clone(),
         beacon_tx,
+        upstream.as_ref().map(|_| upstream_tx),
     )
     .await?;
-    let api = LocalServer::new(region_rx.clone(), router_tx.clone(), settings)?;
+    let api = LocalServer::new(
+        region_rx.clone(),
+        router_tx.clone(),
+        gateway_tx,
+        settings,
+        shutdown,
+    )?;
+    let metrics_addr = settings
+        .metrics
+        .as_ref()
+        .map(SocketAddr::try_from)
+        .transpose()?;
     info!(
         version = %settings::version().to_string(),
         key = %settings.keypair.public_key().to_string(),
@@ -40,6 +60,18 @@ pub async fn run(shutdown: &triggered::Listener, settings: &Settings) -> Result
         gateway.run(shutdown),
         router.run(shutdown),
         api.run(shutdown),
+        async {
+            match upstream.as_mut() {
+                Some(upstream) => upstream.run(shutdown).await,
+                None => Ok(()),
+            }
+        },
+        async {
+            match metrics_addr {
+                Some(listen_addr) => metrics::run(listen_addr, shutdown).await,
+                None => Ok(()),
+            }
+        },
     )
     .map(|_| ())
 }
@@ -1,4 +1,5 @@
 use crate::{
+    metrics,
     service::{CONNECT_TIMEOUT, RPC_TIMEOUT},
     Error, Keypair, PublicKey, Result, Sign,
 };
@@ -146,6 +147,7 @@ impl<U, D, C: ConduitClient<U, D>> ConduitService<U, D, C> {
     }
 
     pub async fn reconnect(&mut self) -> Result {
+        metrics::reconnect(self.module);
         self.disconnect();
         self.connect().await
     }
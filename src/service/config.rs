@@ -1,7 +1,7 @@
 use crate::{
     impl_sign, impl_verify,
     service::{CONNECT_TIMEOUT, RPC_TIMEOUT},
-    KeyedUri, Keypair, Region, RegionParams, Result, Sign, Verify,
+    KeyedUri, Keypair, Region, Result, Sign, Verify,
 };
 use helium_proto::{
     services::{
@@ -33,11 +33,13 @@ impl ConfigService {
         }
     }
 
+    /// Fetches the region parameters for the gateway. The returned response
+    /// has been verified to be signed by the config service.
     pub async fn region_params(
         &mut self,
         default_region: Region,
         keypair: Arc<Keypair>,
-    ) -> Result<RegionParams> {
+    ) -> Result<GatewayRegionParamsResV1> {
         let mut req = GatewayRegionParamsReqV1 {
             region: default_region.into(),
             address: keypair.public_key().to_vec(),
@@ -47,7 +49,7 @@ impl ConfigService {
 
         let resp = self.client.region_params(req).await?.into_inner();
         resp.verify(&self.uri.pubkey)?;
-        Ok(RegionParams::try_from(resp)?)
+        Ok(resp)
     }
 }
 
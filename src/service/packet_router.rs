@@ -103,6 +103,13 @@ impl PacketRouterService {
         Self(ConduitService::new("packet_router", uri, client, keypair))
     }
 
+    /// Switches to the given router uri. The current connection, if any, is
+    /// dropped.
+    pub fn set_uri(&mut self, uri: Uri) {
+        self.0.disconnect();
+        self.0.uri = uri;
+    }
+
     pub async fn send_uplink(&mut self, mut msg: PacketRouterPacketUpV1) -> Result {
         self.session_sign(&mut msg).await?;
         let msg = EnvelopeUpV1 {
@@ -1,8 +1,17 @@
-use crate::{api::GatewayStakingMode, KeyedUri, Keypair, PublicKey, Region, Result};
+use crate::{
+    api::GatewayStakingMode, packet_router::UplinkClass, uplink_filter::EuiPattern, KeyedUri,
+    Keypair, PublicKey, Region, Result,
+};
 use config::{Config, Environment, File};
 use http::uri::Uri;
+use lorawan::{DevAddr, NetID};
 use serde::Deserialize;
-use std::{fmt, path::Path, str::FromStr, sync::Arc};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
 
 pub fn version() -> semver::Version {
     semver::Version::parse(env!("CARGO_PKG_VERSION")).expect("unable to parse version")
@@ -11,14 +20,39 @@ pub fn version() -> semver::Version {
 /// Settings are all the configuration parameters the service needs to operate.
 #[derive(Debug, Deserialize)]
 pub struct Settings {
-    /// The listen address to use for listening for the semtech UDP packet forwarder.
+    /// The listen address to use for listening for the packet forwarder.
     /// Default "127.0.0.1:1680"
     #[serde(default = "default_listen")]
     pub listen: String,
-    /// The listening network port for the grpc / jsonrpc API.
-    /// Default 4467
+    /// The protocol spoken by the packet forwarder on the listen address.
+    /// Default semtech
+    #[serde(default)]
+    pub forwarder: ForwarderProtocol,
+    /// The gateway id (MAC) of the packet forwarder to use for beacon
+    /// transmissions when more than one packet forwarder is connected, for
+    /// example "AA555A0000000000". Defaults to the longest connected packet
+    /// forwarder.
+    #[serde(default)]
+    pub primary_forwarder: Option<String>,
+    /// The listening network port for the grpc / jsonrpc API. Supports a
+    /// port number, a full ip:port listen address or a "unix:/path" unix
+    /// domain socket. Default 4467
     #[serde(default = "default_api")]
     pub api: ListenAddress,
+    /// Ownership and permissions of the api socket file when the api listens
+    /// on a unix domain socket
+    #[serde(default)]
+    pub api_socket: ApiSocketSettings,
+    /// The listen address to serve prometheus metrics on. Supports a port
+    /// number or a full ip:port listen address. Disabled if not set.
+    #[serde(default)]
+    pub metrics: Option<ListenAddress>,
+    /// The listen address to serve the local api as json over http on.
+    /// Supports a port number, which listens on loopback only, or a full
+    /// ip:port listen address. Disabled if not set. Any local user can reach
+    /// this listener, regardless of the `api_socket` permissions.
+    #[serde(default)]
+    pub http_api: Option<ListenAddress>,
     /// The location of the keypair binary file for the gateway. If the keyfile
     /// is not found there a new one is generated and saved in that location.
     pub keypair: Arc<Keypair>,
@@ -32,6 +66,11 @@ pub struct Settings {
     /// asserted location/region is fetched.
     #[serde(default)]
     pub region: Region,
+    /// File to cache the last region parameters fetched from the config
+    /// service in. Cached parameters are verified and used on startup until
+    /// the config service can be reached. Disabled if not set.
+    #[serde(default)]
+    pub region_cache: Option<PathBuf>,
     /// Log settings
     pub log: LogSettings,
     /// The config service to use for region and other config settings
@@ -40,6 +79,13 @@ pub struct Settings {
     pub router: RouterSettings,
     /// Proof-of-coverage (PoC) settings.
     pub poc: PocSettings,
+    /// Uplink filters applied before uplinks are queued for the packet router
+    #[serde(default)]
+    pub filter: FilterSettings,
+    /// Secondary Semtech UDP network server to forward matching uplinks to.
+    /// Disabled if not set.
+    #[serde(default)]
+    pub upstream: Option<UpstreamSettings>,
 }
 
 /// Settings for log method and level to be used by the running service.
@@ -50,12 +96,50 @@ pub struct LogSettings {
 
     /// Whehter to show timestamps in the stdio output stream (default false)
     pub timestamp: bool,
+
+    /// Where to send log output (default stdio)
+    #[serde(default)]
+    pub method: LogMethod,
+
+    /// Format of the log lines for the stdio and syslog methods (default
+    /// compact)
+    #[serde(default)]
+    pub format: LogFormat,
+
+    /// The syslog socket to log to with the syslog method. Either a unix
+    /// socket path or "udp://host:port" for a remote syslog server. Default
+    /// "/dev/log"
+    #[serde(default = "default_syslog")]
+    pub syslog: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogMethod {
+    /// Log to standard output
+    #[default]
+    Stdio,
+    /// Log RFC 5424 messages to a syslog socket
+    Syslog,
+    /// Log to the systemd journal with structured fields
+    Journald,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Compact text lines with key=value fields
+    #[default]
+    Compact,
+    /// One JSON object per line with the event fields flattened in
+    Json,
 }
 
 impl LogSettings {
     pub fn time_formatter(&self) -> impl tracing_subscriber::fmt::time::FormatTime {
         TimeFormatter {
-            timestamp: self.timestamp,
+            // Syslog messages carry their own timestamp
+            timestamp: self.timestamp && self.method == LogMethod::Stdio,
             time: tracing_subscriber::fmt::time(),
         }
     }
@@ -98,10 +182,205 @@ pub struct PocSettings {
 /// Settings for packet routing
 #[derive(Debug, Deserialize, Clone)]
 pub struct RouterSettings {
+    /// The primary packet router
     #[serde(with = "http_serde::uri")]
     pub uri: Uri,
+    /// Packet routers to fail over to, in order, when the active router
+    /// fails. Default none
+    #[serde(default, deserialize_with = "deserialize_uris")]
+    pub failover_uris: Vec<Uri>,
+    /// Number of consecutive failed connects or session offers after which
+    /// the next router is used. Default 3
+    #[serde(default = "default_router_failover_after")]
+    pub failover_after: u32,
+    /// Interval in seconds to retry the primary router at while a failover
+    /// router is used. Default 15 minutes
+    #[serde(default = "default_router_primary_retry")]
+    pub primary_retry: u64,
     // Maximum number of packets to queue up for the packet router
     pub queue: u16,
+    /// Optional on-disk queue for uplinks that survives router outages and
+    /// gateway restarts. When set the in-memory `queue` limit is not used, the
+    /// quotas and eviction order apply to the on-disk queue.
+    #[serde(default)]
+    pub store: Option<RouterStoreSettings>,
+    /// Maximum number of queued packets of each uplink class
+    #[serde(default)]
+    pub quota: RouterQuotaSettings,
+    /// Order of uplink classes to drop packets of when the queue is full.
+    /// Default join, unconfirmed, confirmed
+    #[serde(default = "default_router_evict")]
+    pub evict: Vec<UplinkClass>,
+}
+
+impl RouterSettings {
+    /// Returns the primary and failover router uris in failover order
+    pub fn uris(&self) -> Vec<Uri> {
+        std::iter::once(&self.uri)
+            .chain(self.failover_uris.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+fn deserialize_uris<'de, D>(deserializer: D) -> std::result::Result<Vec<Uri>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|uri| uri.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Per uplink class limits for the packet router queue. A class without a
+/// quota is only limited by the queue size.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct RouterQuotaSettings {
+    /// Join and rejoin requests
+    #[serde(default)]
+    pub join: Option<u16>,
+    /// Confirmed data uplinks
+    #[serde(default)]
+    pub confirmed: Option<u16>,
+    /// Unconfirmed data uplinks
+    #[serde(default)]
+    pub unconfirmed: Option<u16>,
+}
+
+/// Settings for filtering uplinks. Data uplinks are filtered by the network
+/// that issued the device address, join requests by the rules in the join
+/// rules file.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct FilterSettings {
+    /// Toml file with JoinEUI and DevEUI rules for join requests. The file is
+    /// reloaded through the local api. Join requests are not filtered if not
+    /// set.
+    #[serde(default)]
+    pub join_rules: Option<PathBuf>,
+    /// NetIDs to forward data uplinks of. When this and `dev_addrs` are both
+    /// empty all data uplinks not explicitly denied are forwarded.
+    #[serde(default)]
+    pub net_ids: Vec<NetID>,
+    /// DevAddr ranges to forward data uplinks of
+    #[serde(default)]
+    pub dev_addrs: Vec<DevAddrRange>,
+    /// NetIDs to never forward data uplinks of
+    #[serde(default)]
+    pub deny_net_ids: Vec<NetID>,
+    /// DevAddr ranges to never forward data uplinks of
+    #[serde(default)]
+    pub deny_dev_addrs: Vec<DevAddrRange>,
+}
+
+/// An inclusive range of device addresses, written as "48000000-48FFFFFF"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DevAddrRange {
+    pub start: DevAddr,
+    pub end: DevAddr,
+}
+
+impl DevAddrRange {
+    pub fn contains(&self, dev_addr: DevAddr) -> bool {
+        (self.start..=self.end).contains(&dev_addr)
+    }
+}
+
+impl FromStr for DevAddrRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<DevAddr>()
+                .map_err(|_| format!("invalid devaddr range \"{s}\""))
+        };
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        if start > end {
+            return Err(format!("empty devaddr range \"{s}\""));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl<'de> Deserialize<'de> for DevAddrRange {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Settings for a secondary Semtech UDP (GWMP) network server. Matching
+/// uplinks are forwarded to it in addition to the packet router, and its
+/// downlinks are transmitted like packet router downlinks.
+#[derive(Debug, Deserialize, Clone)]
+pub struct UpstreamSettings {
+    /// Host and port of the network server, for example "127.0.0.1:1700"
+    pub host: String,
+    /// Gateway id (MAC) to present to the network server, for example
+    /// "AA555A0000000001"
+    pub gateway_id: String,
+    /// NetIDs to forward data uplinks of
+    #[serde(default)]
+    pub net_ids: Vec<NetID>,
+    /// DevAddr ranges to forward data uplinks of
+    #[serde(default)]
+    pub dev_addrs: Vec<DevAddrRange>,
+    /// JoinEUI (AppEUI) patterns to forward join requests of
+    #[serde(default)]
+    pub join_euis: Vec<EuiPattern>,
+}
+
+/// Settings for the socket file of a local api unix domain socket
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiSocketSettings {
+    /// File permissions of the socket file. Default 0o660
+    #[serde(default = "default_api_socket_mode")]
+    pub mode: u32,
+    /// User id to own the socket file. Unchanged if not set
+    #[serde(default)]
+    pub uid: Option<u32>,
+    /// Group id to own the socket file. Unchanged if not set
+    #[serde(default)]
+    pub gid: Option<u32>,
+}
+
+impl Default for ApiSocketSettings {
+    fn default() -> Self {
+        Self {
+            mode: default_api_socket_mode(),
+            uid: None,
+            gid: None,
+        }
+    }
+}
+
+/// Settings for the on-disk packet router queue
+#[derive(Debug, Deserialize, Clone)]
+pub struct RouterStoreSettings {
+    /// Directory to keep the queue segment files in
+    pub path: PathBuf,
+    /// Maximum size of the queued packets in bytes. Packets are dropped by
+    /// quota and eviction order when the queue grows past this size. Segments
+    /// are deleted once all their packets are delivered or dropped, so up to
+    /// two segments per uplink class may be used on top of this size. Default
+    /// 10 MiB
+    #[serde(default = "default_store_max_size")]
+    pub max_size: u64,
+    /// Maximum age in seconds of a queued packet. Older packets are discarded
+    /// instead of delivered. Default 1 hour
+    #[serde(default = "default_store_max_age")]
+    pub max_age: u64,
+    /// Size in bytes after which a new segment file is started. Default 1 MiB
+    #[serde(default = "default_store_segment_size")]
+    pub segment_size: u64,
 }
 
 impl Settings {
@@ -138,6 +417,10 @@ impl Settings {
     }
 }
 
+fn default_syslog() -> String {
+    "/dev/log".to_string()
+}
+
 fn default_listen() -> String {
     "127.0.0.1:1680".to_string()
 }
@@ -146,6 +429,39 @@ fn default_api() -> ListenAddress {
     ListenAddress::Address("127.0.0.1:4467".to_string())
 }
 
+fn default_api_socket_mode() -> u32 {
+    0o660
+}
+
+fn default_router_failover_after() -> u32 {
+    3
+}
+
+fn default_router_primary_retry() -> u64 {
+    // every 15 minutes
+    15 * 60
+}
+
+fn default_router_evict() -> Vec<UplinkClass> {
+    vec![
+        UplinkClass::Join,
+        UplinkClass::Unconfirmed,
+        UplinkClass::Confirmed,
+    ]
+}
+
+fn default_store_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_store_max_age() -> u64 {
+    3600
+}
+
+fn default_store_segment_size() -> u64 {
+    1024 * 1024
+}
+
 fn default_poc_interval() -> u64 {
     // every 6 hours
     6 * 3600
@@ -190,6 +506,17 @@ impl fmt::Display for StakingMode {
     }
 }
 
+/// Packet forwarder protocols the gateway can listen for
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwarderProtocol {
+    /// Semtech GWMP over UDP
+    #[default]
+    Semtech,
+    /// LoRa Basics Station LNS protocol over websockets
+    Station,
+}
+
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum ListenAddress {
@@ -197,6 +524,16 @@ pub enum ListenAddress {
     Address(String),
 }
 
+impl ListenAddress {
+    /// Returns the socket file path of a "unix:/path" listen address
+    pub fn unix_path(&self) -> Option<PathBuf> {
+        match self {
+            Self::Address(address) => address.strip_prefix("unix:").map(PathBuf::from),
+            Self::Port(_) => None,
+        }
+    }
+}
+
 impl TryFrom<&ListenAddress> for std::net::SocketAddr {
     type Error = crate::Error;
     fn try_from(value: &ListenAddress) -> std::result::Result<Self, Self::Error> {
@@ -324,5 +661,24 @@ mod test {
                 .expect("uri from addr string"),
             Uri::from_static("http://1.2.3.4:4468")
         );
+
+        // Unix domain socket form
+        assert_eq!(
+            ListenAddress::Address("unix:/run/helium/api.sock".to_string()).unix_path(),
+            Some(PathBuf::from("/run/helium/api.sock"))
+        );
+        assert_eq!(ListenAddress::Port(4468).unix_path(), None);
+    }
+
+    #[test]
+    fn dev_addr_range() {
+        let range: DevAddrRange = "48000000-48FFFFFF".parse().expect("devaddr range");
+        assert!(range.contains(DevAddr(0x4800_0001)));
+        assert!(!range.contains(DevAddr(0x4900_0000)));
+        let range: DevAddrRange = "48000001".parse().expect("single devaddr");
+        assert!(range.contains(DevAddr(0x4800_0001)));
+        assert!(!range.contains(DevAddr(0x4800_0002)));
+        assert!("48FFFFFF-48000000".parse::<DevAddrRange>().is_err());
+        assert!("nope".parse::<DevAddrRange>().is_err());
     }
 }
@@ -57,6 +57,12 @@ impl<T> MessageSender<T> {
         _ = self.0.send(msg).await
     }
 
+    /// Sends a message if the channel has room for it, returning whether the
+    /// message was sent
+    pub fn try_send(&self, msg: T) -> bool {
+        self.0.try_send(msg).is_ok()
+    }
+
     pub async fn request<R, F>(&self, req: F) -> Result<R>
     where
         F: FnOnce(ResponseSender<R>) -> T,
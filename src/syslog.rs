@@ -0,0 +1,150 @@
+//! RFC 5424 syslog output for tracing.
+//!
+//! Each formatted tracing event is sent as a single syslog message over a Unix
+//! datagram socket (like `/dev/log`) or over UDP to a remote syslog server.
+//! Messages are handed to a `tracing_appender` worker thread so a slow or
+//! blocked syslog socket never stalls the runtime.
+use crate::Result;
+use std::{
+    io::{self, Write},
+    net::UdpSocket,
+    os::unix::net::UnixDatagram,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::{Level, Metadata};
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Prefix of a syslog address to send to a remote syslog server over UDP
+const UDP_PREFIX: &str = "udp://";
+/// The syslog daemon facility
+const FACILITY_DAEMON: u8 = 3;
+/// RFC 5424 value for an unknown header field
+const NIL_VALUE: &str = "-";
+
+#[derive(Debug)]
+enum Transport {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+impl Write for Transport {
+    /// Sends the given buffer as a single datagram
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // There is nowhere to report a failure to log
+        _ = match self {
+            Self::Unix(socket) => socket.send(buf),
+            Self::Udp(socket) => socket.send(buf),
+        };
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Syslog {
+    writer: NonBlocking,
+    hostname: String,
+    app_name: String,
+    pid: u32,
+}
+
+impl Syslog {
+    /// Connects to the syslog socket at the given address. The address is
+    /// either a Unix socket path or `udp://host:port` for a remote server.
+    /// Queued messages are sent until the returned guard is dropped.
+    pub fn new(address: &str, app_name: &str) -> Result<(Self, WorkerGuard)> {
+        let transport = match address.strip_prefix(UDP_PREFIX) {
+            Some(remote) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(remote)?;
+                Transport::Udp(socket)
+            }
+            None => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(address)?;
+                Transport::Unix(socket)
+            }
+        };
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|hostname| hostname.trim().to_string())
+            .ok()
+            .filter(|hostname| !hostname.is_empty())
+            .unwrap_or_else(|| NIL_VALUE.to_string());
+        let (writer, guard) = tracing_appender::non_blocking(transport);
+        let syslog = Self {
+            writer,
+            hostname,
+            app_name: app_name.to_string(),
+            pid: std::process::id(),
+        };
+        Ok((syslog, guard))
+    }
+
+    fn message(&self, level: &Level) -> SyslogMessage<'_> {
+        let severity = match *level {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            Level::DEBUG | Level::TRACE => 7,
+        };
+        SyslogMessage {
+            syslog: self,
+            priority: FACILITY_DAEMON * 8 + severity,
+            buf: vec![],
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = SyslogMessage<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.message(&Level::INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.message(meta.level())
+    }
+}
+
+/// A single syslog message which is queued for sending when dropped
+pub struct SyslogMessage<'a> {
+    syslog: &'a Syslog,
+    priority: u8,
+    buf: Vec<u8>,
+}
+
+impl Write for SyslogMessage<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogMessage<'_> {
+    fn drop(&mut self) {
+        let msg = String::from_utf8_lossy(&self.buf);
+        let msg = msg.trim_end();
+        if msg.is_empty() {
+            return;
+        }
+        let timestamp = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_else(|_| NIL_VALUE.to_string());
+        let syslog = self.syslog;
+        let message = format!(
+            "<{}>1 {timestamp} {} {} {} - - {msg}",
+            self.priority, syslog.hostname, syslog.app_name, syslog.pid
+        );
+        // Each write is queued as one message for the worker to send
+        _ = syslog.writer.clone().write_all(message.as_bytes());
+    }
+}
@@ -0,0 +1,374 @@
+//! Regulatory transmit budget tracking.
+//!
+//! Tracks the airtime of transmissions per regulatory sub-band of the current
+//! region to enforce sub-band duty-cycle limits (like ETSI limits in EU868)
+//! and the maximum dwell time of a single transmission (like in AS923).
+use crate::{Region, RegionParams};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use semtech_udp::pull_resp::TxPk;
+use std::{
+    collections::VecDeque,
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Window duty-cycle limits are measured over
+const DUTY_CYCLE_WINDOW: Duration = Duration::from_secs(3600);
+/// Maximum dwell time of a single transmission in regions that limit it
+const MAX_DWELL_TIME: Duration = Duration::from_millis(400);
+/// Preamble length used by LoRaWAN
+const PREAMBLE_SYMBOLS: f64 = 8.0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RegulatoryError {
+    #[error("airtime {airtime:?} exceeds dwell time {max:?}")]
+    DwellTime { airtime: Duration, max: Duration },
+    #[error("duty cycle exhausted for sub-band {min_hz}-{max_hz} Hz, available in {wait:?}")]
+    DutyCycle {
+        min_hz: u64,
+        max_hz: u64,
+        wait: Duration,
+    },
+    #[error("frequency {0} Hz outside of regulatory sub-bands")]
+    Frequency(u64),
+    #[error("unable to compute airtime")]
+    Airtime,
+}
+
+/// A regulatory sub-band with its duty-cycle limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SubBand {
+    pub min_hz: u64,
+    pub max_hz: u64,
+    /// Maximum fraction of time the sub-band may be transmitted on
+    pub duty_cycle: f64,
+}
+
+impl SubBand {
+    const fn new(min_hz: u64, max_hz: u64, duty_cycle: f64) -> Self {
+        Self {
+            min_hz,
+            max_hz,
+            duty_cycle,
+        }
+    }
+
+    fn contains(&self, frequency: u64) -> bool {
+        (self.min_hz..=self.max_hz).contains(&frequency)
+    }
+
+    fn budget(&self) -> Duration {
+        DUTY_CYCLE_WINDOW.mul_f64(self.duty_cycle)
+    }
+}
+
+/// ETSI EN 300 220 sub-bands as used by the LoRaWAN EU868 regional parameters
+const EU868_SUB_BANDS: &[SubBand] = &[
+    SubBand::new(863_000_000, 865_000_000, 0.001),
+    SubBand::new(865_000_000, 868_000_000, 0.01),
+    SubBand::new(868_000_000, 868_600_000, 0.01),
+    SubBand::new(868_700_000, 869_200_000, 0.001),
+    SubBand::new(869_400_000, 869_650_000, 0.1),
+    SubBand::new(869_700_000, 870_000_000, 0.01),
+];
+
+const EU433_SUB_BANDS: &[SubBand] = &[SubBand::new(433_050_000, 434_790_000, 0.1)];
+
+/// Duty-cycle limited sub-bands by the frequencies of the default channels
+/// that identify a channel plan using them. Regions sharing the spectrum
+/// without duty-cycle limits, like IN865, do not use these channels.
+const DUTY_CYCLE_SUB_BANDS: &[(RangeInclusive<u64>, &[SubBand])] = &[
+    (868_000_000..=868_600_000, EU868_SUB_BANDS),
+    (433_050_000..=434_790_000, EU433_SUB_BANDS),
+];
+
+/// Regulatory rules for a region
+#[derive(Debug, Clone, Default)]
+struct Rules {
+    sub_bands: &'static [SubBand],
+    dwell_time: Option<Duration>,
+}
+
+impl From<&RegionParams> for Rules {
+    fn from(region_params: &RegionParams) -> Self {
+        let sub_bands = DUTY_CYCLE_SUB_BANDS
+            .iter()
+            .find(|(default_channels, _)| {
+                channel_frequencies(region_params)
+                    .iter()
+                    .any(|frequency| default_channels.contains(frequency))
+            })
+            .map(|(_, sub_bands)| *sub_bands)
+            .unwrap_or_default();
+        let dwell_time = region_params
+            .region
+            .to_string()
+            .starts_with("AS923")
+            .then_some(MAX_DWELL_TIME);
+        Self {
+            sub_bands,
+            dwell_time,
+        }
+    }
+}
+
+fn channel_frequencies(region_params: &RegionParams) -> Vec<u64> {
+    region_params
+        .params
+        .iter()
+        .map(|param| param.channel_frequency)
+        .collect()
+}
+
+/// Airtime recorded for a transmission. A reservation is released when the
+/// packet forwarder did not transmit the packet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reservation {
+    sub_band: Option<usize>,
+    start: Instant,
+    airtime: Duration,
+}
+
+impl Reservation {
+    pub fn airtime(&self) -> Duration {
+        self.airtime
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    region: Option<Region>,
+    channels: Vec<u64>,
+    rules: Rules,
+    /// Transmissions in the duty-cycle window as (sub-band index, start,
+    /// airtime)
+    history: VecDeque<(usize, Instant, Duration)>,
+}
+
+/// Shared transmit budget for the gateway. Clones share the same budget so
+/// downlink tasks can reserve airtime for fallback windows.
+#[derive(Debug, Clone, Default)]
+pub struct TxBudget(Arc<Mutex<Inner>>);
+
+impl TxBudget {
+    pub fn new(region_params: &RegionParams) -> Self {
+        let budget = Self::default();
+        budget.set_region(region_params);
+        budget
+    }
+
+    /// Updates the rules to the given region parameters. The transmit history
+    /// is reset when the region or its channels change.
+    pub fn set_region(&self, region_params: &RegionParams) {
+        let mut inner = self.0.lock().expect("tx budget lock");
+        let channels = channel_frequencies(region_params);
+        if inner.region.as_ref() == Some(&region_params.region) && inner.channels == channels {
+            return;
+        }
+        inner.rules = Rules::from(region_params);
+        inner.region = Some(region_params.region);
+        inner.channels = channels;
+        inner.history.clear();
+    }
+
+    /// Checks the given packet against the regional limits and records its
+    /// airtime if it can be transmitted now. The returned reservation should
+    /// be released if the packet is not transmitted.
+    pub fn reserve(&self, txpk: &TxPk) -> Result<Reservation, RegulatoryError> {
+        let (frequency, airtime) = tx_airtime(txpk).ok_or(RegulatoryError::Airtime)?;
+        self.reserve_at(frequency, airtime, Instant::now())
+    }
+
+    /// Returns the airtime of a reservation for a packet that was not
+    /// transmitted to the budget
+    pub fn release(&self, reservation: Reservation) {
+        let Some(index) = reservation.sub_band else {
+            return;
+        };
+        let mut inner = self.0.lock().expect("tx budget lock");
+        let entry = (index, reservation.start, reservation.airtime);
+        if let Some(position) = inner.history.iter().position(|recorded| *recorded == entry) {
+            inner.history.remove(position);
+        }
+    }
+
+    fn reserve_at(
+        &self,
+        frequency: u64,
+        airtime: Duration,
+        now: Instant,
+    ) -> Result<Reservation, RegulatoryError> {
+        let mut inner = self.0.lock().expect("tx budget lock");
+        if let Some(max) = inner.rules.dwell_time.filter(|max| airtime > *max) {
+            return Err(RegulatoryError::DwellTime { airtime, max });
+        }
+        if inner.rules.sub_bands.is_empty() {
+            return Ok(Reservation {
+                sub_band: None,
+                start: now,
+                airtime,
+            });
+        }
+        let (index, sub_band) = inner
+            .rules
+            .sub_bands
+            .iter()
+            .enumerate()
+            .find(|(_, sub_band)| sub_band.contains(frequency))
+            .map(|(index, sub_band)| (index, *sub_band))
+            .ok_or(RegulatoryError::Frequency(frequency))?;
+
+        while inner
+            .history
+            .front()
+            .is_some_and(|(_, start, _)| now.duration_since(*start) >= DUTY_CYCLE_WINDOW)
+        {
+            inner.history.pop_front();
+        }
+        let band_history = || inner.history.iter().filter(|(band, _, _)| *band == index);
+        let used: Duration = band_history().map(|(_, _, airtime)| *airtime).sum();
+        let budget = sub_band.budget();
+        if used + airtime > budget {
+            // Wait until enough of the oldest transmissions leave the window
+            let mut excess = used + airtime - budget;
+            let wait = band_history()
+                .find_map(|(_, start, airtime)| {
+                    excess = excess.saturating_sub(*airtime);
+                    excess
+                        .is_zero()
+                        .then(|| (*start + DUTY_CYCLE_WINDOW).saturating_duration_since(now))
+                })
+                .unwrap_or(DUTY_CYCLE_WINDOW);
+            return Err(RegulatoryError::DutyCycle {
+                min_hz: sub_band.min_hz,
+                max_hz: sub_band.max_hz,
+                wait,
+            });
+        }
+        inner.history.push_back((index, now, airtime));
+        Ok(Reservation {
+            sub_band: Some(index),
+            start: now,
+            airtime,
+        })
+    }
+}
+
+/// Returns the frequency in Hz and the time on air of the given packet
+fn tx_airtime(txpk: &TxPk) -> Option<(u64, Duration)> {
+    let txpk = serde_json::to_value(txpk).ok()?;
+    let frequency = crate::packet::to_hz(txpk["freq"].as_f64()?);
+    let (sf, bw) = txpk["datr"]
+        .as_str()?
+        .strip_prefix("SF")?
+        .split_once("BW")?;
+    let coding_rate = match txpk["codr"].as_str()? {
+        "4/5" => 1,
+        "4/6" => 2,
+        "4/7" => 3,
+        "4/8" => 4,
+        _ => return None,
+    };
+    let payload_len = STANDARD.decode(txpk["data"].as_str()?).ok()?.len();
+    // LoRaWAN downlinks are sent without a payload CRC
+    let crc = txpk["ipol"].as_bool() == Some(false) && txpk["ncrc"].as_bool() != Some(true);
+    let airtime = time_on_air(
+        sf.parse().ok()?,
+        bw.parse::<u32>().ok()? * 1000,
+        coding_rate,
+        payload_len,
+        crc,
+    );
+    Some((frequency, airtime))
+}
+
+/// LoRa time on air for an explicit header packet (Semtech AN1200.13)
+pub fn time_on_air(
+    spreading_factor: u32,
+    bandwidth_hz: u32,
+    coding_rate: u32,
+    payload_len: usize,
+    crc: bool,
+) -> Duration {
+    let sf = spreading_factor as f64;
+    let symbol_time = 2f64.powf(sf) / bandwidth_hz as f64;
+    // Low data rate optimization is required for symbols over 16 ms
+    let low_data_rate = if symbol_time > 0.016 { 1.0 } else { 0.0 };
+    let preamble_time = (PREAMBLE_SYMBOLS + 4.25) * symbol_time;
+    let payload_bits = 8.0 * payload_len as f64 - 4.0 * sf + 28.0 + if crc { 16.0 } else { 0.0 };
+    let payload_symbols = 8.0
+        + ((payload_bits / (4.0 * (sf - 2.0 * low_data_rate))).ceil() * (coding_rate as f64 + 4.0))
+            .max(0.0);
+    let micros = (preamble_time + payload_symbols * symbol_time) * 1_000_000.0;
+    Duration::from_micros(micros.round() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_time_on_air() {
+        assert_eq!(
+            Duration::from_micros(56_576),
+            time_on_air(7, 125_000, 1, 20, true)
+        );
+        assert_eq!(
+            Duration::from_micros(2_465_792),
+            time_on_air(12, 125_000, 1, 51, true)
+        );
+    }
+
+    #[test]
+    fn test_duty_cycle() {
+        let budget = TxBudget::default();
+        budget.0.lock().expect("tx budget lock").rules = Rules {
+            sub_bands: EU868_SUB_BANDS,
+            dwell_time: None,
+        };
+        let now = Instant::now();
+        let airtime = Duration::from_secs(12);
+        // 1% sub-band allows 36s per hour
+        for _ in 0..3 {
+            budget
+                .reserve_at(868_100_000, airtime, now)
+                .expect("within duty cycle");
+        }
+        assert!(matches!(
+            budget.reserve_at(868_300_000, airtime, now),
+            Err(RegulatoryError::DutyCycle { wait, .. }) if wait == DUTY_CYCLE_WINDOW
+        ));
+        // Other sub-bands have their own budget
+        budget
+            .reserve_at(869_525_000, airtime, now)
+            .expect("other sub-band");
+        budget
+            .reserve_at(868_100_000, airtime, now + DUTY_CYCLE_WINDOW)
+            .expect("window passed");
+    }
+
+    #[test]
+    fn test_release() {
+        let budget = TxBudget::default();
+        budget.0.lock().expect("tx budget lock").rules = Rules {
+            sub_bands: EU868_SUB_BANDS,
+            dwell_time: None,
+        };
+        let now = Instant::now();
+        let airtime = Duration::from_secs(12);
+        for _ in 0..3 {
+            let reservation = budget
+                .reserve_at(868_100_000, airtime, now)
+                .expect("within duty cycle");
+            budget.release(reservation);
+        }
+        // Released airtime does not count against the sub-band
+        for _ in 0..3 {
+            budget
+                .reserve_at(868_100_000, airtime, now)
+                .expect("released budget");
+        }
+        assert!(budget.reserve_at(868_100_000, airtime, now).is_err());
+    }
+}
@@ -0,0 +1,422 @@
+//! Filters applied to uplinks before they are queued for the packet router.
+//!
+//! Data uplinks are filtered by the network that issued their device address
+//! as configured in the filter settings. Join requests are matched against an
+//! optional rules file of JoinEUI (AppEUI) and DevEUI patterns, which can be
+//! reloaded at runtime. The rules file is a toml file with a list of rules:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "vendor"
+//! action = "deprioritize"
+//! app_eui = ["70B3D57ED0000000-70B3D57ED00000FF"]
+//! dev_eui = ["0004A3*"]
+//! ```
+//!
+//! A rule matches a join request when every EUI list it has contains a
+//! matching pattern. The first matching rule decides what happens to the join
+//! request.
+use crate::{
+    packet_router::Priority,
+    settings::{DevAddrRange, FilterSettings},
+    Error, PacketUp, Result,
+};
+use lorawan::{DevAddr, JoinRequest, NetID, PHYPayloadFrame};
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::PathBuf, str::FromStr};
+
+/// Length of an EUI in hex digits
+const EUI_DIGITS: usize = 16;
+
+/// What to do with an uplink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Forward(Priority),
+    Drop,
+}
+
+/// Action for join requests matching a join rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinAction {
+    /// Do not forward matching join requests
+    Drop,
+    /// Only queue matching join requests when the packet router queue has
+    /// room, never dropping other packets for them
+    Deprioritize,
+}
+
+impl fmt::Display for JoinAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Drop => f.write_str("drop"),
+            Self::Deprioritize => f.write_str("deprioritize"),
+        }
+    }
+}
+
+impl FromStr for JoinAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "drop" => Ok(Self::Drop),
+            "deprioritize" => Ok(Self::Deprioritize),
+            other => Err(Error::custom(format!("invalid join action \"{other}\""))),
+        }
+    }
+}
+
+/// A pattern matching an EUI. Written as an exact EUI "0004A30B001C0530", a
+/// prefix of hex digits followed by a star "0004A3*", or an inclusive range
+/// "0004A30B00000000-0004A30BFFFFFFFF".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EuiPattern {
+    Exact(u64),
+    Prefix { value: u64, digits: usize },
+    Range { start: u64, end: u64 },
+}
+
+impl EuiPattern {
+    pub fn matches(&self, eui: u64) -> bool {
+        match *self {
+            Self::Exact(value) => eui == value,
+            Self::Prefix { value, digits } => {
+                digits == 0 || eui >> (4 * (EUI_DIGITS - digits)) == value
+            }
+            Self::Range { start, end } => (start..=end).contains(&eui),
+        }
+    }
+}
+
+impl fmt::Display for EuiPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Exact(value) => write!(f, "{value:016X}"),
+            Self::Prefix { digits: 0, .. } => f.write_str("*"),
+            Self::Prefix { value, digits } => write!(f, "{value:0digits$X}*"),
+            Self::Range { start, end } => write!(f, "{start:016X}-{end:016X}"),
+        }
+    }
+}
+
+impl FromStr for EuiPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::custom(format!("invalid eui pattern \"{s}\""));
+        let parse = |v: &str| {
+            let v = v.trim();
+            if v.len() != EUI_DIGITS {
+                return Err(invalid());
+            }
+            u64::from_str_radix(v, 16).map_err(|_| invalid())
+        };
+        if let Some(prefix) = s.trim().strip_suffix('*') {
+            if prefix.len() > EUI_DIGITS {
+                return Err(invalid());
+            }
+            let value = if prefix.is_empty() {
+                0
+            } else {
+                u64::from_str_radix(prefix, 16).map_err(|_| invalid())?
+            };
+            return Ok(Self::Prefix {
+                value,
+                digits: prefix.len(),
+            });
+        }
+        match s.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    return Err(invalid());
+                }
+                Ok(Self::Range { start, end })
+            }
+            None => parse(s).map(Self::Exact),
+        }
+    }
+}
+
+impl Serialize for EuiPattern {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for EuiPattern {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// A join request filter rule with the number of join requests it matched
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JoinRule {
+    pub name: String,
+    pub action: JoinAction,
+    #[serde(default)]
+    pub app_eui: Vec<EuiPattern>,
+    #[serde(default)]
+    pub dev_eui: Vec<EuiPattern>,
+    #[serde(default, skip_deserializing)]
+    pub matches: u64,
+}
+
+impl JoinRule {
+    pub fn matches(&self, join_request: &JoinRequest) -> bool {
+        let any = |patterns: &[EuiPattern], eui| {
+            patterns.is_empty() || patterns.iter().any(|pattern| pattern.matches(eui))
+        };
+        any(&self.app_eui, join_request.app_eui) && any(&self.dev_eui, join_request.dev_eui)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JoinRulesFile {
+    #[serde(default)]
+    rule: Vec<JoinRule>,
+}
+
+/// The join rules file and the currently loaded join rules
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JoinFilterStatus {
+    pub path: Option<PathBuf>,
+    pub rules: Vec<JoinRule>,
+}
+
+/// Decides which uplinks are forwarded to the packet router, and with what
+/// priority.
+#[derive(Debug, Default)]
+pub struct UplinkFilter {
+    settings: FilterSettings,
+    join_rules: Vec<JoinRule>,
+}
+
+impl UplinkFilter {
+    /// Constructs a filter from the given settings, loading join rules from
+    /// the configured rules file.
+    pub fn new(settings: &FilterSettings) -> Result<Self> {
+        let mut filter = Self {
+            settings: settings.clone(),
+            join_rules: vec![],
+        };
+        filter.reload()?;
+        Ok(filter)
+    }
+
+    /// Reloads the join rules from the configured rules file. Match counts
+    /// are kept for rules that have the same name after the reload. The
+    /// current rules are kept if the file can not be loaded.
+    pub fn reload(&mut self) -> Result {
+        let Some(path) = &self.settings.join_rules else {
+            return Ok(());
+        };
+        let mut rules = load_join_rules(path)?;
+        for rule in rules.iter_mut() {
+            if let Some(current) = self.join_rules.iter().find(|r| r.name == rule.name) {
+                rule.matches = current.matches;
+            }
+        }
+        self.join_rules = rules;
+        Ok(())
+    }
+
+    pub fn join_status(&self) -> JoinFilterStatus {
+        JoinFilterStatus {
+            path: self.settings.join_rules.clone(),
+            rules: self.join_rules.clone(),
+        }
+    }
+
+    /// Returns what to do with the given uplink. Uplinks that are not data
+    /// frames or join requests are always forwarded.
+    pub fn check(&mut self, packet: &PacketUp) -> Verdict {
+        match packet.frame().map(|frame| &frame.payload) {
+            Some(PHYPayloadFrame::MACPayload(mac_payload))
+                if !self.allows_dev_addr(mac_payload.dev_addr()) =>
+            {
+                Verdict::Drop
+            }
+            Some(PHYPayloadFrame::JoinRequest(join_request)) => self.check_join(join_request),
+            _ => Verdict::Forward(Priority::Normal),
+        }
+    }
+
+    fn check_join(&mut self, join_request: &JoinRequest) -> Verdict {
+        match self
+            .join_rules
+            .iter_mut()
+            .find(|rule| rule.matches(join_request))
+        {
+            Some(rule) => {
+                rule.matches += 1;
+                match rule.action {
+                    JoinAction::Drop => Verdict::Drop,
+                    JoinAction::Deprioritize => Verdict::Forward(Priority::Low),
+                }
+            }
+            None => Verdict::Forward(Priority::Normal),
+        }
+    }
+
+    fn allows_dev_addr(&self, dev_addr: DevAddr) -> bool {
+        let settings = &self.settings;
+        let matches = |net_ids: &[NetID], ranges: &[DevAddrRange]| {
+            net_ids.iter().any(|net_id| net_id.contains(dev_addr))
+                || ranges.iter().any(|range| range.contains(dev_addr))
+        };
+        if matches(&settings.deny_net_ids, &settings.deny_dev_addrs) {
+            return false;
+        }
+        (settings.net_ids.is_empty() && settings.dev_addrs.is_empty())
+            || matches(&settings.net_ids, &settings.dev_addrs)
+    }
+}
+
+fn load_join_rules(path: &std::path::Path) -> Result<Vec<JoinRule>> {
+    let file: JoinRulesFile = config::Config::builder()
+        .add_source(config::File::from(path).format(config::FileFormat::Toml))
+        .build()
+        .and_then(|config| config.try_deserialize())?;
+    for rule in &file.rule {
+        if rule.app_eui.is_empty() && rule.dev_eui.is_empty() {
+            return Err(Error::custom(format!(
+                "join rule \"{}\" has no eui patterns",
+                rule.name
+            )));
+        }
+    }
+    Ok(file.rule)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter(settings: &str) -> UplinkFilter {
+        let settings: FilterSettings = config::Config::builder()
+            .add_source(config::File::from_str(settings, config::FileFormat::Toml))
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .expect("filter settings");
+        UplinkFilter::new(&settings).expect("uplink filter")
+    }
+
+    #[test]
+    fn dev_addr_filter() {
+        let helium = DevAddr(0x7800_0012);
+        let other = DevAddr(0x4800_0001);
+
+        let all = filter("");
+        assert!(all.allows_dev_addr(helium));
+        assert!(all.allows_dev_addr(other));
+
+        let allow = filter(r#"net_ids = ["00003C"]"#);
+        assert!(allow.allows_dev_addr(helium));
+        assert!(!allow.allows_dev_addr(other));
+
+        let deny = filter(
+            r#"
+            dev_addrs = ["48000000-48FFFFFF"]
+            deny_dev_addrs = ["48000001"]
+            "#,
+        );
+        assert!(!deny.allows_dev_addr(helium));
+        assert!(!deny.allows_dev_addr(other));
+        assert!(deny.allows_dev_addr(DevAddr(0x4800_0002)));
+    }
+
+    #[test]
+    fn eui_pattern() {
+        let exact: EuiPattern = "0004A30B001C0530".parse().expect("exact");
+        assert!(exact.matches(0x0004_A30B_001C_0530));
+        assert!(!exact.matches(0x0004_A30B_001C_0531));
+
+        let prefix: EuiPattern = "0004A3*".parse().expect("prefix");
+        assert!(prefix.matches(0x0004_A30B_001C_0530));
+        assert!(!prefix.matches(0x0004_A40B_001C_0530));
+        assert_eq!("0004A3*", prefix.to_string());
+        assert!("*".parse::<EuiPattern>().expect("any").matches(42));
+
+        let range: EuiPattern = "70B3D57ED0000000-70B3D57ED00000FF".parse().expect("range");
+        assert!(range.matches(0x70B3_D57E_D000_00FF));
+        assert!(!range.matches(0x70B3_D57E_D000_0100));
+
+        assert!("0004A3".parse::<EuiPattern>().is_err());
+        assert!("70B3D57ED00000FF-70B3D57ED0000000"
+            .parse::<EuiPattern>()
+            .is_err());
+    }
+
+    #[test]
+    fn join_rules() {
+        let path =
+            std::env::temp_dir().join(format!("gateway-rs-join-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            [[rule]]
+            name = "noisy"
+            action = "drop"
+            dev_eui = ["0004A3*"]
+
+            [[rule]]
+            name = "vendor"
+            action = "deprioritize"
+            app_eui = ["70B3D57ED0000000-70B3D57ED00000FF"]
+            "#,
+        )
+        .expect("rules file");
+        let mut filter = filter(&format!("join_rules = {:?}", path.display().to_string()));
+        let join = |app_eui, dev_eui| JoinRequest {
+            app_eui,
+            dev_eui,
+            dev_nonce: [0, 0],
+        };
+
+        assert_eq!(
+            Verdict::Drop,
+            filter.check_join(&join(0x70B3_D57E_D000_0001, 0x0004_A30B_001C_0530))
+        );
+        assert_eq!(
+            Verdict::Forward(Priority::Low),
+            filter.check_join(&join(0x70B3_D57E_D000_0001, 1))
+        );
+        assert_eq!(
+            Verdict::Forward(Priority::Normal),
+            filter.check_join(&join(1, 1))
+        );
+
+        std::fs::write(
+            &path,
+            r#"
+            [[rule]]
+            name = "vendor"
+            action = "drop"
+            app_eui = ["70B3D57ED0000000-70B3D57ED00000FF"]
+            "#,
+        )
+        .expect("rules file");
+        filter.reload().expect("reload");
+        let status = filter.join_status();
+        assert_eq!(1, status.rules.len());
+        assert_eq!(1, status.rules[0].matches);
+        assert_eq!(JoinAction::Drop, status.rules[0].action);
+
+        std::fs::write(&path, "[[rule]]\nname = \"empty\"\naction = \"drop\"\n")
+            .expect("rules file");
+        assert!(filter.reload().is_err());
+        assert_eq!("vendor", filter.join_status().rules[0].name);
+        _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,280 @@
+//! Forwarding of uplinks to a secondary Semtech UDP (GWMP) network server.
+//!
+//! The gateway acts as a packet forwarder towards the upstream network server
+//! (for example a local ChirpStack). Data uplinks whose device address is in
+//! one of the configured networks and join requests with a matching JoinEUI
+//! are re-emitted to the upstream server, alongside the packet router.
+//! Downlinks from the upstream server are transmitted through the gateway
+//! downlink path, on the packet forwarder that received the uplink. When the
+//! upstream server can not be resolved or its connection fails the error is
+//! logged and the connection retried with backoff, dropping matching uplinks
+//! in the meantime.
+use crate::{
+    forwarder::TxError, gateway, metrics, service::Reconnect, settings::UpstreamSettings, sync,
+    Error, PacketUp, Result,
+};
+use lorawan::{DevAddr, PHYPayloadFrame};
+use semtech_udp::{
+    client_runtime::{DownlinkRequest, Event, UdpRuntime},
+    push_data, tx_ack, MacAddress,
+};
+use tracing::{debug, info, warn};
+
+#[derive(Debug)]
+pub enum Message {
+    Uplink(Box<PacketUp>, push_data::RxPk),
+}
+
+pub type MessageSender = sync::MessageSender<Message>;
+pub type MessageReceiver = sync::MessageReceiver<Message>;
+
+pub fn message_channel() -> (MessageSender, MessageReceiver) {
+    sync::message_channel(20)
+}
+
+impl MessageSender {
+    /// Offers a received uplink and the packet forwarder packet it was
+    /// received in for forwarding to the upstream network server. The uplink
+    /// is dropped, returning false, if the upstream task is not keeping up.
+    pub fn uplink(&self, packet: PacketUp, rxpk: push_data::RxPk) -> bool {
+        self.try_send(Message::Uplink(Box::new(packet), rxpk))
+    }
+}
+
+pub struct Upstream {
+    settings: UpstreamSettings,
+    gateway_id: MacAddress,
+    messages: MessageReceiver,
+    gateway: gateway::MessageSender,
+}
+
+impl Upstream {
+    pub fn new(
+        settings: &UpstreamSettings,
+        messages: MessageReceiver,
+        gateway: gateway::MessageSender,
+    ) -> Result<Self> {
+        Ok(Self {
+            gateway_id: settings.gateway_id()?,
+            settings: settings.clone(),
+            messages,
+            gateway,
+        })
+    }
+
+    pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
+        info!(host = %self.settings.host, gateway_id = %self.gateway_id, "starting");
+        let mut reconnect = Reconnect::default();
+        loop {
+            if let Err(err) = self.forward(shutdown, &mut reconnect).await {
+                warn!(%err, "upstream unavailable");
+            }
+            reconnect.update_next_time(true);
+            // Keep receiving uplinks while waiting to reconnect so an
+            // unavailable upstream network server never holds up the gateway
+            loop {
+                tokio::select! {
+                    _ = shutdown.clone() => {
+                        info!("shutting down");
+                        return Ok(())
+                    },
+                    _ = reconnect.wait() => break,
+                    message = self.messages.recv() => self.discard(message),
+                }
+            }
+        }
+    }
+
+    /// Connects to the upstream network server and forwards uplinks and
+    /// downlinks until shutdown or the connection fails
+    async fn forward(
+        &mut self,
+        shutdown: &triggered::Listener,
+        reconnect: &mut Reconnect,
+    ) -> Result {
+        let lookup = tokio::net::lookup_host(self.settings.host.clone());
+        tokio::pin!(lookup);
+        let host = loop {
+            tokio::select! {
+                _ = shutdown.clone() => return Ok(()),
+                addrs = &mut lookup => break addrs?.next().ok_or_else(|| {
+                    Error::custom(format!("no address for upstream {}", self.settings.host))
+                })?,
+                message = self.messages.recv() => self.discard(message),
+            }
+        };
+        info!(%host, "connecting");
+        let (uplinks, mut events, runtime) = UdpRuntime::new(self.gateway_id, host)
+            .await
+            .map_err(Box::new)?;
+        let runtime_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(err) = runtime.run(runtime_shutdown).await {
+                warn!(%err, "upstream runtime stopped");
+            }
+        });
+        reconnect.retry_count = 0;
+
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => return Ok(()),
+                message = self.messages.recv() => match message {
+                    Some(Message::Uplink(packet, rxpk)) => {
+                        debug!(uplink = %packet, "forwarding uplink upstream");
+                        let push_data = push_data::Packet::from_rxpk(self.gateway_id, rxpk);
+                        if uplinks.send(push_data.into()).await.is_err() {
+                            warn!("upstream runtime closed");
+                            continue;
+                        }
+                        metrics::uplink("upstream");
+                    }
+                    None => warn!("ignoring closed message channel"),
+                },
+                event = events.recv() => match event {
+                    Some(Event::DownlinkRequest(request)) => self.handle_downlink(request),
+                    Some(Event::LostConnection) => warn!(%host, "lost upstream connection"),
+                    Some(Event::Reconnected) => info!(%host, "reconnected upstream"),
+                    Some(Event::UnableToParseUdpFrame(err, buf)) => {
+                        warn!(raw_bytes = ?buf, "ignoring upstream udp parsing error {err}")
+                    }
+                    None => return Err(Error::custom("upstream runtime closed")),
+                },
+            }
+        }
+    }
+
+    /// Drops an uplink received while the upstream network server is
+    /// unavailable
+    fn discard(&self, message: Option<Message>) {
+        match message {
+            Some(Message::Uplink(packet, _)) => {
+                debug!(uplink = %packet, "upstream unavailable, dropping uplink")
+            }
+            None => warn!("ignoring closed message channel"),
+        }
+    }
+
+    /// Transmits a downlink from the upstream network server and reports the
+    /// outcome back to it
+    fn handle_downlink(&self, request: DownlinkRequest) {
+        let gateway = self.gateway.clone();
+        tokio::spawn(async move {
+            let result = gateway
+                .transmit(request.txpk().clone())
+                .await
+                .unwrap_or(Err(TxError::Disconnected));
+            let ack = match result {
+                Ok(_) | Err(TxError::AdjustedTransmitPower(_, _)) => request.ack().await,
+                Err(err) => {
+                    debug!(%err, "upstream downlink not transmitted");
+                    request.nack(tx_ack_error(&err)).await
+                }
+            };
+            if let Err(err) = ack {
+                warn!(%err, "failed to acknowledge upstream downlink");
+            }
+        });
+    }
+}
+
+/// Maps a failed transmit to the closest GWMP tx_ack error. Downlinks refused
+/// by the transmit budget or lost to a packet forwarder error are reported as
+/// a collision since they could not be scheduled.
+fn tx_ack_error(err: &TxError) -> tx_ack::Error {
+    match err {
+        TxError::TooEarly => tx_ack::Error::TooEarly,
+        TxError::TooLate => tx_ack::Error::TooLate,
+        _ => tx_ack::Error::CollisionPacket,
+    }
+}
+
+impl UpstreamSettings {
+    /// The gateway id to present to the upstream network server
+    pub fn gateway_id(&self) -> Result<MacAddress> {
+        let digits: String = self
+            .gateway_id
+            .chars()
+            .filter(|c| !matches!(c, ':' | '-'))
+            .collect();
+        u64::from_str_radix(&digits, 16)
+            .map(|id| MacAddress::from(id.to_be_bytes()))
+            .map_err(|_| Error::custom(format!("invalid gateway_id {}", self.gateway_id)))
+    }
+
+    /// Whether the given uplink is for the upstream network server
+    pub fn matches(&self, packet: &PacketUp) -> bool {
+        match packet.frame().map(|frame| &frame.payload) {
+            Some(PHYPayloadFrame::MACPayload(mac_payload)) => {
+                self.matches_dev_addr(mac_payload.dev_addr())
+            }
+            Some(PHYPayloadFrame::JoinRequest(join_request)) => self
+                .join_euis
+                .iter()
+                .any(|pattern| pattern.matches(join_request.app_eui)),
+            _ => false,
+        }
+    }
+
+    pub fn matches_dev_addr(&self, dev_addr: DevAddr) -> bool {
+        self.net_ids.iter().any(|net_id| net_id.contains(dev_addr))
+            || self.dev_addrs.iter().any(|range| range.contains(dev_addr))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use helium_proto::services::router::PacketRouterPacketUpV1;
+    use lorawan::{PHYPayload, AES128};
+
+    fn settings() -> UpstreamSettings {
+        config::Config::builder()
+            .add_source(config::File::from_str(
+                r#"
+                host = "127.0.0.1:1700"
+                gateway_id = "AA555A0000000001"
+                dev_addrs = ["26000000-27FFFFFF"]
+                join_euis = ["70B3D57ED0*"]
+                "#,
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .expect("upstream settings")
+    }
+
+    fn packet(frame: PHYPayload) -> PacketUp {
+        PacketUp::from(PacketRouterPacketUpV1 {
+            payload: Vec::<u8>::try_from(frame).expect("payload"),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_matches() {
+        let upstream = settings();
+
+        let key = || AES128([1; 16]);
+        let data = |dev_addr| {
+            packet(
+                PHYPayload::data_uplink(dev_addr)
+                    .session_keys(key(), key())
+                    .build()
+                    .expect("data uplink"),
+            )
+        };
+        assert!(upstream.matches(&data(0x2600_1234)));
+        assert!(!upstream.matches(&data(0x7800_0012)));
+
+        let join = |app_eui| {
+            packet(
+                PHYPayload::join_request(app_eui, 1)
+                    .app_key(key())
+                    .build()
+                    .expect("join request"),
+            )
+        };
+        assert!(upstream.matches(&join(0x70B3_D57E_D000_0042)));
+        assert!(!upstream.matches(&join(0x0004_A300_0000_0001)));
+    }
+}